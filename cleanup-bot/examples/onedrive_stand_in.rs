@@ -0,0 +1,39 @@
+//! A local stand-in for the Microsoft identity platform and the OneDrive parts of Microsoft Graph
+//! that cleanup-bot uses, for exercising uploads, restores, token refresh and retries without a
+//! live account.
+//!
+//! Run it with `cargo run --example onedrive_stand_in -- 127.0.0.1:8787` and point the bot at it:
+//!
+//! ```toml
+//! [onedrive]
+//! client_id = "stand-in"
+//! graph_api = "http://127.0.0.1:8787/v1.0"
+//! auth_url = "http://127.0.0.1:8787/oauth2"
+//! ```
+//!
+//! Errors are injected with environment variables:
+//! - `STAND_IN_FAIL_EVERY=n`: answer every n-th request with `503 Service Unavailable`
+//! - `STAND_IN_PARTIAL_CHUNKS=1`: only accept the first half of each upload session chunk
+//! - `STAND_IN_REJECT_REFRESH=1`: reject refresh tokens with `invalid_grant`
+//! - `STAND_IN_CORRUPT_HASH=1`: report a wrong QuickXorHash for uploaded files
+//! - `STAND_IN_TOKEN_LIFETIME=seconds`: lifetime of issued access tokens (default 3600)
+
+use std::env;
+
+use anyhow::Result;
+use cleanup_bot::stand_in::OneDriveStandIn;
+use cleanup_bot::stand_in::onedrive::Options;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    shared::init_tracing!()?;
+
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8787".to_string());
+    let stand_in = OneDriveStandIn::start(&address, Options::from_env()).await?;
+
+    println!("OneDrive stand-in listening on {}", stand_in.url());
+
+    std::future::pending().await
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use serenity::all::{CreateAllowedMentions, CreateMessage, Http, Mentionable};
+use tracing::{error, warn};
+
+use crate::config::ConfigStore;
+
+/// Posts problems that need an admin to the configured alert channel, mentioning the guild's
+/// cleanup admin roles.
+#[derive(Clone)]
+pub struct Alerter {
+    http: Arc<Http>,
+    config: ConfigStore,
+}
+
+impl Alerter {
+    pub fn new(http: Arc<Http>, config: ConfigStore) -> Self {
+        Self { http, config }
+    }
+
+    /// Send an alert. Without an alert channel, alerts are only logged.
+    pub async fn send(&self, message: &str) {
+        warn!("Alert: {message}");
+
+        let Some(channel_id) = self.config.alert_channel() else {
+            return;
+        };
+
+        let admin_roles = match channel_id.to_channel(&self.http).await {
+            Ok(channel) => channel
+                .guild()
+                .map(|c| self.config.admin_roles(c.guild_id))
+                .unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to fetch alert channel {channel_id}: {e:?}");
+                return;
+            }
+        };
+
+        let mentions: Vec<String> = admin_roles
+            .iter()
+            .map(|r| r.mention().to_string())
+            .collect();
+        let content = if mentions.is_empty() {
+            message.to_string()
+        } else {
+            format!("{} {message}", mentions.join(" "))
+        };
+
+        let alert = CreateMessage::new()
+            .content(content)
+            .allowed_mentions(CreateAllowedMentions::new().roles(admin_roles));
+
+        if let Err(e) = channel_id.send_message(&self.http, alert).await {
+            error!("Failed to send alert to channel {channel_id}: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, UserId};
+use tracing::info;
+
+use crate::config::{ArchiveFormat, RetentionPolicy};
+
+const AUDIT_LOG_PATH: &str = "./audit_log.jsonl";
+
+/// A change made to a channel's cleanup policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AuditAction {
+    Enable {
+        policy: RetentionPolicy,
+        #[serde(default)]
+        dry_run: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        archive: Option<ArchiveFormat>,
+    },
+    Disable,
+}
+
+/// Who changed which channel's cleanup policy, and when.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    pub channel_name: String,
+    pub user_id: UserId,
+    pub user_name: String,
+    pub action: AuditAction,
+}
+
+impl AuditRecord {
+    /// Append the record to the audit log (one JSON object per line).
+    pub fn append(&self) -> Result<()> {
+        info!(
+            "Audit: {} ({}) {:?} in #{} ({})",
+            self.user_name, self.user_id, self.action, self.channel_name, self.channel_id
+        );
+
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(AUDIT_LOG_PATH)
+            .context(format!("Failed to open {AUDIT_LOG_PATH}"))?;
+        file.write_all(line.as_bytes())
+            .context("Failed to write audit record")?;
+
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
+mod bandwidth;
 mod queue;
 mod worker;
 
-pub use queue::{BackupQueue, BackupStatus, PendingBackup};
+pub use bandwidth::BandwidthLimiter;
+pub use queue::{BackupQueue, BackupStatus, PendingBackup, UploadSession};
 pub use worker::spawn_worker;
@@ -0,0 +1,78 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::Local;
+use futures::{Stream, StreamExt};
+use tokio::time::{Instant, sleep_until};
+
+use crate::config::BackupWorkerConfig;
+
+// Small pieces keep throttled uploads smooth instead of sending a chunk and then pausing
+const PIECE_SIZE: usize = 64 * 1024;
+
+/// Upload bandwidth shared by all concurrent uploads, following the configured schedule.
+pub struct BandwidthLimiter {
+    config: BackupWorkerConfig,
+    /// When the bytes granted so far have been sent at the current rate
+    next_free: Mutex<Instant>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(config: BackupWorkerConfig) -> Self {
+        Self {
+            config,
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until `bytes` more may be sent.
+    pub async fn acquire(&self, bytes: usize) {
+        let Some(limit) = self.config.bandwidth_limit_at(Local::now().time()) else {
+            return;
+        };
+
+        let send_at = {
+            let mut next_free = self.next_free.lock().unwrap();
+            // Idle time isn't saved up, so a new upload can't burst past the limit
+            let send_at = (*next_free).max(Instant::now());
+            *next_free = send_at + Duration::from_secs_f64(bytes as f64 / limit.get() as f64);
+            send_at
+        };
+
+        sleep_until(send_at).await;
+    }
+
+    /// Split `data` into pieces sent no faster than the limit allows.
+    pub fn throttle_bytes(
+        self: &Arc<Self>,
+        data: Bytes,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let pieces: Vec<_> = (0..data.len())
+            .step_by(PIECE_SIZE)
+            .map(|start| Ok(data.slice(start..(start + PIECE_SIZE).min(data.len()))))
+            .collect();
+        self.throttle(futures::stream::iter(pieces))
+    }
+
+    /// Pass a stream of bytes through no faster than the limit allows.
+    pub fn throttle<S>(
+        self: &Arc<Self>,
+        stream: S,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        let limiter = Arc::clone(self);
+        stream.then(move |piece| {
+            let limiter = Arc::clone(&limiter);
+            async move {
+                if let Ok(piece) = &piece {
+                    limiter.acquire(piece.len()).await;
+                }
+                piece
+            }
+        })
+    }
+}
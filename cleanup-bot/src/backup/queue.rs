@@ -1,13 +1,21 @@
 use std::collections::HashMap;
-use std::fs;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
-const PENDING_BACKUPS_PATH: &str = "./pending_backups.toml";
-const PENDING_BACKUPS_TEMP_PATH: &str = "./pending_backups.toml.tmp";
+use crate::media::IndexedMedia;
+
+const BACKUP_JOURNAL_FILE: &str = "backup_queue.jsonl";
+const BACKUP_JOURNAL_TEMP_FILE: &str = "backup_queue.jsonl.tmp";
+/// Queue file used before the journal, migrated on first load
+const LEGACY_QUEUE_FILE: &str = "pending_backups.toml";
+/// The journal is compacted once it holds this many records and four times as many as live entries.
+const MIN_COMPACTION_RECORDS: usize = 1000;
 
 /// Status of a pending backup.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -15,7 +23,25 @@ const PENDING_BACKUPS_TEMP_PATH: &str = "./pending_backups.toml.tmp";
 pub enum BackupStatus {
     Pending,
     InProgress,
-    Failed { error: String },
+    /// Waiting for `next_attempt_at` before retrying
+    Failed {
+        error: String,
+    },
+    /// Gave up after too many attempts, only retried when an admin asks
+    DeadLetter {
+        error: String,
+    },
+}
+
+/// A resumable upload session, kept so an interrupted upload continues where it stopped.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UploadSession {
+    /// Destination the session belongs to
+    pub destination: String,
+    pub url: String,
+    /// Next byte the server expects
+    pub next_offset: u64,
+    pub expires_at: DateTime<Utc>,
 }
 
 /// A backup that is pending cloud upload.
@@ -28,98 +54,223 @@ pub struct PendingBackup {
     pub timestamp: DateTime<Utc>,
     pub retry_count: u32,
     pub status: BackupStatus,
+    /// Earliest time a failed backup is retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// Destinations the file has already been uploaded to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uploaded_to: Vec<String>,
+    /// Upload session of a partially uploaded file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_session: Option<UploadSession>,
+    /// Media content to add to the index once the file has reached every destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<IndexedMedia>,
 }
 
-/// Persistent queue for tracking pending backups.
+/// A change to the backup queue, one JSON object per journal line.
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+enum JournalRecord {
+    /// Add a backup or replace its previous state
+    Put {
+        backup: Box<PendingBackup>,
+    },
+    Remove {
+        local_path: PathBuf,
+    },
+}
+
+/// Format of the queue file before the journal.
+#[derive(Deserialize)]
+struct LegacyQueue {
+    entries: HashMap<String, PendingBackup>,
+}
+
+/// Persistent queue for tracking pending backups.
+///
+/// Every change appends one record to a journal and syncs it before returning, so a state change
+/// costs a small write instead of rewriting the whole queue. On load, the journal is replayed and
+/// compacted to one record per live backup.
+#[derive(Debug)]
 pub struct BackupQueue {
     entries: HashMap<String, PendingBackup>,
+    /// Directory holding the journal, the working directory outside of tests
+    dir: PathBuf,
+    journal: File,
+    /// Records in the journal, including superseded ones
+    records_on_disk: usize,
 }
 
 impl BackupQueue {
     /// Load the backup queue from disk, or create a new empty queue.
     pub fn load() -> Result<Self> {
-        if let Ok(content) = fs::read_to_string(PENDING_BACKUPS_PATH) {
-            let mut queue: BackupQueue = toml::from_str(&content)
-                .context(format!("Failed to parse {}", PENDING_BACKUPS_PATH))?;
-
-            queue.entries.iter_mut().for_each(|(_, entry)| {
-                if entry.status == BackupStatus::InProgress {
-                    // If we're loading the list and it has InProgress items, that means the process
-                    // shut down during upload, reset status to pending
-                    entry.status = BackupStatus::Pending;
-                }
-            });
+        Self::load_in(Path::new("."))
+    }
 
-            Ok(queue)
-        } else {
-            Ok(Self {
-                entries: HashMap::new(),
-            })
+    fn load_in(dir: &Path) -> Result<Self> {
+        let journal_path = dir.join(BACKUP_JOURNAL_FILE);
+        let legacy_path = dir.join(LEGACY_QUEUE_FILE);
+
+        let mut entries = match fs::read_to_string(&journal_path) {
+            Ok(content) => replay_journal(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => load_legacy_queue(&legacy_path)?,
+            Err(e) => {
+                return Err(e).context(format!("Failed to read {}", journal_path.display()));
+            }
+        };
+
+        entries.values_mut().for_each(|entry| {
+            if entry.status == BackupStatus::InProgress {
+                // If we're loading the list and it has InProgress items, that means the process
+                // shut down during upload, reset status to pending
+                entry.status = BackupStatus::Pending;
+            }
+        });
+
+        // Compacting also drops a record truncated by a crash, so new records start on a fresh line
+        let journal = write_snapshot(dir, &entries)?;
+
+        if legacy_path.exists() {
+            fs::remove_file(&legacy_path)
+                .context(format!("Failed to remove {}", legacy_path.display()))?;
+            info!(
+                "Migrated {} backups from {LEGACY_QUEUE_FILE} to {BACKUP_JOURNAL_FILE}",
+                entries.len()
+            );
         }
+
+        Ok(Self {
+            records_on_disk: entries.len(),
+            entries,
+            dir: dir.to_path_buf(),
+            journal,
+        })
     }
 
     /// Add a backup to the queue.
     pub fn add(&mut self, backup: PendingBackup) -> Result<()> {
         let key = backup.local_path.to_string_lossy().to_string();
+        self.append(&[JournalRecord::Put {
+            backup: Box::new(backup.clone()),
+        }])?;
         self.entries.insert(key, backup);
-        self.save()
+        self.maybe_compact()
     }
 
     /// Remove a backup from the queue by its local path.
     pub fn remove(&mut self, local_path: &Path) -> Result<()> {
         let key = local_path.to_string_lossy().to_string();
-        self.entries.remove(&key);
-        self.save()
+        if self.entries.contains_key(&key) {
+            self.append(&[JournalRecord::Remove {
+                local_path: local_path.to_path_buf(),
+            }])?;
+            self.entries.remove(&key);
+        }
+        self.maybe_compact()
     }
 
-    /// Get all pending backups (status == Pending).
-    pub fn get_pending(&self) -> Vec<&PendingBackup> {
+    /// Get all backups to upload now: pending ones, and failed ones whose retry delay has passed.
+    pub fn get_due(&self, now: DateTime<Utc>) -> Vec<&PendingBackup> {
         self.entries
             .values()
-            .filter(|b| matches!(b.status, BackupStatus::Pending))
+            .filter(|b| match b.status {
+                BackupStatus::Pending => true,
+                BackupStatus::Failed { .. } => b.next_attempt_at.is_none_or(|t| t <= now),
+                BackupStatus::InProgress | BackupStatus::DeadLetter { .. } => false,
+            })
             .collect()
     }
 
-    /// Get all failed backups that haven't exceeded max retries.
-    pub fn get_failed(&self, max_retries: u32) -> Vec<&PendingBackup> {
-        self.entries
-            .values()
-            .filter(|b| {
-                matches!(b.status, BackupStatus::Failed { .. }) && b.retry_count < max_retries
-            })
-            .collect()
+    /// Iterate over all backups in the queue.
+    pub fn entries(&self) -> impl Iterator<Item = &PendingBackup> {
+        self.entries.values()
+    }
+
+    /// Move failed backups that already used up their retries to the dead letter state,
+    /// e.g. entries left over from before dead lettering existed.
+    pub fn dead_letter_exhausted(&mut self, max_retries: u32) -> Result<usize> {
+        self.update_where(
+            |b| matches!(b.status, BackupStatus::Failed { .. }) && b.retry_count >= max_retries,
+            |b| {
+                if let BackupStatus::Failed { error } = &b.status {
+                    b.status = BackupStatus::DeadLetter {
+                        error: error.clone(),
+                    };
+                }
+            },
+        )
     }
 
     /// Mark a backup as in progress.
     pub fn mark_in_progress(&mut self, local_path: &Path) -> Result<()> {
-        let key = local_path.to_string_lossy().to_string();
-        if let Some(backup) = self.entries.get_mut(&key) {
-            backup.status = BackupStatus::InProgress;
-            self.save()?;
-        }
-        Ok(())
+        self.update(local_path, |b| b.status = BackupStatus::InProgress)
     }
 
-    /// Mark a backup as failed with an error message.
-    pub fn mark_failed(&mut self, local_path: &Path, error: String) -> Result<()> {
-        let key = local_path.to_string_lossy().to_string();
-        if let Some(backup) = self.entries.get_mut(&key) {
-            backup.status = BackupStatus::Failed { error };
-            backup.retry_count += 1;
-            self.save()?;
+    /// Record that a backup reached a destination, so retries skip it.
+    pub fn mark_uploaded_to(&mut self, local_path: &Path, destination: &str) -> Result<()> {
+        self.update(local_path, |b| b.uploaded_to.push(destination.to_string()))
+    }
+
+    /// Save or clear the resumable upload session of a backup.
+    pub fn set_upload_session(
+        &mut self,
+        local_path: &Path,
+        session: Option<UploadSession>,
+    ) -> Result<()> {
+        if self
+            .get(local_path)
+            .is_some_and(|b| b.upload_session == session)
+        {
+            return Ok(());
         }
-        Ok(())
+        self.update(local_path, |b| b.upload_session = session)
+    }
+
+    /// Mark a backup as failed with an error message, to be retried at `next_attempt_at`.
+    pub fn mark_failed(
+        &mut self,
+        local_path: &Path,
+        error: String,
+        next_attempt_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.update(local_path, |b| {
+            b.status = BackupStatus::Failed { error };
+            b.retry_count += 1;
+            b.next_attempt_at = Some(next_attempt_at);
+        })
     }
 
-    /// Reset a failed backup to pending for retry.
+    /// Stop retrying a backup until an admin asks for it.
+    pub fn mark_dead_letter(&mut self, local_path: &Path, error: String) -> Result<()> {
+        self.update(local_path, |b| {
+            b.status = BackupStatus::DeadLetter { error };
+            b.retry_count += 1;
+            b.next_attempt_at = None;
+        })
+    }
+
+    /// Retry failed and dead-lettered backups matching `filter` right away, with a fresh
+    /// retry budget. Returns how many were reset.
+    pub fn retry_where(&mut self, filter: impl Fn(&PendingBackup) -> bool) -> Result<usize> {
+        self.update_where(
+            |b| {
+                matches!(
+                    b.status,
+                    BackupStatus::Failed { .. } | BackupStatus::DeadLetter { .. }
+                ) && filter(b)
+            },
+            |b| {
+                b.status = BackupStatus::Pending;
+                b.retry_count = 0;
+                b.next_attempt_at = None;
+            },
+        )
+    }
+
+    /// Reset a backup to pending without counting an attempt, e.g. while a destination is paused.
     pub fn reset_to_pending(&mut self, local_path: &Path) -> Result<()> {
-        let key = local_path.to_string_lossy().to_string();
-        if let Some(backup) = self.entries.get_mut(&key) {
-            backup.status = BackupStatus::Pending;
-            self.save()?;
-        }
-        Ok(())
+        self.update(local_path, |b| b.status = BackupStatus::Pending)
     }
 
     /// Get a backup by its local path.
@@ -128,13 +279,348 @@ impl BackupQueue {
         self.entries.get(&key)
     }
 
-    /// Save the queue to disk atomically (write to temp file, then rename).
-    fn save(&self) -> Result<()> {
-        let content = toml::to_string_pretty(&self)?;
-        let temp_path = PathBuf::from(PENDING_BACKUPS_TEMP_PATH);
-        fs::write(&temp_path, &content).context("Failed to write temp backup queue file")?;
-        fs::rename(&temp_path, PENDING_BACKUPS_PATH)
-            .context("Failed to rename backup queue file")?;
+    /// Apply a change to a backup and journal its new state. The queue only changes once the
+    /// record is on disk, so a failed write doesn't leave a state that a restart reverts.
+    fn update(&mut self, local_path: &Path, f: impl FnOnce(&mut PendingBackup)) -> Result<()> {
+        let key = local_path.to_string_lossy().to_string();
+        let Some(backup) = self.entries.get(&key) else {
+            return Ok(());
+        };
+
+        let mut updated = backup.clone();
+        f(&mut updated);
+        self.append(&[JournalRecord::Put {
+            backup: Box::new(updated.clone()),
+        }])?;
+        self.entries.insert(key, updated);
+        self.maybe_compact()
+    }
+
+    /// Apply a change to every backup matching `filter`, journaled with a single sync. Returns
+    /// how many changed.
+    fn update_where(
+        &mut self,
+        filter: impl Fn(&PendingBackup) -> bool,
+        f: impl Fn(&mut PendingBackup),
+    ) -> Result<usize> {
+        let updated: Vec<PendingBackup> = self
+            .entries
+            .values()
+            .filter(|b| filter(b))
+            .map(|b| {
+                let mut b = b.clone();
+                f(&mut b);
+                b
+            })
+            .collect();
+        if updated.is_empty() {
+            return Ok(0);
+        }
+
+        let records: Vec<_> = updated
+            .iter()
+            .map(|b| JournalRecord::Put {
+                backup: Box::new(b.clone()),
+            })
+            .collect();
+        self.append(&records)?;
+
+        let count = updated.len();
+        for backup in updated {
+            let key = backup.local_path.to_string_lossy().to_string();
+            self.entries.insert(key, backup);
+        }
+        self.maybe_compact()?;
+        Ok(count)
+    }
+
+    /// Append records and wait until they are on disk.
+    fn append(&mut self, records: &[JournalRecord]) -> Result<()> {
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+
+        self.journal
+            .write_all(lines.as_bytes())
+            .context("Failed to write backup journal record")?;
+        self.journal
+            .sync_data()
+            .context("Failed to sync backup journal")?;
+        self.records_on_disk += records.len();
+
         Ok(())
     }
+
+    /// Rewrite the journal with one record per live backup once superseded records dominate.
+    fn maybe_compact(&mut self) -> Result<()> {
+        if self.records_on_disk < MIN_COMPACTION_RECORDS
+            || self.records_on_disk < self.entries.len() * 4
+        {
+            return Ok(());
+        }
+
+        self.journal = write_snapshot(&self.dir, &self.entries)?;
+        self.records_on_disk = self.entries.len();
+        Ok(())
+    }
+}
+
+/// Rebuild the queue from journal records, later records win.
+fn replay_journal(content: &str) -> HashMap<String, PendingBackup> {
+    let mut entries = HashMap::new();
+
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(JournalRecord::Put { backup }) => {
+                let key = backup.local_path.to_string_lossy().to_string();
+                entries.insert(key, *backup);
+            }
+            Ok(JournalRecord::Remove { local_path }) => {
+                entries.remove(&*local_path.to_string_lossy());
+            }
+            // A crash mid-write can leave a truncated last line
+            Err(e) => warn!("Skipping unreadable backup journal record: {e}"),
+        }
+    }
+
+    entries
+}
+
+/// Read the queue file used before the journal, if there is one.
+fn load_legacy_queue(path: &Path) -> Result<HashMap<String, PendingBackup>> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            let queue: LegacyQueue =
+                toml::from_str(&content).context(format!("Failed to parse {}", path.display()))?;
+            Ok(queue.entries)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e).context(format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Atomically replace the journal with one record per backup (write and sync a temp file, then
+/// rename). Returns the new journal opened for appending.
+fn write_snapshot(dir: &Path, entries: &HashMap<String, PendingBackup>) -> Result<File> {
+    let journal_path = dir.join(BACKUP_JOURNAL_FILE);
+    let temp_path = dir.join(BACKUP_JOURNAL_TEMP_FILE);
+
+    let mut content = String::new();
+    for backup in entries.values() {
+        content.push_str(&serde_json::to_string(&JournalRecord::Put {
+            backup: Box::new(backup.clone()),
+        })?);
+        content.push('\n');
+    }
+
+    let mut temp = File::create(&temp_path).context("Failed to create temp backup journal file")?;
+    temp.write_all(content.as_bytes())
+        .context("Failed to write temp backup journal file")?;
+    temp.sync_all()
+        .context("Failed to sync temp backup journal file")?;
+    fs::rename(&temp_path, &journal_path).context("Failed to rename backup journal file")?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .context("Failed to sync backup journal directory")?;
+
+    OpenOptions::new()
+        .append(true)
+        .open(&journal_path)
+        .context(format!("Failed to open {}", journal_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test's queue.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("backup-queue-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn backup(name: &str) -> PendingBackup {
+        PendingBackup {
+            message_id: 1,
+            channel_id: 2,
+            local_path: PathBuf::from(format!("media/2024-05-01/1_{name}")),
+            original_filename: name.to_string(),
+            timestamp: Utc::now(),
+            retry_count: 0,
+            status: BackupStatus::Pending,
+            next_attempt_at: None,
+            uploaded_to: Vec::new(),
+            upload_session: None,
+            content: None,
+        }
+    }
+
+    fn journal_lines(dir: &Path) -> usize {
+        fs::read_to_string(dir.join(BACKUP_JOURNAL_FILE))
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    #[test]
+    fn replays_puts_and_removes() {
+        let dir = temp_dir("replay");
+        let (a, b) = (backup("a.jpg"), backup("b.jpg"));
+
+        let mut queue = BackupQueue::load_in(&dir).unwrap();
+        queue.add(a.clone()).unwrap();
+        queue.add(b.clone()).unwrap();
+        queue
+            .mark_failed(&a.local_path, "timeout".to_string(), Utc::now())
+            .unwrap();
+        queue.mark_uploaded_to(&a.local_path, "onedrive").unwrap();
+        queue.mark_in_progress(&b.local_path).unwrap();
+        queue.remove(&b.local_path).unwrap();
+        drop(queue);
+
+        let queue = BackupQueue::load_in(&dir).unwrap();
+        assert_eq!(queue.entries().count(), 1);
+        let a = queue.get(&a.local_path).unwrap();
+        assert_eq!(
+            a.status,
+            BackupStatus::Failed {
+                error: "timeout".to_string()
+            }
+        );
+        assert_eq!(a.retry_count, 1);
+        assert_eq!(a.uploaded_to, ["onedrive"]);
+        assert!(queue.get(&b.local_path).is_none());
+    }
+
+    #[test]
+    fn in_progress_backups_are_pending_after_restart() {
+        let dir = temp_dir("in-progress");
+        let a = backup("a.jpg");
+
+        let mut queue = BackupQueue::load_in(&dir).unwrap();
+        queue.add(a.clone()).unwrap();
+        queue.mark_in_progress(&a.local_path).unwrap();
+        drop(queue);
+
+        let queue = BackupQueue::load_in(&dir).unwrap();
+        assert_eq!(
+            queue.get(&a.local_path).unwrap().status,
+            BackupStatus::Pending
+        );
+    }
+
+    #[test]
+    fn compacts_once_superseded_records_dominate() {
+        let dir = temp_dir("compaction");
+        let a = backup("a.jpg");
+
+        let mut queue = BackupQueue::load_in(&dir).unwrap();
+        queue.add(a.clone()).unwrap();
+        for _ in 0..MIN_COMPACTION_RECORDS - 2 {
+            queue.mark_in_progress(&a.local_path).unwrap();
+        }
+        assert_eq!(journal_lines(&dir), MIN_COMPACTION_RECORDS - 1);
+
+        queue.mark_uploaded_to(&a.local_path, "s3").unwrap();
+        assert_eq!(journal_lines(&dir), 1);
+
+        // Appends after compaction go to the new journal
+        queue.remove(&a.local_path).unwrap();
+        drop(queue);
+        let queue = BackupQueue::load_in(&dir).unwrap();
+        assert_eq!(queue.entries().count(), 0);
+    }
+
+    #[test]
+    fn torn_trailing_record_is_ignored() {
+        let dir = temp_dir("torn");
+        let (a, b) = (backup("a.jpg"), backup("b.jpg"));
+
+        let mut queue = BackupQueue::load_in(&dir).unwrap();
+        queue.add(a.clone()).unwrap();
+        drop(queue);
+
+        let record = serde_json::to_string(&JournalRecord::Put {
+            backup: Box::new(b.clone()),
+        })
+        .unwrap();
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(dir.join(BACKUP_JOURNAL_FILE))
+            .unwrap();
+        journal
+            .write_all(&record.as_bytes()[..record.len() / 2])
+            .unwrap();
+        drop(journal);
+
+        let mut queue = BackupQueue::load_in(&dir).unwrap();
+        assert!(queue.get(&a.local_path).is_some());
+        assert!(queue.get(&b.local_path).is_none());
+
+        // Records written after the torn one start on a fresh line
+        queue.add(b.clone()).unwrap();
+        drop(queue);
+        let queue = BackupQueue::load_in(&dir).unwrap();
+        assert_eq!(queue.entries().count(), 2);
+    }
+
+    #[test]
+    fn migrates_legacy_toml_queue() {
+        let dir = temp_dir("legacy");
+        fs::write(
+            dir.join(LEGACY_QUEUE_FILE),
+            r#"
+            [entries."media/2024-05-01/1_a.jpg"]
+            message_id = 1
+            channel_id = 2
+            local_path = "media/2024-05-01/1_a.jpg"
+            original_filename = "a.jpg"
+            timestamp = "2024-05-01T12:00:00Z"
+            retry_count = 2
+
+            [entries."media/2024-05-01/1_a.jpg".status]
+            type = "Failed"
+            error = "timeout"
+
+            [entries."media/2024-05-01/1_b.jpg"]
+            message_id = 1
+            channel_id = 2
+            local_path = "media/2024-05-01/1_b.jpg"
+            original_filename = "b.jpg"
+            timestamp = "2024-05-01T12:00:00Z"
+            retry_count = 0
+
+            [entries."media/2024-05-01/1_b.jpg".status]
+            type = "InProgress"
+            "#,
+        )
+        .unwrap();
+
+        let queue = BackupQueue::load_in(&dir).unwrap();
+        assert!(!dir.join(LEGACY_QUEUE_FILE).exists());
+        assert_eq!(journal_lines(&dir), 2);
+
+        let a = queue.get(Path::new("media/2024-05-01/1_a.jpg")).unwrap();
+        assert_eq!(a.retry_count, 2);
+        assert_eq!(
+            a.status,
+            BackupStatus::Failed {
+                error: "timeout".to_string()
+            }
+        );
+        let b = queue.get(Path::new("media/2024-05-01/1_b.jpg")).unwrap();
+        assert_eq!(b.status, BackupStatus::Pending);
+        drop(queue);
+
+        // The journal takes over
+        let queue = BackupQueue::load_in(&dir).unwrap();
+        assert_eq!(queue.entries().count(), 2);
+    }
 }
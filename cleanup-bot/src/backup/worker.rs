@@ -1,172 +1,342 @@
-use std::ops::Deref;
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use chrono::Utc;
+use futures::{StreamExt, stream};
+use rand::Rng;
 use tokio::task::JoinHandle;
 use tokio::time::interval;
 use tracing::{debug, error, info, warn};
 
+use super::bandwidth::BandwidthLimiter;
 use super::queue::BackupQueue;
+use crate::alert::Alerter;
 use crate::config::BackupWorkerConfig;
-use crate::onedrive::OneDriveClient;
+use crate::destination::{BackupDestination, UploadProgress};
+use crate::media::MediaIndex;
 
-/// Spawn the background backup worker.
+/// Spawn the background backup worker, uploading every file to all destinations.
+/// Uploaded media is added to `media_index`.
 pub fn spawn_worker(
     queue: Arc<Mutex<BackupQueue>>,
     config: BackupWorkerConfig,
-    onedrive_client: Arc<OneDriveClient>,
+    destinations: Vec<Arc<dyn BackupDestination>>,
+    alerter: Alerter,
+    media_index: Arc<Mutex<MediaIndex>>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
-        run_worker(queue, config, onedrive_client).await;
+        run_worker(queue, config, destinations, alerter, media_index).await;
     })
 }
 
 async fn run_worker(
     queue: Arc<Mutex<BackupQueue>>,
     config: BackupWorkerConfig,
-    onedrive_client: Arc<OneDriveClient>,
+    destinations: Vec<Arc<dyn BackupDestination>>,
+    alerter: Alerter,
+    media_index: Arc<Mutex<MediaIndex>>,
 ) {
     let check_interval = Duration::from_secs(config.check_interval_seconds);
     let mut interval = interval(check_interval);
+    let limiter = Arc::new(BandwidthLimiter::new(config.clone()));
 
     info!(
-        "Backup worker started (check interval: {}s, max retries: {})",
-        config.check_interval_seconds, config.max_retries
+        "Backup worker started (destinations: {}, check interval: {}s, max retries: {}, \
+         concurrency: {})",
+        destinations
+            .iter()
+            .map(|d| d.name())
+            .collect::<Vec<_>>()
+            .join(", "),
+        config.check_interval_seconds,
+        config.max_retries,
+        config.concurrency
     );
 
+    match queue
+        .lock()
+        .unwrap()
+        .dead_letter_exhausted(config.max_retries)
+    {
+        Ok(0) => {}
+        Ok(count) => warn!("Moved {count} backups that ran out of retries to dead letter"),
+        Err(e) => error!("Failed to dead-letter exhausted backups: {e:?}"),
+    }
+
+    let mut paused: HashSet<String> = HashSet::new();
+
     loop {
         interval.tick().await;
 
-        let pending: Vec<_> = {
+        // Uploads to a destination waiting for an admin can't complete, so it's left out until
+        // it's back instead of burning retries, while the others carry on
+        let (available, unavailable) = check_destinations(&destinations).await;
+        for (name, reason) in &unavailable {
+            if paused.insert(name.to_string()) {
+                alerter
+                    .send(&format!("Backups to {name} are paused: {reason}"))
+                    .await;
+            }
+        }
+        paused.retain(|name| {
+            let still_paused = unavailable.iter().any(|(n, _)| n == name);
+            if !still_paused {
+                info!("Backup destination {name} available again, resuming uploads to it");
+            }
+            still_paused
+        });
+
+        if available.is_empty() {
+            debug!("All backup destinations paused, skipping check");
+            continue;
+        }
+
+        // Files that only wait for paused destinations stay untouched. Oldest first, so a
+        // burst of new files doesn't starve the backlog.
+        let mut pending: Vec<_> = {
             let queue = queue.lock().unwrap();
             queue
-                .get_pending()
+                .get_due(Utc::now())
                 .into_iter()
-                .map(|b| b.local_path.clone())
+                .filter(|b| {
+                    let mut remaining = destinations
+                        .iter()
+                        .map(|d| d.name())
+                        .filter(|name| !b.uploaded_to.iter().any(|d| d == name))
+                        .peekable();
+                    remaining.peek().is_none() || remaining.any(|name| available.contains(&name))
+                })
+                .map(|b| (b.timestamp, b.local_path.clone()))
                 .collect()
         };
+        pending.sort();
 
         if pending.is_empty() {
             debug!("No pending backups to process");
             continue;
         }
 
-        info!("Processing {} pending backups", pending.len());
+        info!(
+            "Processing {} pending backups ({} at a time)",
+            pending.len(),
+            config.concurrency
+        );
 
-        for local_path in pending {
-            // Check if file still exists
-            if !local_path.exists() {
-                warn!("Backup file missing: {}", local_path.display());
-                let mut queue = queue.lock().unwrap();
-                if let Err(e) = queue.mark_failed(&local_path, "file missing".to_string()) {
-                    error!("Failed to mark backup as failed: {e:?}");
-                }
-                continue;
-            }
+        stream::iter(pending)
+            .for_each_concurrent(config.concurrency.get(), |(_, local_path)| {
+                process_backup(
+                    &queue,
+                    &config,
+                    &destinations,
+                    &alerter,
+                    &limiter,
+                    &media_index,
+                    local_path,
+                )
+            })
+            .await;
+    }
+}
 
-            // Get backup info and check retry count
-            let (retry_count, should_skip) = {
-                let queue = queue.lock().unwrap();
-                if let Some(backup) = queue.get(&local_path) {
-                    (backup.retry_count, backup.retry_count >= config.max_retries)
-                } else {
-                    continue;
-                }
-            };
+/// Upload one file to every destination, then delete it locally, or schedule a retry.
+async fn process_backup(
+    queue: &Mutex<BackupQueue>,
+    config: &BackupWorkerConfig,
+    destinations: &[Arc<dyn BackupDestination>],
+    alerter: &Alerter,
+    limiter: &Arc<BandwidthLimiter>,
+    media_index: &Mutex<MediaIndex>,
+    local_path: PathBuf,
+) {
+    // Check if file still exists
+    if !local_path.exists() {
+        // Retrying won't bring the file back
+        warn!("Backup file missing: {}", local_path.display());
+        let mut queue = queue.lock().unwrap();
+        if let Err(e) = queue.mark_dead_letter(&local_path, "file missing".to_string()) {
+            error!("Failed to dead-letter backup: {e:?}");
+        }
+        return;
+    }
 
-            if should_skip {
-                debug!(
-                    "Skipping {} - max retries ({}) exceeded",
-                    local_path.display(),
-                    config.max_retries
-                );
-                continue;
+    let Some(retry_count) = queue
+        .lock()
+        .unwrap()
+        .get(&local_path)
+        .map(|b| b.retry_count)
+    else {
+        return;
+    };
+
+    // Mark as in progress
+    {
+        let mut queue = queue.lock().unwrap();
+        if let Err(e) = queue.mark_in_progress(&local_path) {
+            error!("Failed to mark backup as in progress: {e:?}");
+            return;
+        }
+    }
+
+    // Attempt upload
+    match upload_to_destinations(queue, &local_path, destinations, limiter).await {
+        Ok(waiting) if !waiting.is_empty() => {
+            // Not the file's fault, leave it for when the destinations are back
+            debug!(
+                "{} waits for {} to be available again",
+                local_path.display(),
+                waiting.join(", ")
+            );
+            let mut queue = queue.lock().unwrap();
+            if let Err(e) = queue.reset_to_pending(&local_path) {
+                error!("Failed to reset backup to pending: {e:?}");
             }
+        }
+        Ok(_) => {
+            info!("Successfully uploaded {}", local_path.display());
 
-            // Mark as in progress
-            {
+            // Remove from queue
+            let content = {
                 let mut queue = queue.lock().unwrap();
-                if let Err(e) = queue.mark_in_progress(&local_path) {
-                    error!("Failed to mark backup as in progress: {e:?}");
-                    continue;
+                let content = queue.get(&local_path).and_then(|b| b.content.clone());
+                if let Err(e) = queue.remove(&local_path) {
+                    error!("Failed to remove backup from queue: {e:?}");
                 }
-            }
+                content
+            };
 
-            // Attempt upload
-            match upload_to_cloud(&local_path, onedrive_client.deref()).await {
-                Ok(()) => {
-                    info!("Successfully uploaded {}", local_path.display());
-
-                    // Remove from queue
-                    {
-                        let mut queue = queue.lock().unwrap();
-                        if let Err(e) = queue.remove(&local_path) {
-                            error!("Failed to remove backup from queue: {e:?}");
-                        }
-                    }
-
-                    // Delete local file
-                    if let Err(e) = tokio::fs::remove_file(&local_path).await {
-                        error!(
-                            "Failed to delete local file {}: {e:?}",
-                            local_path.display()
-                        );
-                    } else {
-                        debug!("Deleted local file {}", local_path.display());
-
-                        // remove_dir only removes empty directories — safe to call unconditionally
-                        if let Some(parent) = local_path.parent() {
-                            let _ = tokio::fs::remove_dir(parent).await;
-                        }
-                    }
+            // Later copies of the content can refer to this backup now that it exists
+            if let Some(content) = content {
+                let path = content.path.clone();
+                if let Err(e) = media_index.lock().unwrap().insert(content) {
+                    // Only costs deduplication of later copies
+                    warn!("Failed to add {path} to the media index: {e:?}");
                 }
-                Err(e) => {
-                    warn!(
-                        "Failed to upload {} (attempt {}): {e}",
-                        local_path.display(),
-                        retry_count + 1
-                    );
-
-                    // Mark as failed (will be retried on next cycle after delay)
-                    let mut queue = queue.lock().unwrap();
-                    if let Err(e) = queue.mark_failed(&local_path, e.to_string()) {
-                        error!("Failed to mark backup as failed: {e:?}");
-                    }
+            }
+
+            // Delete local file
+            if let Err(e) = tokio::fs::remove_file(&local_path).await {
+                error!(
+                    "Failed to delete local file {}: {e:?}",
+                    local_path.display()
+                );
+            } else {
+                debug!("Deleted local file {}", local_path.display());
+
+                // remove_dir only removes empty directories — safe to call unconditionally
+                if let Some(parent) = local_path.parent() {
+                    let _ = tokio::fs::remove_dir(parent).await;
                 }
             }
         }
+        Err(e) => {
+            let attempt = retry_count + 1;
+            if attempt >= config.max_retries {
+                let dead_lettered = queue
+                    .lock()
+                    .unwrap()
+                    .mark_dead_letter(&local_path, e.clone());
+                if let Err(e) = dead_lettered {
+                    error!("Failed to dead-letter backup: {e:?}");
+                }
+                alerter
+                    .send(&format!(
+                        "Gave up backing up `{}` after {attempt} attempts: {e}\n\
+                         Use /backup retry to try again.",
+                        local_path.display()
+                    ))
+                    .await;
+                return;
+            }
+
+            let delay = retry_delay(config, attempt);
+            warn!(
+                "Failed to upload {} (attempt {attempt}), retrying in {}s: {e}",
+                local_path.display(),
+                delay.as_secs()
+            );
 
-        // Reset failed backups to pending for retry
-        reset_failed_for_retry(&queue, &config);
+            let next_attempt_at = Utc::now() + delay;
+            let mut queue = queue.lock().unwrap();
+            if let Err(e) = queue.mark_failed(&local_path, e, next_attempt_at) {
+                error!("Failed to mark backup as failed: {e:?}");
+            }
+        }
     }
 }
 
-/// Upload file to cloud storage.
-async fn upload_to_cloud(local_path: &Path, client: &OneDriveClient) -> Result<(), String> {
-    client
-        .upload_file(local_path)
-        .await
-        .map_err(|e| e.to_string())
+/// Exponential backoff with jitter: the delay doubles with every failed attempt up to the
+/// configured maximum, and a random half of it is dropped so failed uploads don't retry in lockstep.
+fn retry_delay(config: &BackupWorkerConfig, attempt: u32) -> Duration {
+    let delay = config
+        .retry_base_delay_seconds
+        .saturating_mul(1 << attempt.saturating_sub(1).min(32))
+        .min(config.retry_max_delay_seconds);
+    let jittered = rand::rng().random_range(delay / 2..=delay);
+    Duration::from_secs(jittered)
 }
 
-/// Reset failed backups to pending status for retry.
-fn reset_failed_for_retry(queue: &Arc<Mutex<BackupQueue>>, config: &BackupWorkerConfig) {
-    let failed_paths: Vec<_> = {
-        let queue = queue.lock().unwrap();
-        queue
-            .get_failed(config.max_retries)
-            .into_iter()
-            .map(|b| b.local_path.clone())
-            .collect()
-    };
+/// Names of the destinations that can accept uploads right now, and why the others can't.
+async fn check_destinations(
+    destinations: &[Arc<dyn BackupDestination>],
+) -> (Vec<&str>, Vec<(&str, String)>) {
+    let mut available = Vec::new();
+    let mut unavailable = Vec::new();
+    for destination in destinations {
+        match destination.unavailable_reason().await {
+            Some(reason) => unavailable.push((destination.name(), reason)),
+            None => available.push(destination.name()),
+        }
+    }
+    (available, unavailable)
+}
 
-    if !failed_paths.is_empty() {
-        let mut queue = queue.lock().unwrap();
-        for path in failed_paths {
-            if let Err(e) = queue.reset_to_pending(&path) {
-                error!("Failed to reset backup to pending: {e:?}");
+/// Upload a file to every available destination it hasn't reached yet. Returns the
+/// unavailable destinations it still has to reach.
+async fn upload_to_destinations<'a>(
+    queue: &Mutex<BackupQueue>,
+    local_path: &Path,
+    destinations: &'a [Arc<dyn BackupDestination>],
+    limiter: &Arc<BandwidthLimiter>,
+) -> Result<Vec<&'a str>, String> {
+    let uploaded_to = queue
+        .lock()
+        .unwrap()
+        .get(local_path)
+        .map(|b| b.uploaded_to.clone())
+        .unwrap_or_default();
+
+    let mut waiting = Vec::new();
+
+    for destination in destinations {
+        let name = destination.name();
+        if uploaded_to.iter().any(|d| d == name) {
+            continue;
+        }
+
+        // It may have become unavailable while earlier uploads ran
+        if destination.unavailable_reason().await.is_some() {
+            waiting.push(name);
+            continue;
+        }
+
+        let progress = UploadProgress::new(queue, local_path, name, limiter);
+        if let Err(e) = destination.upload(local_path, &progress).await {
+            // Failing because it became unavailable isn't the file's fault either
+            if destination.unavailable_reason().await.is_some() {
+                warn!("Failed to upload {} to {name}: {e:#}", local_path.display());
+                waiting.push(name);
+                continue;
             }
+            return Err(format!("{name}: {e:#}"));
+        }
+        debug!("Uploaded {} to {name}", local_path.display());
+
+        if let Err(e) = queue.lock().unwrap().mark_uploaded_to(local_path, name) {
+            error!("Failed to record upload to {name}: {e:?}");
         }
     }
+
+    Ok(waiting)
 }
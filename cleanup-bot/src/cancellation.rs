@@ -16,6 +16,7 @@ impl CancellationToken {
 
 /// Registry for per-channel cancellation tokens.
 /// Allows cleanup tasks to be cancelled when a channel is disabled.
+#[derive(Default)]
 pub struct CancellationRegistry {
     tokens: HashMap<ChannelId, watch::Sender<bool>>,
 }
@@ -1,5 +1,11 @@
+pub mod archive;
+pub mod boundary;
+pub mod exemption;
 pub mod queue;
+pub mod report;
+pub mod run_log;
 pub mod task;
+pub mod transcribed;
 pub mod worker;
 
 pub use worker::spawn_worker;
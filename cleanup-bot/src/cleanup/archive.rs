@@ -0,0 +1,268 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, Embed, Message, MessageId, UserId};
+use tokio::fs;
+use tracing::info;
+
+use crate::config::ArchiveFormat;
+use crate::crypto::{ENCRYPTED_EXTENSION, EncryptionKey};
+
+/// A message as written to a transcript archive.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedMessage {
+    pub id: MessageId,
+    pub channel_id: ChannelId,
+    pub author: ArchivedAuthor,
+    pub content: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edited_timestamp: Option<DateTime<Utc>>,
+    /// The message this one replied to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<MessageId>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<ArchivedReaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<Embed>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<ArchivedAttachment>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedAuthor {
+    pub id: UserId,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    pub bot: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedReaction {
+    pub emoji: String,
+    pub count: u64,
+}
+
+/// Attachments are archived by reference, media files are backed up separately.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedAttachment {
+    pub filename: String,
+    pub url: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+impl From<&Message> for ArchivedMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            id: message.id,
+            channel_id: message.channel_id,
+            author: ArchivedAuthor {
+                id: message.author.id,
+                name: message.author.name.clone(),
+                display_name: message.author.global_name.clone(),
+                bot: message.author.bot,
+            },
+            content: message.content.clone(),
+            timestamp: *message.timestamp,
+            edited_timestamp: message.edited_timestamp.map(|t| *t),
+            reply_to: message
+                .message_reference
+                .as_ref()
+                .and_then(|r| r.message_id),
+            reactions: message
+                .reactions
+                .iter()
+                .map(|r| ArchivedReaction {
+                    emoji: r.reaction_type.to_string(),
+                    count: r.count,
+                })
+                .collect(),
+            embeds: message.embeds.clone(),
+            attachments: message
+                .attachments
+                .iter()
+                .map(|a| ArchivedAttachment {
+                    filename: a.filename.clone(),
+                    url: a.url.clone(),
+                    size: a.size as u64,
+                    content_type: a.content_type.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Write a batch of expired messages to a new transcript file, oldest first.
+/// Files are grouped by the date of the oldest message: `dir/YYYY-MM-DD/<channel>_<first>-<last>.<ext>`,
+/// with `.enc` appended when encrypted, so batches shouldn't span more than one day.
+/// Returns the path and the file name.
+pub async fn write_archive(
+    dir: &Path,
+    channel_id: ChannelId,
+    format: ArchiveFormat,
+    messages: &[Message],
+    encryption: Option<&EncryptionKey>,
+) -> Result<(PathBuf, String)> {
+    let mut archived: Vec<ArchivedMessage> = messages.iter().map(ArchivedMessage::from).collect();
+    archived.sort_by_key(|m| m.id);
+
+    let (Some(first), Some(last)) = (archived.first(), archived.last()) else {
+        anyhow::bail!("No messages to archive");
+    };
+
+    let dir = dir.join(first.timestamp.format("%Y-%m-%d").to_string());
+    fs::create_dir_all(&dir)
+        .await
+        .context("Failed to create archive directory")?;
+
+    let mut filename = format!(
+        "{channel_id}_{}-{}.{}",
+        first.id,
+        last.id,
+        format.extension()
+    );
+    if encryption.is_some() {
+        filename = format!("{filename}.{ENCRYPTED_EXTENSION}");
+    }
+    let path = dir.join(&filename);
+
+    let mut content = match format {
+        ArchiveFormat::Jsonl => render_jsonl(&archived)?,
+        ArchiveFormat::Html => render_html(channel_id, &archived),
+    }
+    .into_bytes();
+    if let Some(key) = encryption {
+        content = key.encrypt(&content)?;
+    }
+
+    fs::write(&path, content)
+        .await
+        .context("Failed to write archive file")?;
+
+    info!(
+        "Archived {} messages from channel {channel_id} to {path:?}",
+        archived.len()
+    );
+
+    Ok((path, filename))
+}
+
+fn render_jsonl(messages: &[ArchivedMessage]) -> Result<String> {
+    let mut content = String::new();
+    for message in messages {
+        content.push_str(&serde_json::to_string(message)?);
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+fn render_html(channel_id: ChannelId, messages: &[ArchivedMessage]) -> String {
+    let mut html = String::new();
+
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Channel {channel_id}</title>\n</head>\n<body>\n<h1>Channel {channel_id}</h1>"
+    );
+
+    for message in messages {
+        let author = message
+            .author
+            .display_name
+            .as_deref()
+            .unwrap_or(&message.author.name);
+
+        let _ = writeln!(html, "<div class=\"message\" id=\"{}\">", message.id);
+        let _ = writeln!(
+            html,
+            "<p><strong>{}</strong> <time datetime=\"{ts}\">{ts}</time>{edited}</p>",
+            escape_html(author),
+            ts = message.timestamp.to_rfc3339(),
+            edited = if message.edited_timestamp.is_some() {
+                " (edited)"
+            } else {
+                ""
+            },
+        );
+
+        if let Some(reply_to) = message.reply_to {
+            let _ = writeln!(
+                html,
+                "<p class=\"reply\">Reply to <a href=\"#{reply_to}\">{reply_to}</a></p>"
+            );
+        }
+
+        if !message.content.is_empty() {
+            let _ = writeln!(
+                html,
+                "<p>{}</p>",
+                escape_html(&message.content).replace('\n', "<br>")
+            );
+        }
+
+        for embed in &message.embeds {
+            let title = embed.title.as_deref().unwrap_or("Embed");
+            let title = match &embed.url {
+                Some(url) => format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_html(url),
+                    escape_html(title)
+                ),
+                None => escape_html(title),
+            };
+            let description = embed.description.as_deref().map(escape_html);
+            let _ = writeln!(
+                html,
+                "<blockquote><p>{title}</p>{}</blockquote>",
+                description
+                    .map(|d| format!("<p>{d}</p>"))
+                    .unwrap_or_default()
+            );
+        }
+
+        for attachment in &message.attachments {
+            let _ = writeln!(
+                html,
+                "<p class=\"attachment\"><a href=\"{}\">{}</a> ({} bytes)</p>",
+                escape_html(&attachment.url),
+                escape_html(&attachment.filename),
+                attachment.size
+            );
+        }
+
+        if !message.reactions.is_empty() {
+            let reactions: Vec<_> = message
+                .reactions
+                .iter()
+                .map(|r| format!("{} {}", escape_html(&r.emoji), r.count))
+                .collect();
+            let _ = writeln!(html, "<p class=\"reactions\">{}</p>", reactions.join(" · "));
+        }
+
+        html.push_str("</div>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
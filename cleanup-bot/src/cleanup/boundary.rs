@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serenity::all::{ChannelId, GetMessages, Http, MessageId};
+use tracing::debug;
+
+use crate::cleanup::task::MAX_MESSAGES_PER_FETCH;
+
+// Messages deleted by hand aren't noticed when a cached boundary is moved forward, so it's
+// walked again from the newest message every so often
+const BOUNDARY_REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy)]
+struct CachedBoundary {
+    /// The `n`th newest message when last resolved
+    id: MessageId,
+    /// The newest message when last resolved
+    newest: MessageId,
+    walked_at: Instant,
+}
+
+/// Boundaries of count-based policies found by earlier runs, so a run only looks at the
+/// messages posted since instead of walking back over every kept message.
+#[derive(Clone, Default)]
+pub struct BoundaryCache {
+    boundaries: Arc<Mutex<HashMap<(ChannelId, u32), CachedBoundary>>>,
+}
+
+impl BoundaryCache {
+    /// Return the ID of the `n`th newest message, or `None` if the channel has fewer than `n`.
+    pub async fn nth_newest(
+        &self,
+        http: &Http,
+        channel_id: ChannelId,
+        n: u32,
+    ) -> Result<Option<MessageId>> {
+        let key = (channel_id, n);
+        let cached = self
+            .boundaries
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .filter(|b| b.walked_at.elapsed() < BOUNDARY_REFRESH_INTERVAL);
+
+        let advanced = match cached {
+            Some(cached) => advance(http, channel_id, n, cached).await?,
+            None => None,
+        };
+        let boundary = match advanced {
+            Some(boundary) => Some(boundary),
+            None => walk(http, channel_id, n).await?,
+        };
+
+        let mut boundaries = self.boundaries.lock().unwrap();
+        match boundary {
+            Some(boundary) => boundaries.insert(key, boundary),
+            None => boundaries.remove(&key),
+        };
+
+        Ok(boundary.map(|b| b.id))
+    }
+}
+
+/// Move a cached boundary forward by one message for every message posted since it was found.
+/// Returns `None` if walking back from the newest message is cheaper.
+async fn advance(
+    http: &Http,
+    channel_id: ChannelId,
+    n: u32,
+    cached: CachedBoundary,
+) -> Result<Option<CachedBoundary>> {
+    let posted = messages_after(http, channel_id, cached.newest, n as usize + 1).await?;
+    if posted.len() > n as usize {
+        return Ok(None);
+    }
+
+    let Some(&newest) = posted.last() else {
+        return Ok(Some(cached));
+    };
+
+    let following = messages_after(http, channel_id, cached.id, posted.len()).await?;
+    let Some(&id) = following.get(posted.len() - 1) else {
+        return Ok(None);
+    };
+
+    debug!(
+        "Moved boundary of {n} in channel {channel_id} forward past {} new message(s)",
+        posted.len()
+    );
+
+    Ok(Some(CachedBoundary {
+        id,
+        newest,
+        walked_at: cached.walked_at,
+    }))
+}
+
+/// Walk back from the newest message to the `n`th newest one.
+/// Returns `None` if the channel has fewer than `n` messages.
+async fn walk(http: &Http, channel_id: ChannelId, n: u32) -> Result<Option<CachedBoundary>> {
+    let mut remaining = n as usize;
+    let mut before: Option<MessageId> = None;
+    let mut newest: Option<MessageId> = None;
+
+    loop {
+        let request = match before {
+            Some(before_id) => GetMessages::new()
+                .limit(MAX_MESSAGES_PER_FETCH)
+                .before(before_id),
+            None => GetMessages::new().limit(MAX_MESSAGES_PER_FETCH),
+        };
+
+        let messages = channel_id
+            .messages(http, request)
+            .await
+            .context("Failed to fetch messages")?;
+
+        // Messages are newest-first
+        newest = newest.or(messages.first().map(|m| m.id));
+        if remaining <= messages.len() {
+            return Ok(newest.map(|newest| CachedBoundary {
+                id: messages[remaining - 1].id,
+                newest,
+                walked_at: Instant::now(),
+            }));
+        }
+
+        if messages.len() < MAX_MESSAGES_PER_FETCH as usize {
+            return Ok(None);
+        }
+
+        remaining -= messages.len();
+        before = messages.last().map(|m| m.id);
+    }
+}
+
+/// IDs of up to `limit` messages following `after`, oldest first.
+async fn messages_after(
+    http: &Http,
+    channel_id: ChannelId,
+    mut after: MessageId,
+    limit: usize,
+) -> Result<Vec<MessageId>> {
+    let mut ids = Vec::new();
+
+    while ids.len() < limit {
+        let page = (limit - ids.len()).min(MAX_MESSAGES_PER_FETCH as usize) as u8;
+        let mut batch: Vec<MessageId> = channel_id
+            .messages(http, GetMessages::new().after(after).limit(page))
+            .await
+            .context("Failed to fetch messages")?
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        batch.sort_unstable();
+        ids.extend(&batch);
+
+        match batch.last() {
+            Some(&last) if batch.len() == page as usize => after = last,
+            _ => break,
+        }
+    }
+
+    Ok(ids)
+}
@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serenity::all::{
+    ChannelId, Http, HttpError, Message, ReactionType, RoleId, StatusCode, UserId,
+};
+use tracing::debug;
+
+use crate::config::ExemptionRules;
+
+/// Compiled form of a channel's [`ExemptionRules`].
+pub struct ExemptionFilter {
+    keep_pinned: bool,
+    users: HashSet<UserId>,
+    reactions: Vec<String>,
+    min_reactions: Option<u64>,
+    keywords: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl ExemptionFilter {
+    pub fn new(rules: &ExemptionRules) -> Result<Self> {
+        let patterns = rules
+            .patterns
+            .iter()
+            .map(|p| Regex::new(p).with_context(|| format!("Invalid exemption pattern {p:?}")))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            keep_pinned: rules.keep_pinned,
+            users: rules.users.iter().copied().collect(),
+            reactions: rules.reactions.clone(),
+            min_reactions: rules.min_reactions.map(|n| n.get() as u64),
+            keywords: rules.keywords.iter().map(|k| k.to_lowercase()).collect(),
+            patterns,
+        })
+    }
+
+    /// Also exempt messages from these users (e.g. members resolved from role rules).
+    pub fn with_users(mut self, users: impl IntoIterator<Item = UserId>) -> Self {
+        self.users.extend(users);
+        self
+    }
+
+    /// Returns true if the message should be kept regardless of age.
+    pub fn is_exempt(&self, message: &Message) -> bool {
+        if self.keep_pinned && message.pinned {
+            return true;
+        }
+
+        if self.users.contains(&message.author.id) {
+            return true;
+        }
+
+        if !self.reactions.is_empty()
+            && message.reactions.iter().any(|r| {
+                let name = match &r.reaction_type {
+                    ReactionType::Unicode(emoji) => Some(emoji.as_str()),
+                    ReactionType::Custom { name, .. } => name.as_deref(),
+                    _ => None,
+                };
+                name.is_some_and(|name| self.reactions.iter().any(|e| e == name))
+            })
+        {
+            return true;
+        }
+
+        if let Some(min_reactions) = self.min_reactions {
+            let total: u64 = message.reactions.iter().map(|r| r.count).sum();
+            if total >= min_reactions {
+                return true;
+            }
+        }
+
+        if !self.keywords.is_empty() {
+            let content = message.content.to_lowercase();
+            if self.keywords.iter().any(|k| content.contains(k.as_str())) {
+                return true;
+            }
+        }
+
+        self.patterns.iter().any(|p| p.is_match(&message.content))
+    }
+}
+
+/// Find the authors of `messages` that currently hold any of `roles`.
+/// Messages fetched over REST don't carry member data, so each author is looked up once.
+pub async fn resolve_role_exempt_users(
+    http: &Http,
+    channel_id: ChannelId,
+    roles: &[RoleId],
+    messages: &[Message],
+) -> Result<Vec<UserId>> {
+    if roles.is_empty() || messages.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let guild_id = channel_id
+        .to_channel(http)
+        .await
+        .context("Failed to fetch channel")?
+        .guild()
+        .map(|c| c.guild_id)
+        .context("Role exemptions only apply to guild channels")?;
+
+    let authors: HashSet<UserId> = messages.iter().map(|m| m.author.id).collect();
+    let mut exempt = Vec::new();
+
+    for user_id in authors {
+        match guild_id.member(http, user_id).await {
+            Ok(member) => {
+                if member.roles.iter().any(|r| roles.contains(r)) {
+                    exempt.push(user_id);
+                }
+            }
+            // Members who left the guild (and webhooks) no longer hold any roles
+            Err(e) if is_not_found(&e) => {
+                debug!("{user_id} is no longer a member of {guild_id}");
+            }
+            // Anything else could be hiding an exempt member, so don't delete on a guess
+            Err(e) => {
+                return Err(e).context(format!("Failed to fetch member {user_id}"));
+            }
+        }
+    }
+
+    debug!(
+        "{} author(s) in channel {channel_id} exempt by role",
+        exempt.len()
+    );
+
+    Ok(exempt)
+}
+
+fn is_not_found(error: &serenity::Error) -> bool {
+    matches!(
+        error,
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response))
+            if response.status_code == StatusCode::NOT_FOUND
+    )
+}
@@ -1,8 +1,9 @@
-use std::num::NonZeroU32;
-
+use chrono::{DateTime, Utc};
 use serenity::all::{Message, MessageId};
 
-use crate::media::{AttachmentsExt, MediaAttachment};
+use crate::cleanup::exemption::ExemptionFilter;
+use crate::config::AttachmentRules;
+use crate::media::{AttachmentsExt, MediaAttachment, MessageAuthor};
 
 /// A message that should be deleted immediately (no media backup needed).
 #[derive(Debug)]
@@ -16,15 +17,21 @@ pub struct BackupJob {
     pub message_id: MessageId,
     pub attachments: Vec<MediaAttachment>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub author: MessageAuthor,
+    pub content: String,
 }
 
 /// Result of classifying messages for cleanup.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ClassifiedMessages {
     /// Messages that can be deleted immediately (no media).
     pub delete_jobs: Vec<DeleteJob>,
     /// Messages that need media backup before deletion.
     pub backup_jobs: Vec<BackupJob>,
+    /// Number of messages kept because of an exemption rule.
+    pub exempted: usize,
+    /// The messages behind both kinds of job, kept for archiving.
+    pub expired: Vec<Message>,
 }
 
 impl ClassifiedMessages {
@@ -32,16 +39,28 @@ impl ClassifiedMessages {
         Self {
             delete_jobs: Vec::new(),
             backup_jobs: Vec::new(),
+            exempted: 0,
+            expired: Vec::new(),
         }
     }
 }
 
-/// Classify messages into delete jobs (no media) and backup jobs (has media).
-pub fn classify_messages(messages: Vec<Message>) -> ClassifiedMessages {
+/// Classify messages into delete jobs (nothing to back up) and backup jobs (has attachments
+/// matching the backup rules). Messages matching an exemption rule are left out of both.
+pub fn classify_messages(
+    messages: Vec<Message>,
+    exemptions: &ExemptionFilter,
+    attachment_rules: &AttachmentRules,
+) -> ClassifiedMessages {
     let mut result = ClassifiedMessages::new();
 
     for message in messages {
-        let media_attachments = message.attachments.extract_media();
+        if exemptions.is_exempt(&message) {
+            result.exempted += 1;
+            continue;
+        }
+
+        let media_attachments = message.attachments.extract_media(attachment_rules);
 
         if media_attachments.is_empty() {
             result.delete_jobs.push(DeleteJob {
@@ -52,19 +71,67 @@ pub fn classify_messages(messages: Vec<Message>) -> ClassifiedMessages {
                 message_id: message.id,
                 attachments: media_attachments,
                 timestamp: *message.timestamp,
+                author: MessageAuthor::from(&message.author),
+                content: message.content.clone(),
             });
         }
+
+        result.expired.push(message);
     }
 
     result
 }
 
-/// Filter messages to only those older than the retention cutoff.
-pub fn filter_expired_messages(messages: Vec<Message>, retention_days: NonZeroU32) -> Vec<Message> {
-    let cutoff = chrono::Utc::now() - chrono::Duration::days(retention_days.get() as i64);
+/// A retention policy resolved against the channel's current history for one cleanup run.
+#[derive(Debug, Clone, Copy)]
+pub struct ExpiryCutoffs {
+    /// Messages older than this have exceeded the max age.
+    pub age_cutoff: Option<DateTime<Utc>>,
+    /// Messages older than this ID are beyond the max count.
+    pub count_boundary: Option<MessageId>,
+    /// Messages at or newer than this ID are within the minimum kept.
+    pub keep_boundary: Option<MessageId>,
+}
+
+impl ExpiryCutoffs {
+    pub fn is_expired(&self, message: &Message) -> bool {
+        if self.keep_boundary.is_some_and(|b| message.id >= b) {
+            return false;
+        }
+
+        self.age_cutoff.is_some_and(|c| *message.timestamp < c)
+            || self.count_boundary.is_some_and(|b| message.id < b)
+    }
+
+    /// ID that every expired message is older than, so fetching `before` it skips recent
+    /// history. `None` if nothing can expire.
+    pub fn expired_before(&self) -> Option<MessageId> {
+        let age_boundary = self.age_cutoff.and_then(snowflake_at);
+        let expiry_boundary = age_boundary.into_iter().chain(self.count_boundary).max()?;
+
+        Some(match self.keep_boundary {
+            Some(keep_boundary) => expiry_boundary.min(keep_boundary),
+            None => expiry_boundary,
+        })
+    }
+}
+
+/// Smallest message ID created at `time`: messages older than it have lower IDs.
+/// `None` if `time` predates Discord.
+fn snowflake_at(time: DateTime<Utc>) -> Option<MessageId> {
+    const DISCORD_EPOCH_MS: i64 = 1_420_070_400_000;
+
+    let since_epoch = time.timestamp_millis() - DISCORD_EPOCH_MS;
+    if since_epoch <= 0 {
+        return None;
+    }
+    Some(MessageId::new((since_epoch as u64) << 22))
+}
 
+/// Filter messages to only those expired under the resolved retention policy.
+pub fn filter_expired_messages(messages: Vec<Message>, cutoffs: &ExpiryCutoffs) -> Vec<Message> {
     messages
         .into_iter()
-        .filter(|m| *m.timestamp < cutoff)
+        .filter(|m| cutoffs.is_expired(m))
         .collect()
 }
@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+
+use crate::cleanup::queue::ClassifiedMessages;
+
+/// Summary of what a cleanup run deleted, or would delete in dry-run mode.
+#[derive(Debug, Default, Clone)]
+pub struct CleanupReport {
+    /// Messages fetched from the channel
+    pub scanned: usize,
+    /// Expired messages without media
+    pub text_messages: usize,
+    /// Expired messages with media to back up
+    pub media_messages: usize,
+    /// Total size of the media attachments
+    pub attachment_bytes: u64,
+    /// Expired messages kept by an exemption rule
+    pub exempted: usize,
+    /// Timestamp of the oldest affected message
+    pub oldest: Option<DateTime<Utc>>,
+    /// Timestamp of the newest affected message
+    pub newest: Option<DateTime<Utc>>,
+    /// Messages actually deleted from Discord
+    pub deleted: usize,
+    /// Messages that failed to delete or back up
+    pub failed: usize,
+    /// Media files added to the backup queue
+    pub backups_queued: usize,
+    /// Messages written to a transcript archive
+    pub archived: usize,
+    /// Transcript files added to the backup queue
+    pub transcripts_written: usize,
+    /// Media messages left in Discord because the download directory is full
+    pub skipped_media: usize,
+    /// Whether the run stopped early because cleanup was disabled
+    pub cancelled: bool,
+}
+
+impl CleanupReport {
+    /// Add a batch of classified messages to the report.
+    pub fn add_classified(&mut self, classified: &ClassifiedMessages) {
+        self.text_messages += classified.delete_jobs.len();
+        self.media_messages += classified.backup_jobs.len();
+        self.exempted += classified.exempted;
+
+        let delete_times = classified
+            .delete_jobs
+            .iter()
+            .map(|j| *j.message_id.created_at());
+        let backup_times = classified.backup_jobs.iter().map(|j| j.timestamp);
+
+        for timestamp in delete_times.chain(backup_times) {
+            self.oldest = Some(self.oldest.map_or(timestamp, |t| t.min(timestamp)));
+            self.newest = Some(self.newest.map_or(timestamp, |t| t.max(timestamp)));
+        }
+
+        self.attachment_bytes += classified
+            .backup_jobs
+            .iter()
+            .flat_map(|j| &j.attachments)
+            .map(|a| a.size)
+            .sum::<u64>();
+    }
+
+    /// Number of messages deleted (or that would be deleted).
+    pub fn affected(&self) -> usize {
+        self.text_messages + self.media_messages
+    }
+}
+
+/// Format a byte count for display, e.g. `1.5 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
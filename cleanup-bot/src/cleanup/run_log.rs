@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::all::ChannelId;
+use tracing::warn;
+
+use crate::cleanup::report::CleanupReport;
+
+const RUN_LOG_PATH: &str = "./cleanup_runs.jsonl";
+const RUN_LOG_TEMP_PATH: &str = "./cleanup_runs.jsonl.tmp";
+/// Number of runs kept across all channels. The file is compacted once it holds twice this many.
+const MAX_RUN_LOG_ENTRIES: usize = 1000;
+
+/// Outcome of a single cleanup run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub channel_id: ChannelId,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub scanned: usize,
+    pub deleted: usize,
+    /// Messages a dry run found expired
+    #[serde(default)]
+    pub would_delete: usize,
+    pub failed: usize,
+    pub backups_queued: usize,
+    #[serde(default)]
+    pub archived: usize,
+    #[serde(default)]
+    pub transcripts_written: usize,
+    #[serde(default)]
+    pub skipped_media: usize,
+    #[serde(default)]
+    pub exempted: usize,
+    pub cancelled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RunRecord {
+    pub fn new(
+        channel_id: ChannelId,
+        started_at: DateTime<Utc>,
+        dry_run: bool,
+        report: &CleanupReport,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            channel_id,
+            started_at,
+            finished_at: Utc::now(),
+            dry_run,
+            scanned: report.scanned,
+            deleted: report.deleted,
+            would_delete: if dry_run { report.affected() } else { 0 },
+            failed: report.failed,
+            backups_queued: report.backups_queued,
+            archived: report.archived,
+            transcripts_written: report.transcripts_written,
+            skipped_media: report.skipped_media,
+            exempted: report.exempted,
+            cancelled: report.cancelled,
+            error,
+        }
+    }
+}
+
+/// Append-only log of recent cleanup runs, persisted as JSON lines.
+pub struct RunLog {
+    entries: VecDeque<RunRecord>,
+    lines_on_disk: usize,
+}
+
+impl RunLog {
+    /// Load the run log from disk, or start an empty one.
+    pub fn load() -> Result<Self> {
+        let Ok(content) = fs::read_to_string(RUN_LOG_PATH) else {
+            return Ok(Self {
+                entries: VecDeque::new(),
+                lines_on_disk: 0,
+            });
+        };
+
+        let mut entries = VecDeque::new();
+        let mut lines_on_disk = 0;
+
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            lines_on_disk += 1;
+            match serde_json::from_str(line) {
+                Ok(record) => entries.push_back(record),
+                // A crash mid-write can leave a truncated last line
+                Err(e) => warn!("Skipping unreadable run log entry: {e}"),
+            }
+        }
+
+        while entries.len() > MAX_RUN_LOG_ENTRIES {
+            entries.pop_front();
+        }
+
+        Ok(Self {
+            entries,
+            lines_on_disk,
+        })
+    }
+
+    /// Record a run and persist it.
+    pub fn record(&mut self, record: RunRecord) -> Result<()> {
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+
+        self.entries.push_back(record);
+        if self.entries.len() > MAX_RUN_LOG_ENTRIES {
+            self.entries.pop_front();
+        }
+
+        if self.lines_on_disk >= MAX_RUN_LOG_ENTRIES * 2 {
+            return self.compact();
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(RUN_LOG_PATH)
+            .context(format!("Failed to open {RUN_LOG_PATH}"))?;
+        file.write_all(line.as_bytes())
+            .context("Failed to write run log entry")?;
+        self.lines_on_disk += 1;
+
+        Ok(())
+    }
+
+    /// Most recent runs for a channel, newest first.
+    pub fn recent(&self, channel_id: ChannelId, limit: usize) -> Vec<&RunRecord> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|r| r.channel_id == channel_id)
+            .take(limit)
+            .collect()
+    }
+
+    /// Most recent run for a channel.
+    pub fn last(&self, channel_id: ChannelId) -> Option<&RunRecord> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|r| r.channel_id == channel_id)
+    }
+
+    /// Rewrite the file with only the entries kept in memory (write to temp file, then rename).
+    fn compact(&mut self) -> Result<()> {
+        let mut content = String::new();
+        for entry in &self.entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+
+        fs::write(RUN_LOG_TEMP_PATH, &content).context("Failed to write temp run log file")?;
+        fs::rename(RUN_LOG_TEMP_PATH, RUN_LOG_PATH).context("Failed to rename run log file")?;
+        self.lines_on_disk = self.entries.len();
+
+        Ok(())
+    }
+}
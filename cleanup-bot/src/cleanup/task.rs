@@ -1,18 +1,32 @@
-use std::num::NonZeroU32;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::{Context, Result};
-use chrono::Days;
-use serenity::all::{ChannelId, GetMessages, Http, Timestamp};
+use chrono::{Days, NaiveDate};
+use serenity::all::{ChannelId, GetMessages, Http, Message, MessageId, Timestamp};
 use tokio::time::sleep;
 use tracing::{debug, error, info, warn};
 
+use crate::alert::Alerter;
 use crate::backup::{BackupQueue, BackupStatus, PendingBackup};
 use crate::cancellation::{CancellationRegistry, CancellationToken};
-use crate::cleanup::queue::{BackupJob, DeleteJob, classify_messages, filter_expired_messages};
-use crate::config::ConfigStore;
-use crate::media::MediaDownloader;
+use crate::cleanup::archive::write_archive;
+use crate::cleanup::boundary::BoundaryCache;
+use crate::cleanup::exemption::{ExemptionFilter, resolve_role_exempt_users};
+use crate::cleanup::queue::{
+    BackupJob, ClassifiedMessages, DeleteJob, ExpiryCutoffs, classify_messages,
+    filter_expired_messages,
+};
+use crate::cleanup::report::CleanupReport;
+use crate::cleanup::run_log::{RunLog, RunRecord};
+use crate::cleanup::transcribed::TranscribedMessages;
+use crate::config::{ArchiveFormat, ConfigStore, RetentionPolicy};
+use crate::crypto::EncryptionKey;
+use crate::media::quota::download_budget;
+use crate::media::{ChannelContext, MediaDownloader, MediaIndex, MessageMetadata};
 
 // Note: Discord requires messages to be < 14 days old for bulk delete
 // see (https://discord.com/developers/docs/resources/message#bulk-delete-messages).
@@ -21,53 +35,110 @@ const BULK_DELETE_MIN: usize = 2;
 const BULK_DELETE_MAX: usize = 100;
 const SINGLE_DELETE_DELAY: Duration = Duration::from_millis(200);
 const BULK_DELETE_DELAY: Duration = Duration::from_secs(1);
-const MAX_MESSAGES_PER_FETCH: u8 = 100;
+pub const MAX_MESSAGES_PER_FETCH: u8 = 100;
 const TARGET_EXPIRED_MESSAGES: usize = 100;
 const MAX_PAGINATION_ROUNDS: usize = 10;
+// Previews answer a slash command, which must be done within the 15 minute interaction window
+const MAX_PREVIEW_ROUNDS: usize = 500;
+// Room for a message's metadata sidecar and encryption overhead on top of its attachments
+const SIDECAR_ALLOWANCE: u64 = 64 * 1024;
+
+/// Shared state handed to every cleanup task.
+#[derive(Clone)]
+pub struct CleanupContext {
+    pub http: Arc<Http>,
+    pub config: ConfigStore,
+    pub backup_queue: Arc<Mutex<BackupQueue>>,
+    pub cancellation: Arc<Mutex<CancellationRegistry>>,
+    pub run_log: Arc<Mutex<RunLog>>,
+    /// Key for encrypting backups, set when backup encryption is enabled
+    pub encryption: Option<EncryptionKey>,
+    pub alerter: Alerter,
+    /// Set while media messages are skipped for lack of disk space, so the alert is sent once
+    pub storage_full: Arc<AtomicBool>,
+    pub media_index: Arc<Mutex<MediaIndex>>,
+    /// Archived messages whose deletion hasn't succeeded yet
+    pub transcribed: Arc<Mutex<TranscribedMessages>>,
+    pub boundaries: BoundaryCache,
+}
 
 /// Run cleanup for a single channel.
 pub async fn cleanup_channel(
-    http: Arc<Http>,
-    config: ConfigStore,
-    backup_queue: Arc<Mutex<BackupQueue>>,
-    cancellation: Arc<Mutex<CancellationRegistry>>,
+    ctx: CleanupContext,
     channel_id: ChannelId,
-    retention_days: NonZeroU32,
+    policy: RetentionPolicy,
     cancel_token: CancellationToken,
 ) {
-    let result = run_cleanup(
-        http,
-        config,
-        backup_queue,
-        channel_id,
-        retention_days,
-        cancel_token,
-    )
-    .await;
+    let started_at = chrono::Utc::now();
+    let dry_run = ctx.config.is_dry_run(channel_id);
+    let mut report = CleanupReport::default();
+    let result = run_cleanup(&ctx, channel_id, policy, cancel_token, dry_run, &mut report).await;
 
     // Deregister cancellation token
-    cancellation.lock().unwrap().deregister(channel_id);
+    ctx.cancellation.lock().unwrap().deregister(channel_id);
+
+    let error = match result {
+        Ok(()) => {
+            if dry_run {
+                info!("Dry run for channel {channel_id} would delete: {report:?}");
+            }
+            None
+        }
+        Err(e) => {
+            error!("Cleanup failed for channel {channel_id}: {e:?}");
+            Some(format!("{e:#}"))
+        }
+    };
 
-    if let Err(e) = result {
-        error!("Cleanup failed for channel {channel_id}: {e:?}");
+    let record = RunRecord::new(channel_id, started_at, dry_run, &report, error);
+    if let Err(e) = ctx.run_log.lock().unwrap().record(record) {
+        error!("Failed to record cleanup run: {e:?}");
     }
 }
 
 async fn run_cleanup(
-    http: Arc<Http>,
-    config: ConfigStore,
-    backup_queue: Arc<Mutex<BackupQueue>>,
+    ctx: &CleanupContext,
     channel_id: ChannelId,
-    retention_days: NonZeroU32,
+    policy: RetentionPolicy,
     cancel_token: CancellationToken,
+    dry_run: bool,
+    report: &mut CleanupReport,
 ) -> Result<()> {
-    use serenity::all::{Message, MessageId};
+    let CleanupContext {
+        http,
+        config,
+        backup_queue,
+        encryption,
+        media_index,
+        transcribed,
+        boundaries,
+        ..
+    } = ctx;
+
+    info!(
+        "Starting {}cleanup for channel {channel_id} (retention: {policy})",
+        if dry_run { "dry-run " } else { "" }
+    );
+
+    let Some(cutoffs) = resolve_cutoffs(http, boundaries, channel_id, &policy).await? else {
+        info!(
+            "Channel {channel_id} has no more than {} messages, nothing to clean up",
+            policy.min_keep()
+        );
+        return Ok(());
+    };
 
-    info!("Starting cleanup for channel {channel_id} (retention: {retention_days} days)");
+    let Some(expired_before) = cutoffs.expired_before() else {
+        info!("Nothing in channel {channel_id} can expire under {policy}");
+        return Ok(());
+    };
 
-    // Load pagination cursor from config
-    let mut cursor: Option<MessageId> =
-        config.get_pagination_cursor(channel_id).map(MessageId::new);
+    // Every message before the boundary is expired, so start there rather than at the newest
+    // message. A saved cursor resumes further back, past messages earlier runs left in place.
+    let mut cursor = config
+        .get_pagination_cursor(channel_id)
+        .map(MessageId::new)
+        .map_or(expired_before, |c| c.min(expired_before));
 
     let mut expired_messages: Vec<Message> = Vec::new();
     let mut reached_end = false;
@@ -76,21 +147,18 @@ async fn run_cleanup(
     for round in 0..MAX_PAGINATION_ROUNDS {
         if cancel_token.is_cancelled() {
             info!("Cleanup cancelled for channel {channel_id}");
+            report.cancelled = true;
             return Ok(());
         }
 
-        // Build request with pagination
-        let request = match cursor {
-            Some(before_id) => GetMessages::new()
-                .limit(MAX_MESSAGES_PER_FETCH)
-                .before(before_id),
-            None => GetMessages::new().limit(MAX_MESSAGES_PER_FETCH),
-        };
+        let request = GetMessages::new()
+            .limit(MAX_MESSAGES_PER_FETCH)
+            .before(cursor);
 
         debug!(
             "Pagination round {}: fetching messages before {}",
             round + 1,
-            cursor.unwrap_or_default()
+            cursor
         );
 
         // Fetch messages
@@ -109,10 +177,11 @@ async fn run_cleanup(
             "Fetched {} messages from channel {channel_id}",
             messages.len()
         );
+        report.scanned += messages.len();
 
         // Update cursor to oldest message in batch (last element, since messages are newest-first)
         if let Some(oldest) = messages.last() {
-            cursor = Some(oldest.id);
+            cursor = oldest.id;
         }
 
         // Check if we got a partial batch (indicates end of channel history)
@@ -122,7 +191,7 @@ async fn run_cleanup(
         }
 
         // Filter expired messages and add to collection
-        let batch_expired = filter_expired_messages(messages, retention_days);
+        let batch_expired = filter_expired_messages(messages, &cutoffs);
         debug!("Found {} expired messages in batch", batch_expired.len());
         expired_messages.extend(batch_expired);
 
@@ -136,7 +205,7 @@ async fn run_cleanup(
 
             // Update cursor to oldest message in truncated batch
             if let Some(oldest) = expired_messages.last() {
-                cursor = Some(oldest.id);
+                cursor = oldest.id;
             }
 
             break;
@@ -155,65 +224,378 @@ async fn run_cleanup(
             expired_messages.len()
         );
 
-        // Classify into delete vs backup jobs
-        let classified = classify_messages(expired_messages);
+        let mut classified = classify_expired(http, config, channel_id, expired_messages).await?;
         info!(
-            "Classified: {} delete jobs, {} backup jobs",
+            "Classified: {} delete jobs, {} backup jobs, {} exempt",
             classified.delete_jobs.len(),
-            classified.backup_jobs.len()
+            classified.backup_jobs.len(),
+            classified.exempted
         );
+        report.add_classified(&classified);
+
+        if dry_run {
+            // Move through history as a real run would, so successive dry runs cover all of it.
+            // Re-enabling the channel for real resets the cursor.
+            save_cursor(config, channel_id, cursor, reached_end)?;
+            return Ok(());
+        }
 
         if cancel_token.is_cancelled() {
             info!("Cleanup cancelled for channel {channel_id}");
+            report.cancelled = true;
             return Ok(());
         }
 
-        // Process delete jobs (non-media messages)
-        if !classified.delete_jobs.is_empty() {
-            delete_messages(&http, channel_id, &classified.delete_jobs, &cancel_token).await?;
+        if !classified.backup_jobs.is_empty() {
+            let newest_skipped = apply_storage_limits(ctx, &mut classified, report).await?;
+            if let Some(newest_skipped) = newest_skipped {
+                // Resume with the skipped messages next time, so they're backed up once there's
+                // space instead of waiting for the cursor to wrap around
+                cursor = cursor.max(MessageId::new(newest_skipped.get() + 1));
+            }
+        }
+
+        // Back up media first, so messages whose download fails stay out of the archive
+        if !classified.backup_jobs.is_empty() {
+            let downloader = MediaDownloader::new(
+                Arc::clone(http),
+                config.media_backup_config().download_dir,
+                encryption.clone(),
+                Arc::clone(media_index),
+            );
+
+            let backed_up = process_backup_jobs(
+                http,
+                channel_id,
+                &downloader,
+                backup_queue,
+                &classified.backup_jobs,
+                &cancel_token,
+                report,
+            )
+            .await?;
+
+            let failed: HashSet<MessageId> = classified
+                .backup_jobs
+                .iter()
+                .map(|j| j.message_id)
+                .filter(|id| !backed_up.contains(id))
+                .collect();
+            classified.expired.retain(|m| !failed.contains(&m.id));
+            classified.delete_jobs.extend(
+                backed_up
+                    .into_iter()
+                    .map(|message_id| DeleteJob { message_id }),
+            );
         }
 
         if cancel_token.is_cancelled() {
             info!("Cleanup cancelled for channel {channel_id}");
+            report.cancelled = true;
             return Ok(());
         }
 
-        // Process backup jobs (media messages)
-        if !classified.backup_jobs.is_empty() {
-            let download_dir = config.media_backup_config().download_dir;
+        // Archive before anything is deleted, so a failed write leaves the messages in place.
+        // Messages archived by an earlier run whose deletion failed are already in a transcript.
+        if let Some(format) = config.archive_format(channel_id) {
+            let untranscribed: Vec<Message> = {
+                let transcribed = transcribed.lock().unwrap();
+                classified
+                    .expired
+                    .iter()
+                    .filter(|m| !transcribed.contains(m.id))
+                    .cloned()
+                    .collect()
+            };
+
+            report.transcripts_written += archive_messages(
+                config,
+                backup_queue,
+                channel_id,
+                format,
+                &untranscribed,
+                encryption.as_ref(),
+            )
+            .await?;
+            report.archived += untranscribed.len();
+            transcribed
+                .lock()
+                .unwrap()
+                .add(untranscribed.iter().map(|m| m.id))?;
+        }
 
-            process_backup_jobs(
-                &http,
+        // Delete text messages and media messages whose files are queued for backup
+        if !classified.delete_jobs.is_empty() {
+            let deleted = delete_messages(
+                http,
                 channel_id,
-                download_dir,
-                &backup_queue,
-                &classified.backup_jobs,
+                &classified.delete_jobs,
                 &cancel_token,
+                report,
             )
             .await?;
+            transcribed.lock().unwrap().remove(deleted)?;
         }
     }
 
+    save_cursor(config, channel_id, cursor, reached_end)?;
+    info!("Cleanup completed for channel {channel_id}");
+
+    Ok(())
+}
+
+/// Save where the next run should continue from, or clear the cursor to start over from the
+/// newest expired messages once the start of the channel was reached.
+fn save_cursor(
+    config: &ConfigStore,
+    channel_id: ChannelId,
+    cursor: MessageId,
+    reached_end: bool,
+) -> Result<()> {
     if reached_end {
         debug!("Reached end of channel history, clearing pagination cursor");
-        config.set_pagination_cursor(channel_id, None)?;
+        config.set_pagination_cursor(channel_id, None)
     } else {
-        debug!("Saving pagination cursor: {:?}", cursor);
-        config.set_pagination_cursor(channel_id, cursor.map(|c| c.get()))?;
+        debug!("Saving pagination cursor: {cursor}");
+        config.set_pagination_cursor(channel_id, Some(cursor.get()))
     }
+}
 
-    info!("Cleanup completed for channel {channel_id}");
+/// Drop backup jobs that don't fit in the download directory's quota or free space, leaving
+/// those messages in Discord. Returns the newest skipped message, if any.
+async fn apply_storage_limits(
+    ctx: &CleanupContext,
+    classified: &mut ClassifiedMessages,
+    report: &mut CleanupReport,
+) -> Result<Option<MessageId>> {
+    let Some(mut budget) = download_budget(&ctx.config.media_backup_config()).await? else {
+        return Ok(None);
+    };
+
+    let mut skipped = HashSet::new();
+    classified.backup_jobs.retain(|job| {
+        let size = job.attachments.iter().map(|a| a.size).sum::<u64>() + SIDECAR_ALLOWANCE;
+        if size <= budget {
+            budget -= size;
+            true
+        } else {
+            skipped.insert(job.message_id);
+            false
+        }
+    });
 
-    Ok(())
+    if skipped.is_empty() {
+        if ctx.storage_full.swap(false, Ordering::Relaxed) {
+            info!("Download directory has space again, resuming media backups");
+        }
+        return Ok(None);
+    }
+
+    // Skipped messages stay in Discord, so keep them out of the transcript until they're deleted
+    classified.expired.retain(|m| !skipped.contains(&m.id));
+    report.media_messages -= skipped.len();
+    report.skipped_media += skipped.len();
+    warn!(
+        "Download directory is full, leaving {} media messages in Discord",
+        skipped.len()
+    );
+
+    if !ctx.storage_full.swap(true, Ordering::Relaxed) {
+        ctx.alerter
+            .send(
+                "The media download directory is full, media messages are left in Discord \
+                 until pending backups are uploaded.",
+            )
+            .await;
+    }
+
+    Ok(skipped.into_iter().max())
 }
 
-/// Delete non-media messages with rate limiting.
+/// Write expired messages to transcript archives and queue them for backup. Returns the number
+/// of transcripts written, one per UTC day, so a restore finds every message under the day it
+/// was posted.
+async fn archive_messages(
+    config: &ConfigStore,
+    backup_queue: &Mutex<BackupQueue>,
+    channel_id: ChannelId,
+    format: ArchiveFormat,
+    messages: &[Message],
+    encryption: Option<&EncryptionKey>,
+) -> Result<usize> {
+    let mut days: BTreeMap<NaiveDate, Vec<Message>> = BTreeMap::new();
+    for message in messages {
+        days.entry(message.timestamp.date_naive())
+            .or_default()
+            .push(message.clone());
+    }
+
+    let archive_dir = config.archive_config().dir;
+    for messages in days.values() {
+        archive_day(
+            &archive_dir,
+            backup_queue,
+            channel_id,
+            format,
+            messages,
+            encryption,
+        )
+        .await?;
+    }
+
+    Ok(days.len())
+}
+
+/// Write one day's expired messages to a transcript archive and queue it for backup.
+async fn archive_day(
+    archive_dir: &Path,
+    backup_queue: &Mutex<BackupQueue>,
+    channel_id: ChannelId,
+    format: ArchiveFormat,
+    messages: &[Message],
+    encryption: Option<&EncryptionKey>,
+) -> Result<()> {
+    let Some(newest) = messages.iter().max_by_key(|m| m.id) else {
+        return Ok(());
+    };
+
+    let (local_path, filename) =
+        write_archive(archive_dir, channel_id, format, messages, encryption)
+            .await
+            .context("Failed to write message archive")?;
+
+    let pending = PendingBackup {
+        message_id: newest.id.get(),
+        channel_id: channel_id.get(),
+        local_path,
+        original_filename: filename,
+        timestamp: *newest.timestamp,
+        retry_count: 0,
+        status: BackupStatus::Pending,
+        next_attempt_at: None,
+        uploaded_to: Vec::new(),
+        upload_session: None,
+        content: None,
+    };
+    backup_queue
+        .lock()
+        .unwrap()
+        .add(pending)
+        .context("Failed to queue message archive for backup")
+}
+
+/// Scan a channel's history and report what the policy would delete, without deleting
+/// anything or touching the pagination cursor.
+/// Also returns whether the scan reached the start of the channel.
+pub async fn preview_cleanup(
+    http: &Http,
+    config: &ConfigStore,
+    channel_id: ChannelId,
+    policy: RetentionPolicy,
+) -> Result<(CleanupReport, bool)> {
+    let mut report = CleanupReport::default();
+
+    // Previews are one-off, so boundaries are found from scratch
+    let boundaries = BoundaryCache::default();
+    let Some(cutoffs) = resolve_cutoffs(http, &boundaries, channel_id, &policy).await? else {
+        return Ok((report, true));
+    };
+
+    let Some(mut cursor) = cutoffs.expired_before() else {
+        return Ok((report, true));
+    };
+
+    for _ in 0..MAX_PREVIEW_ROUNDS {
+        let request = GetMessages::new()
+            .limit(MAX_MESSAGES_PER_FETCH)
+            .before(cursor);
+
+        let messages = channel_id
+            .messages(http, request)
+            .await
+            .context("Failed to fetch messages")?;
+
+        let reached_end = messages.len() < MAX_MESSAGES_PER_FETCH as usize;
+        report.scanned += messages.len();
+        if let Some(oldest) = messages.last() {
+            cursor = oldest.id;
+        }
+
+        let expired = filter_expired_messages(messages, &cutoffs);
+        if !expired.is_empty() {
+            let classified = classify_expired(http, config, channel_id, expired).await?;
+            report.add_classified(&classified);
+        }
+
+        if reached_end {
+            return Ok((report, true));
+        }
+    }
+
+    Ok((report, false))
+}
+
+/// Classify expired messages into delete vs backup jobs, keeping exempt messages.
+async fn classify_expired(
+    http: &Http,
+    config: &ConfigStore,
+    channel_id: ChannelId,
+    messages: Vec<Message>,
+) -> Result<ClassifiedMessages> {
+    let rules = config.get_exemptions(channel_id);
+    let role_exempt_users =
+        resolve_role_exempt_users(http, channel_id, &rules.roles, &messages).await?;
+    let exemptions = ExemptionFilter::new(&rules)?.with_users(role_exempt_users);
+
+    let attachment_rules = config.get_attachment_rules(channel_id);
+
+    Ok(classify_messages(messages, &exemptions, &attachment_rules))
+}
+
+/// Resolve a retention policy into cutoffs for this run.
+/// Returns `None` if the channel holds no more than the policy's `min_keep` messages.
+async fn resolve_cutoffs(
+    http: &Http,
+    boundaries: &BoundaryCache,
+    channel_id: ChannelId,
+    policy: &RetentionPolicy,
+) -> Result<Option<ExpiryCutoffs>> {
+    let age_cutoff = policy
+        .max_age_days()
+        .map(|days| chrono::Utc::now() - chrono::Duration::days(days.get() as i64));
+
+    let count_boundary = match policy.max_count() {
+        Some(max_count) => {
+            boundaries
+                .nth_newest(http, channel_id, max_count.get())
+                .await?
+        }
+        None => None,
+    };
+
+    let keep_boundary = match policy.min_keep() {
+        0 => None,
+        min_keep => match boundaries.nth_newest(http, channel_id, min_keep).await? {
+            Some(id) => Some(id),
+            None => return Ok(None),
+        },
+    };
+
+    Ok(Some(ExpiryCutoffs {
+        age_cutoff,
+        count_boundary,
+        keep_boundary,
+    }))
+}
+
+/// Delete messages with rate limiting. Returns the messages that were deleted.
 async fn delete_messages(
     http: &Http,
     channel_id: ChannelId,
     jobs: &[DeleteJob],
     cancel_token: &CancellationToken,
-) -> Result<()> {
+    report: &mut CleanupReport,
+) -> Result<Vec<MessageId>> {
     let bulk_delete_cutoff: Timestamp = Timestamp::now()
         .checked_sub_days(BULK_DELETE_THRESHOLD)
         .context("can't compute bulk delete cutoff")?
@@ -221,6 +603,7 @@ async fn delete_messages(
     let (mut bulk_jobs, mut individual_jobs): (Vec<_>, Vec<_>) = jobs.iter().partition(|j|
         // We can bulk delete messages newer than the cutoff
         j.message_id.created_at() > bulk_delete_cutoff);
+    let mut deleted = Vec::new();
 
     if bulk_jobs.len() < BULK_DELETE_MIN {
         individual_jobs.append(&mut bulk_jobs);
@@ -231,7 +614,7 @@ async fn delete_messages(
 
         for chunk in chunks {
             if cancel_token.is_cancelled() {
-                return Ok(());
+                return Ok(deleted);
             }
 
             if let Err(e) = channel_id
@@ -239,11 +622,14 @@ async fn delete_messages(
                 .await
             {
                 warn!("Bulk delete failed: {e:?}",);
+                report.failed += chunk.len();
             } else {
                 info!(
                     "Bulk deleted {} messages from channel {channel_id}",
                     chunk.len(),
                 );
+                report.deleted += chunk.len();
+                deleted.extend(chunk.iter().map(|j| j.message_id));
             }
 
             sleep(BULK_DELETE_DELAY).await;
@@ -251,38 +637,44 @@ async fn delete_messages(
     }
 
     if !individual_jobs.is_empty() {
-        for job in jobs {
+        for job in individual_jobs {
             if cancel_token.is_cancelled() {
-                return Ok(());
+                return Ok(deleted);
             }
 
             if let Err(e) = channel_id.delete_message(http, job.message_id).await {
                 error!("Failed to delete message {}: {e:?}", job.message_id);
+                report.failed += 1;
             } else {
                 debug!("Deleted message {}", job.message_id);
+                report.deleted += 1;
+                deleted.push(job.message_id);
             }
 
             sleep(SINGLE_DELETE_DELAY).await;
         }
     }
 
-    Ok(())
+    Ok(deleted)
 }
 
-/// Process backup jobs: download media locally, add to backup queue, then delete Discord message.
+/// Download media and queue it for backup. Returns the messages whose files were all queued,
+/// which are safe to delete.
 async fn process_backup_jobs(
     http: &Http,
     channel_id: ChannelId,
-    download_dir: std::path::PathBuf,
+    downloader: &MediaDownloader,
     backup_queue: &Mutex<BackupQueue>,
     jobs: &[BackupJob],
     cancel_token: &CancellationToken,
-) -> Result<()> {
-    let downloader = MediaDownloader::new(download_dir);
+    report: &mut CleanupReport,
+) -> Result<Vec<MessageId>> {
+    let channel = fetch_channel_context(http, channel_id).await;
+    let mut backed_up = Vec::new();
 
     for job in jobs {
         if cancel_token.is_cancelled() {
-            return Ok(());
+            break;
         }
 
         info!(
@@ -291,8 +683,17 @@ async fn process_backup_jobs(
             job.attachments.len()
         );
 
+        let metadata = MessageMetadata {
+            message_id: job.message_id,
+            channel_id,
+            channel: channel.clone(),
+            author: job.author.clone(),
+            content: job.content.clone(),
+            timestamp: job.timestamp,
+        };
+
         let results = match downloader
-            .download_attachments(job.message_id, job.timestamp, &job.attachments)
+            .download_attachments(&metadata, &job.attachments)
             .await
         {
             Ok(results) => {
@@ -309,10 +710,12 @@ async fn process_backup_jobs(
                     job.message_id
                 );
                 // Don't delete the message if download failed
+                report.failed += 1;
                 continue;
             }
         };
 
+        let mut queued_all = true;
         {
             let mut queue = backup_queue.lock().unwrap();
             for result in &results {
@@ -324,33 +727,62 @@ async fn process_backup_jobs(
                     timestamp: job.timestamp,
                     retry_count: 0,
                     status: BackupStatus::Pending,
+                    next_attempt_at: None,
+                    uploaded_to: Vec::new(),
+                    upload_session: None,
+                    content: result.content.clone(),
                 };
                 if let Err(e) = queue.add(pending) {
                     error!(
                         "Failed to add backup to queue for {}: {e:?}",
                         result.local_path.display()
                     );
-                    // Don't delete the message if we can't track it
+                    queued_all = false;
                     continue;
                 }
+                report.backups_queued += 1;
             }
         }
 
-        // NOW it's safe to delete Discord message
-        if let Err(e) = channel_id.delete_message(http, job.message_id).await {
-            error!(
-                "Failed to delete message {} after backup: {e:?}",
-                job.message_id
-            );
-            // Message stays in Discord, but files are queued for backup
-            // This is acceptable - the message might get re-processed next run
+        if queued_all {
+            backed_up.push(job.message_id);
         } else {
-            info!("Deleted message {} after successful backup", job.message_id);
+            // Don't delete the message if we can't track all of its files
+            report.failed += 1;
         }
-
-        // Rate limit between message deletions
-        sleep(SINGLE_DELETE_DELAY).await;
     }
 
-    Ok(())
+    Ok(backed_up)
+}
+
+/// Look up channel and guild names for backup sidecars. Missing names don't stop the backup.
+async fn fetch_channel_context(http: &Http, channel_id: ChannelId) -> ChannelContext {
+    let channel = match channel_id.to_channel(http).await {
+        Ok(channel) => channel.guild(),
+        Err(e) => {
+            warn!("Couldn't fetch channel {channel_id} for backup metadata: {e:?}");
+            None
+        }
+    };
+
+    let Some(channel) = channel else {
+        return ChannelContext::default();
+    };
+
+    let guild_name = match channel.guild_id.to_partial_guild(http).await {
+        Ok(guild) => Some(guild.name),
+        Err(e) => {
+            warn!(
+                "Couldn't fetch guild {} for backup metadata: {e:?}",
+                channel.guild_id
+            );
+            None
+        }
+    };
+
+    ChannelContext {
+        channel_name: Some(channel.name),
+        guild_id: Some(channel.guild_id),
+        guild_name,
+    }
 }
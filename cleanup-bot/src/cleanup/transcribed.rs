@@ -0,0 +1,232 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serenity::all::MessageId;
+use tracing::warn;
+
+const TRANSCRIBED_PATH: &str = "./transcribed_messages.jsonl";
+/// The journal is compacted once it holds this many records.
+const MIN_COMPACTION_RECORDS: usize = 100;
+
+/// A change to the set, one JSON object per journal line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+enum JournalRecord {
+    Add { message_ids: Vec<MessageId> },
+    Remove { message_ids: Vec<MessageId> },
+}
+
+/// Messages already written to a transcript but not yet deleted from Discord.
+///
+/// Transcripts are written before deletion, so a run whose deletes fail or are cancelled leaves
+/// archived messages in the channel. Later runs skip them when archiving, so restores don't
+/// repost them twice. Persisted as a journal like the backup queue.
+#[derive(Debug)]
+pub struct TranscribedMessages {
+    path: PathBuf,
+    ids: HashSet<MessageId>,
+    journal: File,
+    records_on_disk: usize,
+}
+
+impl TranscribedMessages {
+    /// Load the set from disk, or start an empty one.
+    pub fn load() -> Result<Self> {
+        Self::load_from(Path::new(TRANSCRIBED_PATH))
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        let ids = match fs::read_to_string(path) {
+            Ok(content) => replay_journal(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+        };
+
+        // Compacting also drops a record truncated by a crash
+        let journal = write_snapshot(path, &ids)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            ids,
+            journal,
+            records_on_disk: 1,
+        })
+    }
+
+    /// Whether a message is already in a transcript.
+    pub fn contains(&self, message_id: MessageId) -> bool {
+        self.ids.contains(&message_id)
+    }
+
+    /// Record messages that were written to a transcript.
+    pub fn add(&mut self, message_ids: impl IntoIterator<Item = MessageId>) -> Result<()> {
+        let message_ids: Vec<_> = message_ids
+            .into_iter()
+            .filter(|id| !self.ids.contains(id))
+            .collect();
+        if message_ids.is_empty() {
+            return Ok(());
+        }
+
+        self.append(&JournalRecord::Add {
+            message_ids: message_ids.clone(),
+        })?;
+        self.ids.extend(message_ids);
+        self.maybe_compact()
+    }
+
+    /// Forget messages that were deleted from Discord.
+    pub fn remove(&mut self, message_ids: impl IntoIterator<Item = MessageId>) -> Result<()> {
+        let message_ids: Vec<_> = message_ids
+            .into_iter()
+            .filter(|id| self.ids.contains(id))
+            .collect();
+        if message_ids.is_empty() {
+            return Ok(());
+        }
+
+        self.append(&JournalRecord::Remove {
+            message_ids: message_ids.clone(),
+        })?;
+        for id in &message_ids {
+            self.ids.remove(id);
+        }
+        self.maybe_compact()
+    }
+
+    /// Append a record and wait until it is on disk.
+    fn append(&mut self, record: &JournalRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        self.journal
+            .write_all(line.as_bytes())
+            .context("Failed to write transcribed messages record")?;
+        self.journal
+            .sync_data()
+            .context("Failed to sync transcribed messages")?;
+        self.records_on_disk += 1;
+
+        Ok(())
+    }
+
+    fn maybe_compact(&mut self) -> Result<()> {
+        if self.records_on_disk < MIN_COMPACTION_RECORDS {
+            return Ok(());
+        }
+
+        self.journal = write_snapshot(&self.path, &self.ids)?;
+        self.records_on_disk = 1;
+        Ok(())
+    }
+}
+
+fn replay_journal(content: &str) -> HashSet<MessageId> {
+    let mut ids = HashSet::new();
+
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(JournalRecord::Add { message_ids }) => ids.extend(message_ids),
+            Ok(JournalRecord::Remove { message_ids }) => {
+                for id in &message_ids {
+                    ids.remove(id);
+                }
+            }
+            // A crash mid-write can leave a truncated last line
+            Err(e) => warn!("Skipping unreadable transcribed messages record: {e}"),
+        }
+    }
+
+    ids
+}
+
+/// Atomically replace the journal with a single record holding every message, and open it for
+/// appending.
+fn write_snapshot(path: &Path, ids: &HashSet<MessageId>) -> Result<File> {
+    let mut content = serde_json::to_string(&JournalRecord::Add {
+        message_ids: ids.iter().copied().collect(),
+    })?;
+    content.push('\n');
+
+    let temp_path = path.with_extension("jsonl.tmp");
+    let mut temp =
+        File::create(&temp_path).context("Failed to create temp transcribed messages file")?;
+    temp.write_all(content.as_bytes())
+        .context("Failed to write temp transcribed messages file")?;
+    temp.sync_all()
+        .context("Failed to sync temp transcribed messages file")?;
+    fs::rename(&temp_path, path).context("Failed to rename transcribed messages file")?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)
+            .and_then(|d| d.sync_all())
+            .context("Failed to sync transcribed messages directory")?;
+    }
+
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .context(format!("Failed to open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("transcribed-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("transcribed_messages.jsonl")
+    }
+
+    fn ids(ids: &[u64]) -> Vec<MessageId> {
+        ids.iter().copied().map(MessageId::new).collect()
+    }
+
+    #[test]
+    fn partially_deleted_batch_is_not_transcribed_again() {
+        let path = temp_path("partial");
+        let mut transcribed = TranscribedMessages::load_from(&path).unwrap();
+
+        // A run archives three messages, but only the first delete succeeds
+        transcribed.add(ids(&[1, 2, 3])).unwrap();
+        transcribed.remove(ids(&[1])).unwrap();
+        drop(transcribed);
+
+        // The next run finds the two left in Discord next to a new one
+        let transcribed = TranscribedMessages::load_from(&path).unwrap();
+        let pending: Vec<_> = ids(&[2, 3, 4])
+            .into_iter()
+            .filter(|id| !transcribed.contains(*id))
+            .collect();
+        assert_eq!(pending, ids(&[4]));
+        assert_eq!(transcribed.ids, ids(&[2, 3]).into_iter().collect());
+    }
+
+    #[test]
+    fn torn_record_is_ignored() {
+        let path = temp_path("torn");
+        let mut transcribed = TranscribedMessages::load_from(&path).unwrap();
+        transcribed.add(ids(&[1])).unwrap();
+        drop(transcribed);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"op":"Add","message_ids":[2"#).unwrap();
+        drop(file);
+
+        let mut transcribed = TranscribedMessages::load_from(&path).unwrap();
+        assert_eq!(transcribed.ids, ids(&[1]).into_iter().collect());
+
+        // Records appended after the torn one are read back
+        transcribed.add(ids(&[3])).unwrap();
+        drop(transcribed);
+        let transcribed = TranscribedMessages::load_from(&path).unwrap();
+        assert_eq!(transcribed.ids, ids(&[1, 3]).into_iter().collect());
+    }
+}
@@ -1,33 +1,19 @@
-use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use serenity::all::Http;
 use tokio::time::{MissedTickBehavior, interval};
 use tracing::{debug, info};
 
-use crate::backup::BackupQueue;
-use crate::cancellation::CancellationRegistry;
-use crate::cleanup::task::cleanup_channel;
-use crate::config::ConfigStore;
+use crate::cleanup::task::{CleanupContext, cleanup_channel};
 
 /// Spawn the cleanup scheduler task.
-pub fn spawn_worker(
-    http: Arc<Http>,
-    config: ConfigStore,
-    backup_queue: Arc<Mutex<BackupQueue>>,
-    cancellation: Arc<Mutex<CancellationRegistry>>,
-) -> tokio::task::JoinHandle<()> {
+pub fn spawn_worker(ctx: CleanupContext) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        run_worker(http, config, backup_queue, cancellation).await;
+        run_worker(ctx).await;
     })
 }
 
-async fn run_worker(
-    http: Arc<Http>,
-    config: ConfigStore,
-    backup_queue: Arc<Mutex<BackupQueue>>,
-    cancellation: Arc<Mutex<CancellationRegistry>>,
-) {
+async fn run_worker(ctx: CleanupContext) {
+    let config = ctx.config.clone();
     let scheduler_interval = Duration::from_secs(config.schedule_interval_seconds().get() as u64);
     let mut interval = interval(scheduler_interval);
     interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
@@ -54,15 +40,12 @@ async fn run_worker(
         );
 
         // Spawn independent cleanup tasks for each channel
-        for (channel_id, retention_days) in channels {
-            let http = Arc::clone(&http);
-            let config = config.clone();
-            let backup_queue = Arc::clone(&backup_queue);
-            let cancellation_registry = Arc::clone(&cancellation);
+        for (channel_id, policy) in channels {
+            let ctx = ctx.clone();
 
             // Check and register atomically to prevent race condition
             let cancel_token = {
-                let mut registry = cancellation_registry.lock().unwrap();
+                let mut registry = ctx.cancellation.lock().unwrap();
                 if registry.is_running(channel_id) {
                     debug!(
                         "Cleanup already running for channel {}, skipping",
@@ -74,21 +57,12 @@ async fn run_worker(
             };
 
             debug!(
-                "Spawning cleanup task for channel {} (retention: {} days)",
-                channel_id, retention_days
+                "Spawning cleanup task for channel {} (retention: {})",
+                channel_id, policy
             );
 
             tokio::spawn(async move {
-                cleanup_channel(
-                    http,
-                    config,
-                    backup_queue,
-                    cancellation_registry,
-                    channel_id,
-                    retention_days,
-                    cancel_token,
-                )
-                .await;
+                cleanup_channel(ctx, channel_id, policy, cancel_token).await;
             });
         }
     }
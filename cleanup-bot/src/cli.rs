@@ -0,0 +1,198 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use indoc::indoc;
+use serenity::all::{ChannelId, Http};
+
+use crate::config::Config;
+use crate::crypto::{EncryptionKey, KEY_ENV, is_encrypted};
+use crate::destination::Destinations;
+use crate::restore::{RestoreQuery, Restorer};
+
+const USAGE: &str = indoc! {"
+    Usage:
+      cleanup-bot                         Run the bot
+      cleanup-bot generate-key            Print a new encryption key
+      cleanup-bot decrypt <path>...       Decrypt .enc files, directories are searched recursively
+      cleanup-bot restore <options>       Restore backed-up messages from a backup destination
+
+    Restore options:
+      --message <id>                      A single message
+      --from <date> [--until <date>]      Messages posted on these days (YYYY-MM-DD, UTC)
+      --channel <id>                      Only messages from this channel
+      --source <destination>              Destination to restore from, defaults to the first available
+      --output <dir>                      Save the files and a description of each message to a directory
+      --target <channel id>               Or repost the messages to a channel, using the bot's token
+"};
+
+/// Run an offline tool instead of the bot. These print to the terminal rather than the log,
+/// so they are usable on a machine holding downloaded backups.
+pub async fn run(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("generate-key") => {
+            println!("{}", EncryptionKey::generate());
+            Ok(())
+        }
+        Some("decrypt") if args.len() > 1 => decrypt(&args[1..]).await,
+        Some("restore") => restore(&args[1..]).await,
+        _ => bail!("{USAGE}"),
+    }
+}
+
+/// Decrypt backed-up files next to the originals, dropping the `.enc` extension.
+async fn decrypt(paths: &[String]) -> Result<()> {
+    let key = EncryptionKey::load()?
+        .with_context(|| format!("Set {KEY_ENV} to the key the backups were encrypted with"))?;
+
+    let mut files = Vec::new();
+    for path in paths {
+        collect_encrypted(Path::new(path), &mut files)
+            .with_context(|| format!("Failed to read {path}"))?;
+    }
+
+    let mut decrypted = 0;
+    let mut failed = 0;
+
+    for file in files {
+        let output = file.with_extension("");
+        if output.exists() {
+            println!(
+                "Skipping {}, {} already exists",
+                file.display(),
+                output.display()
+            );
+            continue;
+        }
+
+        match key.decrypt_file(&file, &output).await {
+            Ok(()) => {
+                println!("Decrypted {}", output.display());
+                decrypted += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to decrypt {}: {e:#}", file.display());
+                failed += 1;
+            }
+        }
+    }
+
+    println!("Decrypted {decrypted} files");
+    if failed > 0 {
+        bail!("{failed} files could not be decrypted");
+    }
+    Ok(())
+}
+
+/// Where restored messages go.
+enum RestoreTarget {
+    Directory(PathBuf),
+    Channel(Http, ChannelId),
+}
+
+/// Restore backed-up messages from a destination, to a directory or a Discord channel.
+async fn restore(args: &[String]) -> Result<()> {
+    let mut options = std::collections::HashMap::new();
+    let mut args = args.iter();
+    while let Some(name) = args.next() {
+        let Some(name) = name.strip_prefix("--") else {
+            bail!("Unexpected argument {name}\n\n{USAGE}");
+        };
+        let value = args
+            .next()
+            .with_context(|| format!("--{name} needs a value\n\n{USAGE}"))?;
+        options.insert(name, value.as_str());
+    }
+
+    let channel_id = |name: &str| {
+        options
+            .get(name)
+            .map(|id| {
+                id.parse::<u64>()
+                    .ok()
+                    .filter(|&id| id != 0)
+                    .map(ChannelId::new)
+                    .with_context(|| format!("--{name} isn't a channel ID"))
+            })
+            .transpose()
+    };
+
+    let query = RestoreQuery::parse(
+        options.get("message").copied(),
+        options.get("from").copied(),
+        options.get("until").copied(),
+        channel_id("channel")?,
+    )?;
+
+    let target = match (options.get("output"), channel_id("target")?) {
+        (Some(dir), None) => RestoreTarget::Directory(PathBuf::from(dir)),
+        (None, Some(channel_id)) => {
+            let bot_config = shared::load_bot_config!()?;
+            RestoreTarget::Channel(Http::new(&bot_config.discord_token), channel_id)
+        }
+        _ => bail!("Give either --output or --target\n\n{USAGE}"),
+    };
+
+    let config = Config::load()?;
+    let key = EncryptionKey::load()?;
+    let destinations = Destinations::from_config(&config, key.clone()).await;
+    let source = destinations
+        .restore_source(options.get("source").copied())
+        .await?;
+
+    let restorer = Restorer::new(source.as_ref(), key.as_ref());
+    let messages = match restorer.find(&query).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            restorer.finish().await;
+            return Err(e);
+        }
+    };
+    println!("Found {} messages in {}", messages.len(), source.name());
+
+    let mut restored = 0;
+    let mut failed = 0;
+
+    for message in &messages {
+        let result = match &target {
+            RestoreTarget::Directory(dir) => restorer.save(message, dir).await,
+            RestoreTarget::Channel(http, channel_id) => {
+                restorer.repost(http, *channel_id, message).await
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                println!("Restored message {} from {}", message.id, message.timestamp);
+                restored += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to restore message {}: {e:#}", message.id);
+                failed += 1;
+            }
+        }
+    }
+    restorer.finish().await;
+
+    println!("Restored {restored} messages");
+    if failed > 0 {
+        bail!("{failed} messages could not be restored");
+    }
+    Ok(())
+}
+
+fn collect_encrypted(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_encrypted(&path, files)?;
+        } else if is_encrypted(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
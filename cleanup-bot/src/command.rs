@@ -2,50 +2,294 @@ use std::num::NonZeroU32;
 use std::sync::{Arc, Mutex};
 
 use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
 use indoc::formatdoc;
-use serenity::all::Mentionable;
+use poise::CreateReply;
+use serenity::all::{ChannelId, CreateMessage, Mentionable, MessageId, Permissions};
+use tracing::{error, info, warn};
 
+use crate::audit::{AuditAction, AuditRecord};
+use crate::backup::{BackupQueue, BackupStatus, PendingBackup};
 use crate::cancellation::CancellationRegistry;
-use crate::config::{ChannelConfig, ConfigStore};
+use crate::cleanup::report::format_bytes;
+use crate::cleanup::run_log::{RunLog, RunRecord};
+use crate::cleanup::task::preview_cleanup;
+use crate::config::{ArchiveFormat, ChannelConfig, ConfigStore, ExemptionRules, RetentionPolicy};
+use crate::crypto::EncryptionKey;
+use crate::destination::Destinations;
+use crate::restore::{RestoreQuery, Restorer};
 
 pub struct CommandData {
     pub config: ConfigStore,
     pub cancellation: Arc<Mutex<CancellationRegistry>>,
+    pub run_log: Arc<Mutex<RunLog>>,
+    pub backup_queue: Arc<Mutex<BackupQueue>>,
+    pub destinations: Destinations,
+    /// Key for decrypting restored backups, set whether or not new backups are encrypted
+    pub encryption: Option<EncryptionKey>,
 }
 
+// Discord rejects messages longer than 2000 characters
+const MAX_MESSAGE_LENGTH: usize = 2000;
+const DEFAULT_HISTORY_LENGTH: usize = 10;
+// Discord shows at most 25 autocomplete choices
+const MAX_AUTOCOMPLETE_CHOICES: usize = 25;
+
 type Context<'a> = poise::Context<'a, CommandData, Error>;
 
-#[poise::command(slash_command, subcommands("enable", "disable"))]
+/// Allow members with Manage Messages, or any of the guild's configured admin roles.
+async fn is_cleanup_admin(ctx: Context<'_>) -> Result<bool> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(false);
+    };
+
+    let allowed = match ctx.author_member().await {
+        Some(member) => {
+            let has_permission = member
+                .permissions
+                .is_some_and(|p| p.contains(Permissions::MANAGE_MESSAGES));
+            let admin_roles = ctx.data().config.admin_roles(guild_id);
+            has_permission || member.roles.iter().any(|r| admin_roles.contains(r))
+        }
+        None => false,
+    };
+
+    if !allowed {
+        warn!(
+            "Denied /{} for {} ({})",
+            ctx.command().qualified_name,
+            ctx.author().name,
+            ctx.author().id
+        );
+        ctx.send(
+            CreateReply::default()
+                .content("You need the **Manage Messages** permission or a cleanup admin role to use this command.")
+                .ephemeral(true),
+        )
+        .await?;
+    }
+
+    Ok(allowed)
+}
+
+/// Allow members with Administrator or Manage Server. Admin roles aren't enough, since signing
+/// in decides which account every backup of every guild goes to.
+async fn is_server_admin(ctx: Context<'_>) -> Result<bool> {
+    let allowed = ctx.author_member().await.is_some_and(|member| {
+        member.permissions.is_some_and(|p| {
+            p.contains(Permissions::ADMINISTRATOR) || p.contains(Permissions::MANAGE_GUILD)
+        })
+    });
+
+    if !allowed {
+        warn!(
+            "Denied /{} for {} ({})",
+            ctx.command().qualified_name,
+            ctx.author().name,
+            ctx.author().id
+        );
+        ctx.send(
+            CreateReply::default()
+                .content("You need the **Administrator** or **Manage Server** permission to use this command.")
+                .ephemeral(true),
+        )
+        .await?;
+    }
+
+    Ok(allowed)
+}
+
+/// Record a policy change. Failing to write the audit log doesn't undo the change.
+fn audit(ctx: Context<'_>, channel_name: String, action: AuditAction) {
+    let record = AuditRecord {
+        timestamp: Utc::now(),
+        guild_id: ctx.guild_id(),
+        channel_id: ctx.channel_id(),
+        channel_name,
+        user_id: ctx.author().id,
+        user_name: ctx.author().name.clone(),
+        action,
+    };
+
+    if let Err(e) = record.append() {
+        error!("Failed to write audit record: {e:?}");
+    }
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    check = "is_cleanup_admin",
+    subcommands("enable", "disable", "preview", "status", "history")
+)]
 pub async fn cleanup(_ctx: Context<'_>) -> Result<()> {
     Ok(())
 }
 
+/// Build a retention policy from command options. `Ok(None)` means use the default policy.
+fn build_policy(
+    default_policy_days: NonZeroU32,
+    policy_days: Option<NonZeroU32>,
+    max_count: Option<NonZeroU32>,
+    min_keep: Option<u32>,
+) -> Result<Option<RetentionPolicy>, &'static str> {
+    let policy = match (policy_days, max_count, min_keep) {
+        (None, None, None) => return Ok(None),
+        (days, None, min_keep) => RetentionPolicy::Age {
+            max_age_days: days.unwrap_or(default_policy_days),
+            min_keep: min_keep.unwrap_or(0),
+        },
+        (None, Some(max_count), None) => RetentionPolicy::Count { max_count },
+        (None, Some(_), Some(_)) => {
+            return Err("`min_keep` only applies together with `policy_days`");
+        }
+        (Some(max_age_days), Some(max_count), min_keep) => {
+            let min_keep = min_keep.unwrap_or(0);
+            if min_keep >= max_count.get() {
+                return Err("`min_keep` must be less than `max_count`");
+            }
+            RetentionPolicy::AgeOrCount {
+                max_age_days,
+                max_count,
+                min_keep,
+            }
+        }
+    };
+
+    Ok(Some(policy))
+}
+
 #[poise::command(slash_command)]
 pub async fn enable(
     ctx: Context<'_>,
     #[description = "How many days should messages be retained"]
     #[min = 1]
     policy_days: Option<NonZeroU32>,
+    #[description = "Keep at most this many of the newest messages"]
+    #[min = 1]
+    max_count: Option<NonZeroU32>,
+    #[description = "Never delete the newest this many messages"] min_keep: Option<u32>,
+    #[description = "Only report what would be deleted"] dry_run: Option<bool>,
+    #[description = "Archive expired messages to a transcript before deleting them"]
+    archive: Option<ArchiveFormat>,
 ) -> Result<()> {
-    let channel_config = ChannelConfig {
-        name: ctx.channel_id().name(&ctx.http()).await?,
+    let dry_run = dry_run.unwrap_or(false);
+
+    let policy = match build_policy(
+        ctx.data().config.default_policy_days(),
         policy_days,
+        max_count,
+        min_keep,
+    ) {
+        Ok(policy) => policy,
+        Err(reason) => {
+            ctx.send(CreateReply::default().content(reason).ephemeral(true))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let channel_name = ctx.channel_id().name(&ctx.http()).await?;
+    let channel_config = ChannelConfig {
+        name: channel_name.clone(),
+        policy,
+        exemptions: ExemptionRules::default(),
+        dry_run,
+        archive,
+        attachments: None,
         pagination_cursor: None,
     };
 
-    let policy_days = ctx
+    let policy = ctx
         .data()
         .config
         .add_channel(ctx.channel_id(), channel_config)?;
 
-    ctx.say(formatdoc! {"
+    audit(
+        ctx,
+        channel_name,
+        AuditAction::Enable {
+            policy,
+            dry_run,
+            archive,
+        },
+    );
+
+    let mut message = formatdoc! {"
         Enabled cleanup for {channel}
-        Retention policy: **{policy_days} {day_suffix}**
+        Retention policy: **{policy}**
         ",
         channel = ctx.channel_id().mention(),
-        day_suffix = if policy_days.get() == 1 {"day"}  else {"days"}
-    })
-    .await?;
+    };
+
+    if let Some(format) = archive {
+        message.push_str(&format!(
+            "Expired messages are archived as **{format}** before deletion\n"
+        ));
+    }
+
+    if dry_run {
+        message.push_str(
+            "_Dry run: nothing will be deleted, see `/cleanup history` for what each run would \
+             delete._\n",
+        );
+    }
+
+    ctx.say(message).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command)]
+pub async fn preview(
+    ctx: Context<'_>,
+    #[description = "Retention in days to preview (defaults to the channel's policy)"]
+    #[min = 1]
+    policy_days: Option<NonZeroU32>,
+) -> Result<()> {
+    // Scanning a long history takes a while
+    ctx.defer_ephemeral().await?;
+
+    let policy = match policy_days {
+        Some(days) => RetentionPolicy::from_days(days),
+        None => ctx.data().config.resolve_channel_policy(ctx.channel_id()),
+    };
+
+    let (report, complete) =
+        preview_cleanup(ctx.http(), &ctx.data().config, ctx.channel_id(), policy).await?;
+
+    let timestamp = |t: Option<DateTime<Utc>>| {
+        t.map(|t| format!("<t:{}:f>", t.timestamp()))
+            .unwrap_or_else(|| "n/a".to_string())
+    };
+
+    let mut message = formatdoc! {"
+        Cleanup preview for {channel} (retention: **{policy}**)
+        Would delete **{affected}** messages:
+        - Text messages: {text}
+        - Media messages: {media} ({bytes})
+        Kept by exemptions: {exempted}
+        Oldest affected: {oldest}
+        Newest affected: {newest}
+        Scanned {scanned} messages
+        ",
+        channel = ctx.channel_id().mention(),
+        affected = report.affected(),
+        text = report.text_messages,
+        media = report.media_messages,
+        bytes = format_bytes(report.attachment_bytes),
+        exempted = report.exempted,
+        oldest = timestamp(report.oldest),
+        newest = timestamp(report.newest),
+        scanned = report.scanned,
+    };
+
+    if !complete {
+        message
+            .push_str("_Stopped before the start of the channel, older history not included._\n");
+    }
+
+    ctx.send(CreateReply::default().content(message).ephemeral(true))
+        .await?;
     Ok(())
 }
 
@@ -53,6 +297,13 @@ pub async fn enable(
 pub async fn disable(ctx: Context<'_>) -> Result<()> {
     ctx.data().config.remove_channel(ctx.channel_id())?;
 
+    let channel_name = ctx
+        .channel_id()
+        .name(&ctx.http())
+        .await
+        .unwrap_or_else(|_| "unknown channel".to_string());
+    audit(ctx, channel_name, AuditAction::Disable);
+
     // Cancel any running cleanup task for the channel
     let was_running = ctx
         .data()
@@ -73,3 +324,669 @@ pub async fn disable(ctx: Context<'_>) -> Result<()> {
     ctx.say(message).await?;
     Ok(())
 }
+
+#[poise::command(slash_command)]
+pub async fn status(ctx: Context<'_>) -> Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let guild_channels = guild_id.channels(ctx.http()).await?;
+    let mut channels: Vec<_> = ctx
+        .data()
+        .config
+        .enabled_channels()
+        .into_iter()
+        .filter(|(channel_id, _)| guild_channels.contains_key(channel_id))
+        .collect();
+    channels.sort_by_key(|(channel_id, _)| *channel_id);
+
+    if channels.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content("Cleanup isn't enabled in any channel.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut message = String::from("**Cleanup status**\n");
+
+    for (channel_id, policy) in channels {
+        let running = ctx
+            .data()
+            .cancellation
+            .lock()
+            .unwrap()
+            .is_running(channel_id);
+        let dry_run = ctx.data().config.is_dry_run(channel_id);
+        let archive = ctx.data().config.archive_format(channel_id);
+
+        message.push_str(&format!(
+            "{channel}: **{policy}**{archive}{dry_run}{running}\n",
+            channel = channel_id.mention(),
+            archive = archive
+                .map(|f| format!(" (archived as {f})"))
+                .unwrap_or_default(),
+            dry_run = if dry_run { " (dry run)" } else { "" },
+            running = if running { " · _running_" } else { "" },
+        ));
+
+        let cursor = match ctx.data().config.get_pagination_cursor(channel_id) {
+            Some(cursor) => format!(
+                "scanned back to <t:{}:d>",
+                MessageId::new(cursor).created_at().unix_timestamp()
+            ),
+            None => "starting from newest expired messages".to_string(),
+        };
+        message.push_str(&format!("- Cursor: {cursor}\n"));
+
+        let last_run = ctx
+            .data()
+            .run_log
+            .lock()
+            .unwrap()
+            .last(channel_id)
+            .map(format_run)
+            .unwrap_or_else(|| "never".to_string());
+        message.push_str(&format!("- Last run: {last_run}\n"));
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(truncate_message(message))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "How many runs to show"]
+    #[min = 1]
+    #[max = 25]
+    count: Option<usize>,
+) -> Result<()> {
+    let count = count.unwrap_or(DEFAULT_HISTORY_LENGTH);
+
+    let runs: Vec<String> = ctx
+        .data()
+        .run_log
+        .lock()
+        .unwrap()
+        .recent(ctx.channel_id(), count)
+        .into_iter()
+        .map(|r| format!("- {}", format_run(r)))
+        .collect();
+
+    let message = if runs.is_empty() {
+        format!(
+            "No cleanup runs recorded for {channel}",
+            channel = ctx.channel_id().mention()
+        )
+    } else {
+        format!(
+            "Last {n} cleanup runs for {channel}:\n{runs}",
+            n = runs.len(),
+            channel = ctx.channel_id().mention(),
+            runs = runs.join("\n"),
+        )
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .content(truncate_message(message))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    check = "is_server_admin",
+    subcommands("auth")
+)]
+pub async fn onedrive(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Sign in to OneDrive again. The sign-in code is sent by DM.
+#[poise::command(slash_command)]
+pub async fn auth(ctx: Context<'_>) -> Result<()> {
+    let Some(token_store) = ctx.data().destinations.onedrive_tokens.clone() else {
+        ctx.send(
+            CreateReply::default()
+                .content("OneDrive backups aren't configured.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    ctx.defer_ephemeral().await?;
+
+    let flow = token_store.lock().await.device_code_flow();
+    let code = flow.start().await?;
+
+    let expires_at = Utc::now() + code.expires_in;
+    let instructions = formatdoc! {"
+        To connect OneDrive for cleanup backups, visit {uri} and enter the code **{user_code}**
+        The code expires <t:{expires}:R>.
+        ",
+        uri = code.verification_uri,
+        user_code = code.user_code,
+        expires = expires_at.timestamp(),
+    };
+
+    if let Err(e) = ctx
+        .author()
+        .direct_message(ctx.http(), CreateMessage::new().content(instructions))
+        .await
+    {
+        warn!("Failed to DM OneDrive sign-in code: {e:?}");
+        ctx.send(
+            CreateReply::default()
+                .content(
+                    "I couldn't DM you. Allow direct messages from server members and try again.",
+                )
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content("Sent you a DM with the sign-in code.")
+            .ephemeral(true),
+    )
+    .await?;
+
+    info!(
+        "{} ({}) started OneDrive sign-in",
+        ctx.author().name,
+        ctx.author().id
+    );
+
+    // Waiting for the user can take minutes, don't hold up the command
+    let http = Arc::clone(&ctx.serenity_context().http);
+    let user = ctx.author().clone();
+    tokio::spawn(async move {
+        let result = match flow.wait_for_tokens(&code).await {
+            Ok(tokens) => token_store.lock().await.set_tokens(tokens),
+            Err(e) => Err(e),
+        };
+
+        let message = match result {
+            Ok(()) => "OneDrive connected, backups will resume shortly.".to_string(),
+            Err(e) => {
+                error!("OneDrive sign-in failed: {e:?}");
+                format!("OneDrive sign-in failed: {e}")
+            }
+        };
+
+        if let Err(e) = user
+            .direct_message(&http, CreateMessage::new().content(message))
+            .await
+        {
+            warn!("Failed to DM OneDrive sign-in result: {e:?}");
+        }
+    });
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    check = "is_cleanup_admin",
+    subcommands("backup_status", "retry", "retry_all", "discard", "restore")
+)]
+pub async fn backup(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Backups from this guild's channels, the queue itself is shared by all guilds.
+async fn guild_backups(ctx: Context<'_>) -> Result<Vec<PendingBackup>> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(Vec::new());
+    };
+
+    let guild_channels = guild_id.channels(ctx.http()).await?;
+    let mut backups: Vec<_> = ctx
+        .data()
+        .backup_queue
+        .lock()
+        .unwrap()
+        .entries()
+        .filter(|b| guild_channels.contains_key(&ChannelId::new(b.channel_id)))
+        .cloned()
+        .collect();
+    backups.sort_by_key(|b| b.timestamp);
+    Ok(backups)
+}
+
+/// Name a backup is referred to by in commands.
+fn backup_name(backup: &PendingBackup) -> String {
+    backup
+        .local_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| backup.original_filename.clone())
+}
+
+/// Suggest failed and dead-lettered backups.
+async fn autocomplete_failed_backup(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    // Checks don't run for autocomplete, and names show message IDs and attachment names
+    if !is_cleanup_admin(ctx).await.unwrap_or(false) {
+        return Vec::new();
+    }
+
+    let Ok(backups) = guild_backups(ctx).await else {
+        return Vec::new();
+    };
+
+    backups
+        .iter()
+        .filter(|b| {
+            matches!(
+                b.status,
+                BackupStatus::Failed { .. } | BackupStatus::DeadLetter { .. }
+            )
+        })
+        .map(backup_name)
+        .filter(|name| name.contains(partial))
+        .take(MAX_AUTOCOMPLETE_CHOICES)
+        .collect()
+}
+
+/// Show queued backups and why failed ones failed.
+#[poise::command(slash_command, rename = "status")]
+pub async fn backup_status(ctx: Context<'_>) -> Result<()> {
+    let backups = guild_backups(ctx).await?;
+
+    let count = |f: fn(&BackupStatus) -> bool| backups.iter().filter(|b| f(&b.status)).count();
+    let mut message = formatdoc! {"
+        **Backup queue**
+        Pending: {pending} · Uploading: {in_progress} · Waiting to retry: {failed} · Dead letter: {dead_letter}
+        ",
+        pending = count(|s| matches!(s, BackupStatus::Pending)),
+        in_progress = count(|s| matches!(s, BackupStatus::InProgress)),
+        failed = count(|s| matches!(s, BackupStatus::Failed { .. })),
+        dead_letter = count(|s| matches!(s, BackupStatus::DeadLetter { .. })),
+    };
+
+    for backup in &backups {
+        let line = match &backup.status {
+            BackupStatus::Failed { error } => format!(
+                "- `{name}`: attempt {attempts} failed, retrying {retry}: {error}\n",
+                name = backup_name(backup),
+                attempts = backup.retry_count,
+                retry = backup
+                    .next_attempt_at
+                    .map(|t| format!("<t:{}:R>", t.timestamp()))
+                    .unwrap_or_else(|| "soon".to_string()),
+            ),
+            BackupStatus::DeadLetter { error } => format!(
+                "- `{name}`: **gave up** after {attempts} attempts: {error}\n",
+                name = backup_name(backup),
+                attempts = backup.retry_count,
+            ),
+            BackupStatus::Pending | BackupStatus::InProgress => continue,
+        };
+        message.push_str(&line);
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(truncate_message(message))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Retry a failed or dead-lettered backup now.
+#[poise::command(slash_command)]
+pub async fn retry(
+    ctx: Context<'_>,
+    #[description = "File to retry"]
+    #[autocomplete = "autocomplete_failed_backup"]
+    file: String,
+) -> Result<()> {
+    let paths: Vec<_> = guild_backups(ctx)
+        .await?
+        .into_iter()
+        .filter(|b| backup_name(b) == file)
+        .map(|b| b.local_path)
+        .collect();
+
+    let retried = ctx
+        .data()
+        .backup_queue
+        .lock()
+        .unwrap()
+        .retry_where(|b| paths.contains(&b.local_path))?;
+
+    let message = if retried > 0 {
+        info!(
+            "{} ({}) retried backup {file}",
+            ctx.author().name,
+            ctx.author().id
+        );
+        format!("`{file}` will be uploaded again shortly.")
+    } else {
+        format!("`{file}` isn't a failed backup.")
+    };
+
+    ctx.send(CreateReply::default().content(message).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Retry all failed and dead-lettered backups now.
+#[poise::command(slash_command, rename = "retry-all")]
+pub async fn retry_all(ctx: Context<'_>) -> Result<()> {
+    let paths: Vec<_> = guild_backups(ctx)
+        .await?
+        .into_iter()
+        .map(|b| b.local_path)
+        .collect();
+
+    let retried = ctx
+        .data()
+        .backup_queue
+        .lock()
+        .unwrap()
+        .retry_where(|b| paths.contains(&b.local_path))?;
+
+    info!(
+        "{} ({}) retried {retried} backups",
+        ctx.author().name,
+        ctx.author().id
+    );
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("{retried} backups will be uploaded again shortly."))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Give up on a failed backup, deleting its local copy.
+#[poise::command(slash_command)]
+pub async fn discard(
+    ctx: Context<'_>,
+    #[description = "File to discard"]
+    #[autocomplete = "autocomplete_failed_backup"]
+    file: String,
+) -> Result<()> {
+    let backup = guild_backups(ctx).await?.into_iter().find(|b| {
+        backup_name(b) == file
+            && matches!(
+                b.status,
+                BackupStatus::Failed { .. } | BackupStatus::DeadLetter { .. }
+            )
+    });
+
+    let Some(backup) = backup else {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("`{file}` isn't a failed backup."))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    ctx.data()
+        .backup_queue
+        .lock()
+        .unwrap()
+        .remove(&backup.local_path)?;
+
+    match tokio::fs::remove_file(&backup.local_path).await {
+        Ok(()) => {
+            if let Some(parent) = backup.local_path.parent() {
+                let _ = tokio::fs::remove_dir(parent).await;
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!(
+            "Failed to delete discarded backup {}: {e:?}",
+            backup.local_path.display()
+        ),
+    }
+
+    info!(
+        "{} ({}) discarded backup {}",
+        ctx.author().name,
+        ctx.author().id,
+        backup.local_path.display()
+    );
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Discarded `{file}`."))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Suggest configured backup destinations.
+async fn autocomplete_destination(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    ctx.data()
+        .destinations
+        .names()
+        .into_iter()
+        .filter(|name| name.contains(partial))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Repost backed-up messages and their files, attributed to their original authors.
+#[poise::command(slash_command)]
+pub async fn restore(
+    ctx: Context<'_>,
+    #[description = "ID of a single message to restore"] message_id: Option<String>,
+    #[description = "First day to restore (YYYY-MM-DD, UTC)"] from: Option<String>,
+    #[description = "Last day to restore (defaults to the first day)"] until: Option<String>,
+    #[description = "Only restore messages from this channel"] channel: Option<ChannelId>,
+    #[description = "Channel to post to (defaults to this channel)"] target: Option<ChannelId>,
+    #[description = "Backup destination to restore from (defaults to the first available)"]
+    #[autocomplete = "autocomplete_destination"]
+    source: Option<String>,
+) -> Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    // Downloading and reposting takes a while
+    ctx.defer_ephemeral().await?;
+
+    let reply = |message: String| ctx.send(CreateReply::default().content(message).ephemeral(true));
+
+    let query = match RestoreQuery::parse(
+        message_id.as_deref(),
+        from.as_deref(),
+        until.as_deref(),
+        channel,
+    ) {
+        Ok(query) => query,
+        Err(e) => {
+            reply(format!("{e}.")).await?;
+            return Ok(());
+        }
+    };
+
+    let source = match ctx
+        .data()
+        .destinations
+        .restore_source(source.as_deref())
+        .await
+    {
+        Ok(source) => source,
+        Err(e) => {
+            reply(format!("{e}.")).await?;
+            return Ok(());
+        }
+    };
+
+    let target = target.unwrap_or(ctx.channel_id());
+    let guild_channels = guild_id.channels(ctx.http()).await?;
+    if !guild_channels.contains_key(&target) {
+        reply("Restored messages can only be posted in this server.".to_string()).await?;
+        return Ok(());
+    }
+
+    // Restoring must not reveal messages from channels the invoker can't read
+    let guild = guild_id.to_partial_guild(ctx.http()).await?;
+    let Some(member) = ctx.author_member().await else {
+        reply("Couldn't look up your permissions, try again.".to_string()).await?;
+        return Ok(());
+    };
+    let can_view = |channel_id: &ChannelId| {
+        guild_channels.get(channel_id).is_some_and(|channel| {
+            guild
+                .user_permissions_in(channel, &member)
+                .contains(Permissions::VIEW_CHANNEL)
+        })
+    };
+    if !can_view(&target) {
+        reply(format!("You can't view {}.", target.mention())).await?;
+        return Ok(());
+    }
+    if let Some(channel) = channel.filter(|c| !can_view(c)) {
+        reply(format!("You can't view {}.", channel.mention())).await?;
+        return Ok(());
+    }
+
+    let restorer = Restorer::new(source.as_ref(), ctx.data().encryption.as_ref());
+    let found = restorer.find(&query).await;
+    let (messages, hidden): (Vec<_>, Vec<_>) = match found {
+        // Backups of all guilds share a destination
+        Ok(messages) => messages
+            .into_iter()
+            .filter(|m| guild_channels.contains_key(&m.channel_id))
+            .partition(|m| can_view(&m.channel_id)),
+        Err(e) => {
+            restorer.finish().await;
+            return Err(e);
+        }
+    };
+
+    let mut restored = 0;
+    let mut failed = 0;
+    for message in &messages {
+        match restorer.repost(ctx.http(), target, message).await {
+            Ok(()) => restored += 1,
+            Err(e) => {
+                warn!("Failed to restore message {}: {e:?}", message.id);
+                failed += 1;
+            }
+        }
+    }
+    restorer.finish().await;
+
+    info!(
+        "{} ({}) restored {restored} messages from {} to channel {target}",
+        ctx.author().name,
+        ctx.author().id,
+        source.name()
+    );
+
+    let mut message = if messages.is_empty() && hidden.is_empty() {
+        "No backed-up messages found.".to_string()
+    } else {
+        format!(
+            "Restored {restored} messages from {} to {}.",
+            source.name(),
+            target.mention()
+        )
+    };
+    if failed > 0 {
+        message.push_str(&format!(
+            " {failed} messages couldn't be restored, see the log."
+        ));
+    }
+    if !hidden.is_empty() {
+        message.push_str(&format!(
+            " {} messages were left out because they're from channels you can't view.",
+            hidden.len()
+        ));
+    }
+
+    reply(message).await?;
+    Ok(())
+}
+
+/// One-line summary of a cleanup run.
+fn format_run(run: &RunRecord) -> String {
+    let duration = (run.finished_at - run.started_at).num_seconds();
+
+    let mut line = if run.dry_run {
+        format!(
+            "<t:{started}:f> ({duration}s): scanned {scanned}, would delete {would_delete}, \
+             exempt {exempted}",
+            started = run.started_at.timestamp(),
+            scanned = run.scanned,
+            would_delete = run.would_delete,
+            exempted = run.exempted,
+        )
+    } else {
+        format!(
+            "<t:{started}:f> ({duration}s): scanned {scanned}, deleted {deleted}, failed {failed}, \
+             backups queued {backups}, exempt {exempted}",
+            started = run.started_at.timestamp(),
+            scanned = run.scanned,
+            deleted = run.deleted,
+            failed = run.failed,
+            backups = run.backups_queued,
+            exempted = run.exempted,
+        )
+    };
+
+    if run.archived > 0 {
+        line.push_str(&format!(
+            ", archived {} in {} transcripts",
+            run.archived, run.transcripts_written
+        ));
+    }
+    if run.skipped_media > 0 {
+        line.push_str(&format!(
+            ", media left in Discord (disk full) {}",
+            run.skipped_media
+        ));
+    }
+    if run.dry_run {
+        line.push_str(" · _dry run_");
+    }
+    if run.cancelled {
+        line.push_str(" · _cancelled_");
+    }
+    if let Some(error) = &run.error {
+        line.push_str(&format!(" · error: `{error}`"));
+    }
+
+    line
+}
+
+pub fn truncate_message(mut message: String) -> String {
+    if message.len() > MAX_MESSAGE_LENGTH {
+        let mut end = MAX_MESSAGE_LENGTH - 1;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+        message.push('…');
+    }
+    message
+}
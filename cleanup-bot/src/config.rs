@@ -1,14 +1,15 @@
 use std::{
     collections::HashMap,
-    fs,
-    num::NonZeroU32,
+    fmt, fs,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
     path::PathBuf,
     sync::{Arc, Mutex},
 };
 
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
-use serenity::all::ChannelId;
+use chrono::NaiveTime;
+use serde::{Deserialize, Deserializer, Serialize};
+use serenity::all::{ChannelId, GuildId, RoleId, UserId};
 
 const CONFIG_PATH: &str = "./config.toml";
 const CONFIG_TEMP_PATH: &str = "./config.toml.tmp";
@@ -17,41 +18,396 @@ fn default_upload_folder() -> String {
     "/discord-backups".to_string()
 }
 
+fn default_graph_api() -> String {
+    "https://graph.microsoft.com/v1.0".to_string()
+}
+
+fn default_auth_url() -> String {
+    "https://login.microsoftonline.com/consumers/oauth2/v2.0".to_string()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OneDriveConfig {
     pub client_id: String,
     #[serde(default = "default_upload_folder")]
     pub upload_folder: String,
+    /// Microsoft Graph endpoint, only changed to point at a stand-in server
+    #[serde(default = "default_graph_api")]
+    pub graph_api: String,
+    /// Microsoft identity platform endpoint for the device code flow and token refresh
+    #[serde(default = "default_auth_url")]
+    pub auth_url: String,
+}
+
+/// Upload backups to a WebDAV server such as Nextcloud.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebDavConfig {
+    /// Base URL, e.g. `https://cloud.example.com/remote.php/dav/files/alice`
+    pub url: String,
+    pub username: String,
+    /// Account password or app password
+    pub password: String,
+    #[serde(default = "default_upload_folder")]
+    pub upload_folder: String,
+    /// Nextcloud chunked upload endpoint, e.g.
+    /// `https://cloud.example.com/remote.php/dav/uploads/alice`. Derived from `url` for Nextcloud
+    /// file URLs; without it large files are uploaded in a single request.
+    #[serde(default)]
+    pub uploads_url: Option<String>,
+}
+
+/// Copy backups into a local directory, e.g. a mounted NAS share.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocalArchiveConfig {
+    pub dir: PathBuf,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_s3_prefix() -> String {
+    "discord-backups".to_string()
+}
+
+fn default_path_style() -> bool {
+    true
+}
+
+/// Upload backups to an S3-compatible object store.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://nas.local:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Key prefix that backups are stored under
+    #[serde(default = "default_s3_prefix")]
+    pub prefix: String,
+    /// Address the bucket as `endpoint/bucket` instead of `bucket.endpoint` (needed for MinIO)
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+}
+
+fn default_keep_pinned() -> bool {
+    true
+}
+
+/// Rules for keeping expired messages that would otherwise be cleaned up.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExemptionRules {
+    #[serde(default = "default_keep_pinned")]
+    pub keep_pinned: bool,
+    /// Keep messages from these users
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<UserId>,
+    /// Keep messages from members with any of these roles
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<RoleId>,
+    /// Keep messages with any of these reactions (unicode emoji or custom emoji name)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<String>,
+    /// Keep messages with at least this many reactions in total
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_reactions: Option<NonZeroU32>,
+    /// Keep messages containing any of these keywords (case-insensitive)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Keep messages matching any of these regular expressions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+}
+
+impl Default for ExemptionRules {
+    fn default() -> Self {
+        Self {
+            keep_pinned: default_keep_pinned(),
+            users: Vec::new(),
+            roles: Vec::new(),
+            reactions: Vec::new(),
+            min_reactions: None,
+            keywords: Vec::new(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+fn default_allowed_types() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+/// Which attachments are downloaded and backed up before a message is deleted.
+/// Types are MIME patterns such as `application/pdf`, `image/*` or `*`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttachmentRules {
+    /// Back up attachments matching any of these types
+    #[serde(default = "default_allowed_types")]
+    pub allow: Vec<String>,
+    /// Never back up attachments matching these types, even if allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// Skip attachments larger than this many bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_bytes: Option<u64>,
+}
+
+impl Default for AttachmentRules {
+    fn default() -> Self {
+        Self {
+            allow: default_allowed_types(),
+            deny: Vec::new(),
+            max_size_bytes: None,
+        }
+    }
+}
+
+/// How long messages are retained in a channel.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RetentionPolicy {
+    /// Delete messages older than `max_age_days`, always keeping the newest `min_keep`.
+    Age {
+        max_age_days: NonZeroU32,
+        #[serde(default)]
+        min_keep: u32,
+    },
+    /// Keep only the newest `max_count` messages.
+    Count { max_count: NonZeroU32 },
+    /// Delete messages past either limit, always keeping the newest `min_keep`.
+    AgeOrCount {
+        max_age_days: NonZeroU32,
+        max_count: NonZeroU32,
+        #[serde(default)]
+        min_keep: u32,
+    },
+}
+
+impl RetentionPolicy {
+    pub fn from_days(days: NonZeroU32) -> Self {
+        Self::Age {
+            max_age_days: days,
+            min_keep: 0,
+        }
+    }
+
+    pub fn max_age_days(&self) -> Option<NonZeroU32> {
+        match *self {
+            Self::Age { max_age_days, .. } | Self::AgeOrCount { max_age_days, .. } => {
+                Some(max_age_days)
+            }
+            Self::Count { .. } => None,
+        }
+    }
+
+    pub fn max_count(&self) -> Option<NonZeroU32> {
+        match *self {
+            Self::Count { max_count } | Self::AgeOrCount { max_count, .. } => Some(max_count),
+            Self::Age { .. } => None,
+        }
+    }
+
+    pub fn min_keep(&self) -> u32 {
+        match *self {
+            Self::Age { min_keep, .. } | Self::AgeOrCount { min_keep, .. } => min_keep,
+            Self::Count { .. } => 0,
+        }
+    }
+
+    /// Returns true if this policy may expire messages that `other` keeps.
+    pub fn is_stricter_than(&self, other: &Self) -> bool {
+        let shorter_age = match (self.max_age_days(), other.max_age_days()) {
+            (Some(new), Some(old)) => new < old,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        let smaller_count = match (self.max_count(), other.max_count()) {
+            (Some(new), Some(old)) => new < old,
+            (Some(_), None) => true,
+            _ => false,
+        };
+
+        shorter_age || smaller_count || self.min_keep() < other.min_keep()
+    }
+}
+
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = |d: NonZeroU32| format!("{d} {}", if d.get() == 1 { "day" } else { "days" });
+
+        match *self {
+            Self::Age { max_age_days, .. } => write!(f, "{}", days(max_age_days))?,
+            Self::Count { max_count } => write!(f, "newest {max_count} messages")?,
+            Self::AgeOrCount {
+                max_age_days,
+                max_count,
+                ..
+            } => write!(f, "{}, at most {max_count} messages", days(max_age_days))?,
+        }
+
+        match self.min_keep() {
+            0 => Ok(()),
+            min_keep => write!(f, ", never fewer than {min_keep} messages"),
+        }
+    }
+}
+
+/// Transcript format for archiving expired messages before they are deleted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    #[name = "JSON lines"]
+    Jsonl,
+    #[name = "HTML"]
+    Html,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Html => "html",
+        }
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jsonl => write!(f, "JSON lines"),
+            Self::Html => write!(f, "HTML"),
+        }
+    }
+}
+
+/// Accepts either a policy table or a plain number of days (the format older config files use).
+fn deserialize_policy<'de, D>(deserializer: D) -> Result<Option<RetentionPolicy>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PolicyOrDays {
+        Days(NonZeroU32),
+        Policy(RetentionPolicy),
+    }
+
+    Ok(
+        Option::<PolicyOrDays>::deserialize(deserializer)?.map(|p| match p {
+            PolicyOrDays::Days(days) => RetentionPolicy::from_days(days),
+            PolicyOrDays::Policy(policy) => policy,
+        }),
+    )
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ChannelConfig {
     pub name: String,
     /// Override for the global retention policy
-    pub policy_days: Option<NonZeroU32>,
+    #[serde(
+        default,
+        alias = "policy_days",
+        deserialize_with = "deserialize_policy",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub policy: Option<RetentionPolicy>,
+    /// Messages to keep regardless of age
+    #[serde(default)]
+    pub exemptions: ExemptionRules,
+    /// Only report what would be deleted, without deleting anything
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    /// Write expired messages to a transcript archive before deleting them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ArchiveFormat>,
+    /// Override for the global attachment backup rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<AttachmentRules>,
     /// Pagination cursor: oldest message ID seen, next run fetches BEFORE this
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub pagination_cursor: Option<u64>,
 }
 
 impl ChannelConfig {
-    pub fn resolve_policy_days(&self, config: &Config) -> NonZeroU32 {
-        self.policy_days
-            .unwrap_or(config.retention.default_policy_days)
+    pub fn resolve_policy(&self, config: &Config) -> RetentionPolicy {
+        self.policy
+            .unwrap_or_else(|| config.retention.default_policy())
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct GuildConfig {
+    /// Roles allowed to manage cleanup in addition to members with Manage Messages
+    #[serde(default)]
+    pub admin_roles: Vec<RoleId>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RetentionConfig {
     pub default_policy_days: NonZeroU32,
 }
 
+impl RetentionConfig {
+    pub fn default_policy(&self) -> RetentionPolicy {
+        RetentionPolicy::from_days(self.default_policy_days)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BackupWorkerConfig {
     #[serde(default = "default_check_interval")]
     pub check_interval_seconds: u64,
+    /// Failed attempts before a backup is dead-lettered
     #[serde(default = "default_max_retries")]
     pub max_retries: u32,
+    /// Delay before the first retry, doubled after every further failure
+    #[serde(default = "default_retry_base_delay")]
+    pub retry_base_delay_seconds: u64,
+    #[serde(default = "default_retry_max_delay")]
+    pub retry_max_delay_seconds: u64,
+    /// Files uploaded at the same time
+    #[serde(default = "default_upload_concurrency")]
+    pub concurrency: NonZeroUsize,
+    /// Upload bandwidth shared by all uploads in bytes per second, unlimited if not set
+    #[serde(default)]
+    pub bandwidth_limit: Option<NonZeroU64>,
+    /// Bandwidth limits for parts of the day, overriding `bandwidth_limit`
+    #[serde(default)]
+    pub bandwidth_schedule: Vec<BandwidthWindow>,
+}
+
+/// Bandwidth limit between two local times, e.g. unlimited from `"23:00"` to `"07:00"`.
+/// The first matching window applies.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BandwidthWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// Bytes per second, unlimited if not set
+    #[serde(default)]
+    pub limit: Option<NonZeroU64>,
+}
+
+impl BandwidthWindow {
+    /// Returns true if the window covers `time`. Windows ending before they start wrap midnight.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl BackupWorkerConfig {
+    /// Bandwidth limit in effect at a local time of day.
+    pub fn bandwidth_limit_at(&self, time: NaiveTime) -> Option<NonZeroU64> {
+        self.bandwidth_schedule
+            .iter()
+            .find(|w| w.contains(time))
+            .map_or(self.bandwidth_limit, |w| w.limit)
+    }
 }
 
 fn default_check_interval() -> u64 {
@@ -62,11 +418,28 @@ fn default_max_retries() -> u32 {
     5
 }
 
+fn default_retry_base_delay() -> u64 {
+    60
+}
+
+fn default_retry_max_delay() -> u64 {
+    6 * 60 * 60
+}
+
+fn default_upload_concurrency() -> NonZeroUsize {
+    NonZeroUsize::MIN
+}
+
 impl Default for BackupWorkerConfig {
     fn default() -> Self {
         Self {
             check_interval_seconds: default_check_interval(),
             max_retries: default_max_retries(),
+            retry_base_delay_seconds: default_retry_base_delay(),
+            retry_max_delay_seconds: default_retry_max_delay(),
+            concurrency: default_upload_concurrency(),
+            bandwidth_limit: None,
+            bandwidth_schedule: Vec::new(),
         }
     }
 }
@@ -74,6 +447,14 @@ impl Default for BackupWorkerConfig {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MediaBackupConfig {
     pub download_dir: PathBuf,
+    /// Leave media messages in Discord while `download_dir` holds more than this many bytes
+    #[serde(default)]
+    pub max_download_dir_bytes: Option<u64>,
+    /// Leave media messages in Discord while less than this many bytes are free on the disk
+    #[serde(default)]
+    pub min_free_bytes: Option<u64>,
+    #[serde(default)]
+    pub attachments: AttachmentRules,
     #[serde(default)]
     pub worker: BackupWorkerConfig,
 }
@@ -82,19 +463,60 @@ impl Default for MediaBackupConfig {
     fn default() -> Self {
         Self {
             download_dir: PathBuf::from("./media_backups"),
+            max_download_dir_bytes: None,
+            min_free_bytes: None,
+            attachments: AttachmentRules::default(),
             worker: BackupWorkerConfig::default(),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveConfig {
+    /// Where transcripts are written until they are uploaded
+    pub dir: PathBuf,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("./archives"),
+        }
+    }
+}
+
+/// Encryption at rest. The key is read from `CLEANUP_BOT_ENCRYPTION_KEY` or the
+/// `encryption-key` systemd credential, OneDrive tokens are always encrypted when it is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EncryptionConfig {
+    /// Encrypt media backups and transcripts before they are written to disk
+    #[serde(default)]
+    pub backups: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub schedule_interval_seconds: NonZeroU32,
     pub retention: RetentionConfig,
     pub media_backup: MediaBackupConfig,
     #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    #[serde(default)]
     pub onedrive: Option<OneDriveConfig>,
     #[serde(default)]
+    pub webdav: Option<WebDavConfig>,
+    #[serde(default)]
+    pub local_archive: Option<LocalArchiveConfig>,
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+    /// Channel for alerts that need an admin, e.g. paused backups
+    #[serde(default)]
+    pub alert_channel: Option<ChannelId>,
+    #[serde(default)]
+    pub guilds: HashMap<GuildId, GuildConfig>,
+    #[serde(default)]
     channels: HashMap<ChannelId, ChannelConfig>,
 }
 
@@ -115,28 +537,30 @@ impl Config {
     pub fn add_channel_config(
         &mut self,
         channel_id: ChannelId,
-        config: ChannelConfig,
-    ) -> Result<NonZeroU32> {
-        let new_days = config
-            .policy_days
-            .unwrap_or(self.retention.default_policy_days);
+        mut config: ChannelConfig,
+    ) -> Result<RetentionPolicy> {
+        let new_policy = config.resolve_policy(self);
 
-        // Check if policy is becoming stricter (fewer days) - if so, clear pagination cursor
         if let Some(existing) = self.channels.get(&channel_id) {
-            let old_days = existing.resolve_policy_days(self);
-            if new_days < old_days {
-                // Policy is stricter, start fresh from newest messages
-                let mut config = config;
+            // Exemptions and attachment rules are only set in the config file, keep them when
+            // re-enabling
+            config.exemptions = existing.exemptions.clone();
+            config.attachments = existing.attachments.clone();
+
+            // Check if policy is becoming stricter - if so, clear pagination cursor
+            let old_policy = existing.resolve_policy(self);
+            if new_policy.is_stricter_than(&old_policy) {
+                // Policy is stricter, start fresh from the newest expired messages
                 config.pagination_cursor = None;
                 self.channels.insert(channel_id, config);
                 self.save()?;
-                return Ok(new_days);
+                return Ok(new_policy);
             }
         }
 
         self.channels.insert(channel_id, config);
         self.save()?;
-        Ok(new_days)
+        Ok(new_policy)
     }
 
     pub fn get_pagination_cursor(&self, channel_id: ChannelId) -> Option<u64> {
@@ -157,16 +581,45 @@ impl Config {
         Ok(())
     }
 
+    pub fn resolve_channel_policy(&self, channel_id: ChannelId) -> RetentionPolicy {
+        self.channels
+            .get(&channel_id)
+            .map(|c| c.resolve_policy(self))
+            .unwrap_or_else(|| self.retention.default_policy())
+    }
+
+    pub fn is_dry_run(&self, channel_id: ChannelId) -> bool {
+        self.channels.get(&channel_id).is_some_and(|c| c.dry_run)
+    }
+
+    pub fn archive_format(&self, channel_id: ChannelId) -> Option<ArchiveFormat> {
+        self.channels.get(&channel_id).and_then(|c| c.archive)
+    }
+
+    pub fn get_exemptions(&self, channel_id: ChannelId) -> ExemptionRules {
+        self.channels
+            .get(&channel_id)
+            .map(|c| c.exemptions.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn get_attachment_rules(&self, channel_id: ChannelId) -> AttachmentRules {
+        self.channels
+            .get(&channel_id)
+            .and_then(|c| c.attachments.clone())
+            .unwrap_or_else(|| self.media_backup.attachments.clone())
+    }
+
     pub fn remove_channel(&mut self, channel_id: ChannelId) -> Result<()> {
         self.channels.remove(&channel_id);
         self.save()
     }
 
     /// Returns a list of all enabled channels with their resolved retention policies.
-    pub fn enabled_channels(&self) -> Vec<(ChannelId, NonZeroU32)> {
+    pub fn enabled_channels(&self) -> Vec<(ChannelId, RetentionPolicy)> {
         self.channels
             .iter()
-            .map(|(id, config)| (*id, config.resolve_policy_days(self)))
+            .map(|(id, config)| (*id, config.resolve_policy(self)))
             .collect()
     }
 }
@@ -189,19 +642,49 @@ impl ConfigStore {
         self.inner.lock().unwrap().schedule_interval_seconds
     }
 
+    /// Returns the global default retention in days.
+    pub fn default_policy_days(&self) -> NonZeroU32 {
+        self.inner.lock().unwrap().retention.default_policy_days
+    }
+
     /// Returns a list of all enabled channels with their resolved retention policies.
-    pub fn enabled_channels(&self) -> Vec<(ChannelId, NonZeroU32)> {
+    pub fn enabled_channels(&self) -> Vec<(ChannelId, RetentionPolicy)> {
         self.inner.lock().unwrap().enabled_channels()
     }
 
+    /// Returns the roles allowed to manage cleanup in a guild.
+    pub fn admin_roles(&self, guild_id: GuildId) -> Vec<RoleId> {
+        self.inner
+            .lock()
+            .unwrap()
+            .guilds
+            .get(&guild_id)
+            .map(|g| g.admin_roles.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the channel alerts are posted to, if configured.
+    pub fn alert_channel(&self) -> Option<ChannelId> {
+        self.inner.lock().unwrap().alert_channel
+    }
+
     /// Returns the media backup configuration.
     pub fn media_backup_config(&self) -> MediaBackupConfig {
         self.inner.lock().unwrap().media_backup.clone()
     }
 
+    /// Returns the transcript archive configuration.
+    pub fn archive_config(&self) -> ArchiveConfig {
+        self.inner.lock().unwrap().archive.clone()
+    }
+
     /// Adds or updates a channel configuration.
-    /// Returns the resolved policy days for the channel.
-    pub fn add_channel(&self, channel_id: ChannelId, config: ChannelConfig) -> Result<NonZeroU32> {
+    /// Returns the resolved retention policy for the channel.
+    pub fn add_channel(
+        &self,
+        channel_id: ChannelId,
+        config: ChannelConfig,
+    ) -> Result<RetentionPolicy> {
         self.inner
             .lock()
             .unwrap()
@@ -218,6 +701,34 @@ impl ConfigStore {
         self.inner.lock().unwrap().get_pagination_cursor(channel_id)
     }
 
+    /// Returns the channel's retention policy, or the default policy if it isn't enabled.
+    pub fn resolve_channel_policy(&self, channel_id: ChannelId) -> RetentionPolicy {
+        self.inner
+            .lock()
+            .unwrap()
+            .resolve_channel_policy(channel_id)
+    }
+
+    /// Returns true if cleanup runs for the channel only report what they would delete.
+    pub fn is_dry_run(&self, channel_id: ChannelId) -> bool {
+        self.inner.lock().unwrap().is_dry_run(channel_id)
+    }
+
+    /// Returns the channel's archive format, if expired messages should be archived.
+    pub fn archive_format(&self, channel_id: ChannelId) -> Option<ArchiveFormat> {
+        self.inner.lock().unwrap().archive_format(channel_id)
+    }
+
+    /// Gets the exemption rules for a channel.
+    pub fn get_exemptions(&self, channel_id: ChannelId) -> ExemptionRules {
+        self.inner.lock().unwrap().get_exemptions(channel_id)
+    }
+
+    /// Gets the attachment backup rules for a channel, falling back to the global rules.
+    pub fn get_attachment_rules(&self, channel_id: ChannelId) -> AttachmentRules {
+        self.inner.lock().unwrap().get_attachment_rules(channel_id)
+    }
+
     /// Sets the pagination cursor for a channel.
     pub fn set_pagination_cursor(&self, channel_id: ChannelId, cursor: Option<u64>) -> Result<()> {
         self.inner
@@ -0,0 +1,426 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Environment variable holding the base64 encoded encryption key.
+pub const KEY_ENV: &str = "CLEANUP_BOT_ENCRYPTION_KEY";
+/// Name of the systemd credential holding the key, e.g. `LoadCredential=encryption-key:/path`.
+const KEY_CREDENTIAL: &str = "encryption-key";
+const KEY_LEN: usize = 32;
+
+/// Extension appended to the names of encrypted files.
+pub const ENCRYPTED_EXTENSION: &str = "enc";
+
+const MAGIC: &[u8; 8] = b"CBOTENC1";
+// XChaCha20 nonce minus the 5 bytes used by the STREAM counter and last-block flag
+const NONCE_PREFIX_LEN: usize = 19;
+const HEADER_LEN: usize = MAGIC.len() + NONCE_PREFIX_LEN;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// Key for encrypting tokens and backups at rest.
+///
+/// Encrypted data is a header (magic and random nonce prefix) followed by XChaCha20-Poly1305
+/// STREAM chunks of 64 KiB, so files can be encrypted and decrypted without loading them whole,
+/// and truncation or reordering is detected.
+#[derive(Clone)]
+pub struct EncryptionKey(Key);
+
+impl EncryptionKey {
+    /// Load the key from the environment, or from the systemd credentials directory.
+    /// Returns `None` if neither is set.
+    pub fn load() -> Result<Option<Self>> {
+        if let Ok(encoded) = std::env::var(KEY_ENV) {
+            return Self::decode(&encoded)
+                .with_context(|| format!("Invalid key in {KEY_ENV}"))
+                .map(Some);
+        }
+
+        let Some(dir) = std::env::var_os("CREDENTIALS_DIRECTORY") else {
+            return Ok(None);
+        };
+
+        let path = PathBuf::from(dir).join(KEY_CREDENTIAL);
+        match fs::read_to_string(&path) {
+            Ok(encoded) => Self::decode(&encoded)
+                .with_context(|| format!("Invalid key in {path:?}"))
+                .map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {path:?}")),
+        }
+    }
+
+    /// Generate a new random key, base64 encoded.
+    pub fn generate() -> String {
+        STANDARD.encode(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    fn decode(encoded: &str) -> Result<Self> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .context("Key is not valid base64")?;
+        if bytes.len() != KEY_LEN {
+            bail!("Key must be {KEY_LEN} bytes, got {}", bytes.len());
+        }
+        Ok(Self(*Key::from_slice(&bytes)))
+    }
+
+    /// Encrypt a small value held in memory.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut encryptor = self.encryptor();
+        let mut ciphertext = encryptor.header().to_vec();
+        ciphertext.extend(encryptor.update(plaintext)?);
+        ciphertext.extend(encryptor.finish()?);
+        Ok(ciphertext)
+    }
+
+    /// Decrypt a value produced by [`EncryptionKey::encrypt`] or an encrypted file.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if ciphertext.len() < HEADER_LEN {
+            bail!("Encrypted data is truncated");
+        }
+        let (header, body) = ciphertext.split_at(HEADER_LEN);
+        let mut decryptor = self.decryptor(header)?;
+        let mut plaintext = decryptor.update(body)?;
+        plaintext.extend(decryptor.finish()?);
+        Ok(plaintext)
+    }
+
+    /// Start encrypting a stream of data.
+    pub fn encryptor(&self) -> StreamEncryptor {
+        let mut nonce = [0; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut header = [0; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()..].copy_from_slice(&nonce);
+
+        StreamEncryptor {
+            header,
+            inner: EncryptorBE32::new(&self.0, nonce.as_slice().into()),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn decryptor(&self, header: &[u8]) -> Result<StreamDecryptor> {
+        let Some(nonce) = header.strip_prefix(MAGIC.as_slice()) else {
+            bail!("Not an encrypted file");
+        };
+
+        Ok(StreamDecryptor {
+            inner: DecryptorBE32::new(&self.0, nonce.into()),
+            buffer: Vec::with_capacity(CHUNK_SIZE + TAG_LEN),
+        })
+    }
+
+    /// Decrypt `src` into `dst`, removing `dst` again if decryption fails.
+    pub async fn decrypt_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        let result = self.try_decrypt_file(src, dst).await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(dst).await;
+        }
+        result
+    }
+
+    async fn try_decrypt_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        let mut input = tokio::fs::File::open(src)
+            .await
+            .context("Failed to open encrypted file")?;
+
+        let mut header = [0; HEADER_LEN];
+        input
+            .read_exact(&mut header)
+            .await
+            .context("Encrypted file is truncated")?;
+        let mut decryptor = self.decryptor(&header)?;
+
+        let mut output = tokio::fs::File::create(dst)
+            .await
+            .context("Failed to create decrypted file")?;
+
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let read = input.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            output.write_all(&decryptor.update(&buf[..read])?).await?;
+        }
+        output.write_all(&decryptor.finish()?).await?;
+        output.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Returns true if a file name marks it as encrypted.
+pub fn is_encrypted(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == ENCRYPTED_EXTENSION)
+}
+
+/// Encrypts data incrementally, buffering up to one chunk.
+pub struct StreamEncryptor {
+    header: [u8; HEADER_LEN],
+    inner: EncryptorBE32<XChaCha20Poly1305>,
+    buffer: Vec<u8>,
+}
+
+impl StreamEncryptor {
+    /// Bytes to write before any encrypted data.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// Encrypt more data, returning the ciphertext of every completed chunk.
+    pub fn update(&mut self, mut data: &[u8]) -> Result<Vec<u8>> {
+        let mut ciphertext = Vec::new();
+
+        while !data.is_empty() {
+            // A full buffer is only flushed once more data arrives, the last chunk is marked
+            if self.buffer.len() == CHUNK_SIZE {
+                ciphertext.extend(self.seal_chunk()?);
+            }
+            let take = (CHUNK_SIZE - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+
+        Ok(ciphertext)
+    }
+
+    /// Encrypt the remaining buffered data as the final chunk.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        self.inner
+            .encrypt_last(self.buffer.as_slice())
+            .map_err(|_| anyhow::anyhow!("Encryption failed"))
+    }
+
+    fn seal_chunk(&mut self) -> Result<Vec<u8>> {
+        let chunk = self
+            .inner
+            .encrypt_next(self.buffer.as_slice())
+            .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+        self.buffer.clear();
+        Ok(chunk)
+    }
+}
+
+/// Decrypts data incrementally, holding back the chunk that may turn out to be the last one.
+struct StreamDecryptor {
+    inner: DecryptorBE32<XChaCha20Poly1305>,
+    buffer: Vec<u8>,
+}
+
+impl StreamDecryptor {
+    fn update(&mut self, mut data: &[u8]) -> Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+
+        while !data.is_empty() {
+            if self.buffer.len() == CHUNK_SIZE + TAG_LEN {
+                let chunk = self
+                    .inner
+                    .decrypt_next(self.buffer.as_slice())
+                    .map_err(|_| {
+                        anyhow::anyhow!("Decryption failed, wrong key or corrupted data")
+                    })?;
+                plaintext.extend(chunk);
+                self.buffer.clear();
+            }
+            let take = (CHUNK_SIZE + TAG_LEN - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+
+        Ok(plaintext)
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        self.inner
+            .decrypt_last(self.buffer.as_slice())
+            .map_err(|_| anyhow::anyhow!("Decryption failed, wrong key or truncated data"))
+    }
+}
+
+/// Atomically replace `path` with `content`, readable by the owner only.
+pub fn write_private_file(path: &Path, content: &[u8]) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(&temp_path)
+        .with_context(|| format!("Failed to create {temp_path:?}"))?;
+
+    // The mode only applies to new files, tighten a temp file left over from an older version
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path).with_context(|| format!("Failed to replace {path:?}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> EncryptionKey {
+        EncryptionKey::decode(&EncryptionKey::generate()).unwrap()
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    /// Sizes around chunk boundaries, including exact multiples of the chunk size.
+    const SIZES: [usize; 7] = [
+        0,
+        1,
+        CHUNK_SIZE - 1,
+        CHUNK_SIZE,
+        CHUNK_SIZE + 1,
+        3 * CHUNK_SIZE,
+        3 * CHUNK_SIZE + 17,
+    ];
+
+    #[test]
+    fn round_trip() {
+        let key = key();
+        for size in SIZES {
+            let plaintext = data(size);
+            let ciphertext = key.encrypt(&plaintext).unwrap();
+            assert_eq!(key.decrypt(&ciphertext).unwrap(), plaintext, "size {size}");
+        }
+    }
+
+    #[test]
+    fn streamed_round_trip_matches_chunk_layout() {
+        let key = key();
+        for size in SIZES {
+            let plaintext = data(size);
+
+            // Feed uneven pieces, like reads from a file
+            let mut encryptor = key.encryptor();
+            let mut ciphertext = encryptor.header().to_vec();
+            for piece in plaintext.chunks(10_000) {
+                ciphertext.extend(encryptor.update(piece).unwrap());
+            }
+            ciphertext.extend(encryptor.finish().unwrap());
+
+            let chunks = size.div_ceil(CHUNK_SIZE).max(1);
+            assert_eq!(
+                ciphertext.len(),
+                HEADER_LEN + size + chunks * TAG_LEN,
+                "size {size}"
+            );
+            assert_eq!(key.decrypt(&ciphertext).unwrap(), plaintext, "size {size}");
+        }
+    }
+
+    #[tokio::test]
+    async fn decrypt_file_round_trip() {
+        let key = key();
+        let plaintext = data(2 * CHUNK_SIZE);
+        let dir = std::env::temp_dir().join(format!("crypto-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (src, dst) = (dir.join("file.enc"), dir.join("file"));
+        fs::write(&src, key.encrypt(&plaintext).unwrap()).unwrap();
+
+        key.decrypt_file(&src, &dst).await.unwrap();
+
+        assert_eq!(fs::read(&dst).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn truncated_data_fails() {
+        let key = key();
+        let ciphertext = key.encrypt(&data(3 * CHUNK_SIZE + 17)).unwrap();
+
+        // Cut after a whole chunk, inside a chunk, and inside the header
+        for len in [
+            HEADER_LEN + CHUNK_SIZE + TAG_LEN,
+            HEADER_LEN + 2 * (CHUNK_SIZE + TAG_LEN),
+            ciphertext.len() - 1,
+            HEADER_LEN + 100,
+            HEADER_LEN,
+            HEADER_LEN - 1,
+        ] {
+            assert!(key.decrypt(&ciphertext[..len]).is_err(), "length {len}");
+        }
+    }
+
+    #[test]
+    fn tampered_data_fails() {
+        let key = key();
+        let ciphertext = key.encrypt(&data(2 * CHUNK_SIZE)).unwrap();
+
+        for position in [
+            HEADER_LEN - 1,
+            HEADER_LEN,
+            HEADER_LEN + CHUNK_SIZE + TAG_LEN + 5,
+        ] {
+            let mut tampered = ciphertext.clone();
+            tampered[position] ^= 1;
+            assert!(key.decrypt(&tampered).is_err(), "position {position}");
+        }
+
+        // Swapping the first two chunks is caught by the STREAM counter
+        let mut reordered = ciphertext[..HEADER_LEN].to_vec();
+        let chunk = |i: usize| {
+            &ciphertext[HEADER_LEN + i * (CHUNK_SIZE + TAG_LEN)..][..CHUNK_SIZE + TAG_LEN]
+        };
+        reordered.extend(chunk(1));
+        reordered.extend(chunk(0));
+        reordered.extend(&ciphertext[HEADER_LEN + 2 * (CHUNK_SIZE + TAG_LEN)..]);
+        assert!(key.decrypt(&reordered).is_err());
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let ciphertext = key().encrypt(b"secret").unwrap();
+        assert!(key().decrypt(&ciphertext).is_err());
+    }
+
+    #[tokio::test]
+    async fn truncated_file_fails_and_leaves_no_output() {
+        let key = key();
+        let ciphertext = key.encrypt(&data(2 * CHUNK_SIZE + 17)).unwrap();
+        let dir = std::env::temp_dir().join(format!("crypto-truncated-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (src, dst) = (dir.join("file.enc"), dir.join("file"));
+        fs::write(&src, &ciphertext[..HEADER_LEN + 2 * (CHUNK_SIZE + TAG_LEN)]).unwrap();
+
+        assert!(key.decrypt_file(&src, &dst).await.is_err());
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn decodes_keys() {
+        assert!(EncryptionKey::decode(&format!("{}\n", EncryptionKey::generate())).is_ok());
+        assert!(EncryptionKey::decode(&STANDARD.encode([0; KEY_LEN - 1])).is_err());
+        assert!(EncryptionKey::decode("not base64!").is_err());
+    }
+}
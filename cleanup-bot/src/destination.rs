@@ -0,0 +1,259 @@
+mod local;
+mod onedrive;
+mod s3;
+mod webdav;
+
+pub use local::LocalDestination;
+pub use s3::S3Destination;
+pub use webdav::WebDavDestination;
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use futures::StreamExt;
+use reqwest::{Body, Response};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex as TokioMutex;
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+use crate::backup::{BackupQueue, BandwidthLimiter, UploadSession};
+use crate::config::Config;
+use crate::crypto::{EncryptionKey, KEY_ENV};
+use crate::onedrive::{OneDriveClient, TokenStore};
+
+/// Somewhere backed-up files are uploaded to.
+#[async_trait]
+pub trait BackupDestination: Send + Sync {
+    /// Short name used in logs and to track which destinations a backup has reached.
+    fn name(&self) -> &str;
+
+    /// Upload a local file. Uploading the same file again overwrites the earlier copy.
+    async fn upload(&self, local_path: &Path, progress: &UploadProgress<'_>) -> Result<()>;
+
+    /// Names of the files in a directory given relative to the destination's root, e.g.
+    /// `2024/05/01`. A directory that doesn't exist is empty.
+    async fn list(&self, dir: &str) -> Result<Vec<String>>;
+
+    /// Download a file given relative to the destination's root to `local_path`.
+    async fn download(&self, path: &str, local_path: &Path) -> Result<()>;
+
+    /// Why uploads can't succeed until an admin steps in, e.g. expired credentials.
+    async fn unavailable_reason(&self) -> Option<String> {
+        None
+    }
+}
+
+/// The configured backup destinations, in the order they are uploaded to.
+pub struct Destinations {
+    pub all: Vec<Arc<dyn BackupDestination>>,
+    /// Set if OneDrive backups are configured
+    pub onedrive_tokens: Option<Arc<TokioMutex<TokenStore>>>,
+}
+
+impl Destinations {
+    /// Set up every destination in the config. OneDrive tokens are encrypted with `key` if set.
+    pub async fn from_config(config: &Config, key: Option<EncryptionKey>) -> Self {
+        let mut all: Vec<Arc<dyn BackupDestination>> = Vec::new();
+        let mut onedrive_tokens = None;
+
+        if let Some(od_config) = config.onedrive.clone() {
+            if key.is_none() {
+                warn!("{KEY_ENV} is not set, OneDrive tokens are stored unencrypted");
+            }
+
+            let token_store = Arc::new(TokioMutex::new(TokenStore::new(
+                od_config.client_id,
+                od_config.auth_url,
+                key,
+            )));
+
+            // Backups wait until an admin signs in, the bot itself doesn't need OneDrive to start
+            if token_store.lock().await.needs_reauth() {
+                warn!("OneDrive tokens not found, run /onedrive auth to sign in");
+            }
+
+            all.push(Arc::new(OneDriveClient::new(
+                Arc::clone(&token_store),
+                od_config.upload_folder,
+                od_config.graph_api,
+            )));
+            onedrive_tokens = Some(token_store);
+        }
+
+        if let Some(webdav_config) = config.webdav.clone() {
+            all.push(Arc::new(WebDavDestination::new(webdav_config)));
+        }
+
+        if let Some(local_config) = config.local_archive.clone() {
+            all.push(Arc::new(LocalDestination::new(local_config.dir)));
+        }
+
+        if let Some(s3_config) = config.s3.clone() {
+            all.push(Arc::new(S3Destination::new(s3_config)));
+        }
+
+        if all.is_empty() {
+            info!("No backup destination configured, backups will be stored locally only");
+        }
+
+        Self {
+            all,
+            onedrive_tokens,
+        }
+    }
+
+    /// Find a destination by name, or the first available one to restore from.
+    pub async fn restore_source(&self, name: Option<&str>) -> Result<Arc<dyn BackupDestination>> {
+        if let Some(name) = name {
+            return self
+                .all
+                .iter()
+                .find(|d| d.name() == name)
+                .cloned()
+                .with_context(|| format!("No backup destination named {name}"));
+        }
+
+        for destination in &self.all {
+            if destination.unavailable_reason().await.is_none() {
+                return Ok(Arc::clone(destination));
+            }
+        }
+        bail!("No backup destination is available to restore from")
+    }
+
+    /// Names of the configured destinations.
+    pub fn names(&self) -> Vec<&str> {
+        self.all.iter().map(|d| d.name()).collect()
+    }
+}
+
+/// Upload state of one file for one destination: the resumable session, persisted in its backup
+/// queue entry, and the bandwidth shared with other uploads.
+pub struct UploadProgress<'a> {
+    queue: &'a Mutex<BackupQueue>,
+    local_path: &'a Path,
+    destination: &'a str,
+    limiter: &'a Arc<BandwidthLimiter>,
+}
+
+impl<'a> UploadProgress<'a> {
+    pub fn new(
+        queue: &'a Mutex<BackupQueue>,
+        local_path: &'a Path,
+        destination: &'a str,
+        limiter: &'a Arc<BandwidthLimiter>,
+    ) -> Self {
+        Self {
+            queue,
+            local_path,
+            destination,
+            limiter,
+        }
+    }
+
+    /// Request body sending `data` within the bandwidth limit.
+    /// Streamed bodies have no length, so set `Content-Length` on the request.
+    pub fn body(&self, data: Vec<u8>) -> Body {
+        Body::wrap_stream(self.limiter.throttle_bytes(data.into()))
+    }
+
+    /// Request body streaming a file within the bandwidth limit.
+    pub fn file_body(&self, file: tokio::fs::File) -> Body {
+        Body::wrap_stream(self.limiter.throttle(ReaderStream::new(file)))
+    }
+
+    pub fn destination(&self) -> &str {
+        self.destination
+    }
+
+    /// The saved session for this destination, if any.
+    pub fn session(&self) -> Option<UploadSession> {
+        self.queue
+            .lock()
+            .unwrap()
+            .get(self.local_path)
+            .and_then(|b| b.upload_session.clone())
+            .filter(|s| s.destination == self.destination)
+    }
+
+    /// Save or clear the session. Failing to save only costs the ability to resume.
+    pub fn save_session(&self, session: Option<UploadSession>) {
+        if let Err(e) = self
+            .queue
+            .lock()
+            .unwrap()
+            .set_upload_session(self.local_path, session)
+        {
+            warn!(
+                "Failed to save upload session for {}: {e:?}",
+                self.local_path.display()
+            );
+        }
+    }
+}
+
+/// Stream a download response into `local_path`.
+async fn save_response(resp: Response, local_path: &Path) -> Result<()> {
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        bail!("Download failed with status {status}: {body}");
+    }
+
+    let mut file = tokio::fs::File::create(local_path)
+        .await
+        .with_context(|| format!("Failed to create {}", local_path.display()))?;
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        file.write_all(&chunk.context("Failed to read response chunk")?)
+            .await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Build the date-organized path of a backed-up file, relative to the destination's root:
+/// `YYYY/MM/DD/file_name`. The date comes from the parent directory name (format: YYYY-MM-DD).
+pub fn remote_path(local_path: &Path) -> String {
+    let file_name = local_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown");
+
+    let date = local_path
+        .parent()
+        .and_then(|p| p.file_name())
+        .and_then(|n| n.to_str())
+        .and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+        .unwrap_or_else(|| Utc::now().date_naive());
+
+    format!("{}/{file_name}", remote_dir(date))
+}
+
+/// Directory holding the files backed up for a date, relative to the destination's root.
+pub fn remote_dir(date: NaiveDate) -> String {
+    date.format("%Y/%m/%d").to_string()
+}
+
+/// Percent-encode everything except unreserved characters, as SigV4 requires and any server
+/// accepts. Slashes are kept in paths but encoded in query values.
+pub fn uri_encode(input: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        }
+    }
+    encoded
+}
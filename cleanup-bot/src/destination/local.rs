@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::fs;
+use tracing::debug;
+
+use super::{BackupDestination, UploadProgress, remote_path};
+
+/// Copies backups into a local directory, e.g. a mounted NAS share.
+pub struct LocalDestination {
+    dir: PathBuf,
+}
+
+impl LocalDestination {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+#[async_trait]
+impl BackupDestination for LocalDestination {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    async fn upload(&self, local_path: &Path, _progress: &UploadProgress<'_>) -> Result<()> {
+        let target = self.dir.join(remote_path(local_path));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        // Local copies don't use the uplink, so they aren't bandwidth limited.
+        // Copy to a temp file first so an interrupted copy never looks complete
+        let mut temp = target.clone().into_os_string();
+        temp.push(".part");
+        let temp = PathBuf::from(temp);
+        fs::copy(local_path, &temp)
+            .await
+            .with_context(|| format!("Failed to copy to {}", temp.display()))?;
+        fs::rename(&temp, &target)
+            .await
+            .with_context(|| format!("Failed to rename to {}", target.display()))?;
+
+        debug!("Copied {} to {}", local_path.display(), target.display());
+        Ok(())
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>> {
+        let dir = self.dir.join(dir);
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+        };
+
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    async fn download(&self, path: &str, local_path: &Path) -> Result<()> {
+        let source = self.dir.join(path);
+        fs::copy(&source, local_path)
+            .await
+            .with_context(|| format!("Failed to copy {}", source.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::Path;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{BackupDestination, UploadProgress, save_response};
+use crate::onedrive::OneDriveClient;
+
+#[async_trait]
+impl BackupDestination for OneDriveClient {
+    fn name(&self) -> &str {
+        "onedrive"
+    }
+
+    async fn upload(&self, local_path: &Path, progress: &UploadProgress<'_>) -> Result<()> {
+        Ok(self.upload_file(local_path, progress).await?)
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>> {
+        Ok(self.list_folder(dir).await?)
+    }
+
+    async fn download(&self, path: &str, local_path: &Path) -> Result<()> {
+        save_response(self.download_file(path).await?, local_path).await
+    }
+
+    async fn unavailable_reason(&self) -> Option<String> {
+        self.needs_reauth()
+            .await
+            .then(|| "OneDrive needs to be signed in again, run `/onedrive auth`".to_string())
+    }
+}
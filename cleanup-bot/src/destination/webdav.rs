@@ -0,0 +1,416 @@
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use chrono::{TimeDelta, Utc};
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{debug, info};
+
+use super::{BackupDestination, UploadProgress, remote_path, save_response, uri_encode};
+use crate::backup::UploadSession;
+use crate::config::WebDavConfig;
+
+// Larger files are streamed from disk instead of being read into memory
+const BUFFERED_UPLOAD_LIMIT: u64 = 4 * 1024 * 1024; // 4MB
+// Nextcloud takes chunks of 5MB to 5GB, except for the last one. Larger files are uploaded in
+// chunks so an interrupted upload doesn't start over.
+const CHUNK_SIZE: u64 = 10 * 1024 * 1024; // 10MB
+// Nextcloud removes unfinished uploads after a day
+const UPLOAD_LIFETIME: TimeDelta = TimeDelta::days(1);
+// Don't resume uploads about to be removed mid-chunk
+const UPLOAD_EXPIRY_MARGIN: TimeDelta = TimeDelta::minutes(5);
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/></d:prop></d:propfind>"#;
+
+/// Uploads backups to a WebDAV server such as Nextcloud.
+pub struct WebDavDestination {
+    http: Client,
+    config: WebDavConfig,
+    /// Collections known to exist, so each is only created once
+    collections: Mutex<HashSet<String>>,
+}
+
+impl WebDavDestination {
+    pub fn new(config: WebDavConfig) -> Self {
+        Self {
+            http: Client::new(),
+            config,
+            collections: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Path of a file relative to the destination's root, inside the upload folder.
+    fn folder_path(&self, path: &str) -> String {
+        let path = format!("{}/{path}", self.config.upload_folder.trim_matches('/'));
+        path.trim_start_matches('/').to_string()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.config.url.trim_end_matches('/'),
+            uri_encode(path.trim_start_matches('/'), true)
+        )
+    }
+
+    /// Nextcloud's chunked upload endpoint, if the server has one.
+    fn uploads_url(&self) -> Option<String> {
+        if let Some(url) = &self.config.uploads_url {
+            return Some(url.trim_end_matches('/').to_string());
+        }
+
+        // https://cloud.example.com/remote.php/dav/files/alice
+        //   -> https://cloud.example.com/remote.php/dav/uploads/alice
+        let (base, files) = self.config.url.split_once("/remote.php/dav/files/")?;
+        let user = files.split('/').next().filter(|u| !u.is_empty())?;
+        Some(format!("{base}/remote.php/dav/uploads/{user}"))
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.http
+            .request(method, url)
+            .basic_auth(&self.config.username, Some(&self.config.password))
+    }
+
+    /// Create every collection along `dir` that doesn't exist yet (MKCOL).
+    async fn create_collections(&self, dir: &str) -> Result<()> {
+        let mut path = String::new();
+
+        for segment in dir.split('/').filter(|s| !s.is_empty()) {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(segment);
+
+            if self.collections.lock().unwrap().contains(&path) {
+                continue;
+            }
+
+            let resp = self
+                .request(Method::from_bytes(b"MKCOL")?, &self.url(&path))
+                .send()
+                .await?;
+
+            // 405 Method Not Allowed means the collection already exists, possibly because a
+            // concurrent upload just created it
+            match resp.status() {
+                StatusCode::CREATED | StatusCode::METHOD_NOT_ALLOWED => {
+                    debug!("Collection {path} ready");
+                }
+                status => {
+                    let body = resp.text().await.unwrap_or_default();
+                    bail!("Failed to create collection {path}: {status}: {body}");
+                }
+            }
+
+            self.collections.lock().unwrap().insert(path.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Upload a file in a single PUT.
+    async fn simple_upload(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        file_size: u64,
+        progress: &UploadProgress<'_>,
+    ) -> Result<()> {
+        let body = if file_size < BUFFERED_UPLOAD_LIMIT {
+            progress.body(tokio::fs::read(local_path).await?)
+        } else {
+            let file = tokio::fs::File::open(local_path)
+                .await
+                .context("Failed to open file")?;
+            progress.file_body(file)
+        };
+
+        let resp = self
+            .request(Method::PUT, &self.url(remote_path))
+            .header("Content-Type", "application/octet-stream")
+            .header(CONTENT_LENGTH, file_size)
+            .body(body)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            bail!("WebDAV upload failed with status {status}: {body}");
+        }
+
+        Ok(())
+    }
+
+    /// Upload a file with Nextcloud's chunked upload: chunks are put into an upload collection,
+    /// which is then assembled into the file with a MOVE. The session is saved after every
+    /// chunk, so an interrupted upload resumes with the next one.
+    async fn chunked_upload(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        file_size: u64,
+        uploads_url: &str,
+        progress: &UploadProgress<'_>,
+    ) -> Result<()> {
+        let destination = self.url(remote_path);
+
+        let mut session = match self.resume_session(progress).await? {
+            Some(session) => {
+                info!(
+                    "Resuming upload of {remote_path} at byte {}",
+                    session.next_offset
+                );
+                session
+            }
+            None => self.create_upload(uploads_url, &destination).await?,
+        };
+        progress.save_session(Some(session.clone()));
+
+        let mut file = tokio::fs::File::open(local_path).await?;
+
+        while session.next_offset < file_size {
+            let start = session.next_offset;
+            let len = (file_size - start).min(CHUNK_SIZE);
+            // Chunks are numbered from 1 and assembled in order
+            let number = start / CHUNK_SIZE + 1;
+
+            file.seek(SeekFrom::Start(start)).await?;
+            let mut chunk = vec![0; len as usize];
+            file.read_exact(&mut chunk).await?;
+
+            debug!("Uploading chunk {number} of {remote_path}");
+
+            let resp = self
+                .request(Method::PUT, &format!("{}/{number}", session.url))
+                .header("Destination", &destination)
+                .header("OC-Total-Length", file_size)
+                .header(CONTENT_LENGTH, len)
+                .body(progress.body(chunk))
+                .send()
+                .await?;
+
+            match resp.status() {
+                status if status.is_success() => {}
+                StatusCode::NOT_FOUND => {
+                    // The upload was cleaned up, the next attempt starts a new one
+                    progress.save_session(None);
+                    bail!("Upload collection no longer exists");
+                }
+                status => {
+                    let body = resp.text().await.unwrap_or_default();
+                    bail!("Chunk upload failed with status {status}: {body}");
+                }
+            }
+
+            session.next_offset = start + len;
+            progress.save_session(Some(session.clone()));
+        }
+
+        let resp = self
+            .request(
+                Method::from_bytes(b"MOVE")?,
+                &format!("{}/.file", session.url),
+            )
+            .header("Destination", &destination)
+            .header("OC-Total-Length", file_size)
+            .send()
+            .await?;
+
+        match resp.status() {
+            status if status.is_success() => {
+                progress.save_session(None);
+                Ok(())
+            }
+            StatusCode::NOT_FOUND => {
+                progress.save_session(None);
+                bail!("Upload collection no longer exists");
+            }
+            // Keep the session, so the next attempt only retries assembling the file
+            status => {
+                let body = resp.text().await.unwrap_or_default();
+                bail!("Assembling chunks failed with status {status}: {body}");
+            }
+        }
+    }
+
+    /// Create an upload collection for chunks of the file at `destination`.
+    async fn create_upload(&self, uploads_url: &str, destination: &str) -> Result<UploadSession> {
+        let url = format!("{uploads_url}/cleanup-bot-{:016x}", rand::random::<u64>());
+        let resp = self
+            .request(Method::from_bytes(b"MKCOL")?, &url)
+            .header("Destination", destination)
+            .send()
+            .await?;
+
+        if resp.status() != StatusCode::CREATED {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            bail!("Failed to create upload collection: {status}: {body}");
+        }
+
+        debug!("Created upload collection {url}");
+        Ok(UploadSession {
+            destination: self.name().to_string(),
+            url,
+            next_offset: 0,
+            expires_at: Utc::now() + UPLOAD_LIFETIME,
+        })
+    }
+
+    /// Check that a saved upload collection still exists. Returns `None` if there is no usable
+    /// session and a new one has to be created.
+    async fn resume_session(&self, progress: &UploadProgress<'_>) -> Result<Option<UploadSession>> {
+        let Some(session) = progress.session() else {
+            return Ok(None);
+        };
+
+        if session.expires_at <= Utc::now() + UPLOAD_EXPIRY_MARGIN {
+            debug!("Saved upload collection expired at {}", session.expires_at);
+            progress.save_session(None);
+            return Ok(None);
+        }
+
+        let resp = self
+            .request(Method::from_bytes(b"PROPFIND")?, &session.url)
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+
+        match resp.status() {
+            StatusCode::MULTI_STATUS => Ok(Some(session)),
+            StatusCode::NOT_FOUND => {
+                debug!("Saved upload collection no longer exists");
+                progress.save_session(None);
+                Ok(None)
+            }
+            status => {
+                let body = resp.text().await.unwrap_or_default();
+                bail!("Failed to query upload collection: {status}: {body}");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BackupDestination for WebDavDestination {
+    fn name(&self) -> &str {
+        "webdav"
+    }
+
+    async fn upload(&self, local_path: &Path, progress: &UploadProgress<'_>) -> Result<()> {
+        let remote_path = self.folder_path(&remote_path(local_path));
+        let remote_path = remote_path.as_str();
+
+        if let Some((dir, _)) = remote_path.rsplit_once('/') {
+            self.create_collections(dir).await?;
+        }
+
+        let file_size = tokio::fs::metadata(local_path).await?.len();
+        debug!(
+            "Uploading {} ({file_size} bytes) to {remote_path}",
+            local_path.display()
+        );
+
+        match self.uploads_url() {
+            Some(uploads_url) if file_size > CHUNK_SIZE => {
+                self.chunked_upload(local_path, remote_path, file_size, &uploads_url, progress)
+                    .await?
+            }
+            _ => {
+                self.simple_upload(local_path, remote_path, file_size, progress)
+                    .await?
+            }
+        }
+
+        debug!("WebDAV upload completed for {remote_path}");
+        Ok(())
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>> {
+        let url = format!("{}/", self.url(&self.folder_path(dir)));
+        let resp = self
+            .request(Method::from_bytes(b"PROPFIND")?, &url)
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+
+        match resp.status() {
+            StatusCode::NOT_FOUND => return Ok(Vec::new()),
+            StatusCode::MULTI_STATUS => {}
+            status => {
+                let body = resp.text().await.unwrap_or_default();
+                bail!("WebDAV listing failed with status {status}: {body}");
+            }
+        }
+
+        let body = resp.text().await?;
+        let doc = roxmltree::Document::parse(&body).context("Invalid PROPFIND response")?;
+
+        // The collection itself is listed too, but only files are of interest
+        let names = doc
+            .descendants()
+            .filter(|n| n.tag_name().name() == "response")
+            .filter(|response| {
+                !response
+                    .descendants()
+                    .any(|n| n.tag_name().name() == "collection")
+            })
+            .filter_map(|response| {
+                let href = response
+                    .descendants()
+                    .find(|n| n.tag_name().name() == "href")?
+                    .text()?;
+                let name = href.trim_end_matches('/').rsplit('/').next()?;
+                Some(decode_path(name))
+            })
+            .collect();
+
+        Ok(names)
+    }
+
+    async fn download(&self, path: &str, local_path: &Path) -> Result<()> {
+        let resp = self
+            .request(Method::GET, &self.url(&self.folder_path(path)))
+            .send()
+            .await?;
+
+        save_response(resp, local_path).await
+    }
+}
+
+/// Undo [`uri_encode`] on a name from a server response. Invalid escapes are kept as they are.
+fn decode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
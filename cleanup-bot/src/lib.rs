@@ -0,0 +1,15 @@
+pub mod alert;
+pub mod audit;
+pub mod backup;
+pub mod cancellation;
+pub mod cleanup;
+pub mod cli;
+pub mod command;
+pub mod config;
+pub mod crypto;
+pub mod destination;
+pub mod media;
+pub mod onedrive;
+pub mod restore;
+#[cfg(any(test, feature = "stand-in"))]
+pub mod stand_in;
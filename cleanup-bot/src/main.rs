@@ -1,69 +1,62 @@
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use poise::samples::register_in_guild;
 use serenity::{Client, all::GatewayIntents};
-use tokio::sync::Mutex as TokioMutex;
 use tracing::{error, info};
 
-use crate::{
+use cleanup_bot::{
+    alert::Alerter,
     backup::BackupQueue,
     cancellation::CancellationRegistry,
-    cleanup::spawn_worker,
-    command::{CommandData, cleanup},
+    cleanup::{
+        boundary::BoundaryCache, run_log::RunLog, spawn_worker, task::CleanupContext,
+        transcribed::TranscribedMessages,
+    },
+    cli,
+    command::{CommandData, backup, cleanup, onedrive},
     config::{Config, ConfigStore},
-    onedrive::{OneDriveClient, TokenStore},
+    crypto::{EncryptionKey, KEY_ENV},
+    destination::Destinations,
+    media::MediaIndex,
 };
 
-mod backup;
-mod cancellation;
-mod cleanup;
-mod command;
-mod config;
-mod media;
-mod onedrive;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     shared::init_tracing!()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&args).await;
+    }
+
     let bot_config = shared::load_bot_config!()?;
     let config = Config::load()?;
+    let encryption_key = EncryptionKey::load()?;
+    if config.encryption.backups && encryption_key.is_none() {
+        bail!("Backup encryption is enabled but {KEY_ENV} is not set");
+    }
+    let backup_encryption = encryption_key.clone().filter(|_| config.encryption.backups);
     let backup_worker_config = config.media_backup.worker.clone();
-    let onedrive_config = config.onedrive.clone();
+    let destinations = Destinations::from_config(&config, encryption_key.clone()).await;
     let config_store = ConfigStore::new(config);
     let backup_queue = Arc::new(Mutex::new(BackupQueue::load()?));
     let cancellation = Arc::new(Mutex::new(CancellationRegistry::new()));
+    let run_log = Arc::new(Mutex::new(RunLog::load()?));
+    let media_index = Arc::new(Mutex::new(MediaIndex::load()?));
+    let transcribed = Arc::new(Mutex::new(TranscribedMessages::load()?));
     let intents = GatewayIntents::MESSAGE_CONTENT | GatewayIntents::GUILD_MESSAGES;
 
-    // Initialize OneDrive client if configured
-    let onedrive_client = if let Some(od_config) = onedrive_config {
-        let token_store = Arc::new(TokioMutex::new(TokenStore::new(
-            od_config.client_id.clone(),
-        )));
-
-        // Check if we need to authenticate
-        if !token_store.lock().await.has_tokens() {
-            info!("OneDrive tokens not found, starting device code flow...");
-            token_store.lock().await.device_code_flow().await?;
-        }
-
-        Some(Arc::new(OneDriveClient::new(
-            token_store,
-            od_config.upload_folder,
-        )))
-    } else {
-        info!("OneDrive not configured, backups will be stored locally only");
-        None
-    };
-
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
-            commands: vec![cleanup()],
+            commands: vec![cleanup(), backup(), onedrive()],
             ..Default::default()
         })
         .setup({
             let config_store = config_store.clone();
             let cancellation = Arc::clone(&cancellation);
+            let run_log = Arc::clone(&run_log);
 
             move |ctx, ready, framework| {
                 let http = Arc::clone(&ctx.http);
@@ -76,25 +69,38 @@ async fn main() -> Result<()> {
                     }
 
                     // Spawn the backup worker (only if we have somewhere to back up to)
-                    if let Some(onedrive_client) = onedrive_client {
-                        backup::spawn_worker(
+                    if !destinations.all.is_empty() {
+                        cleanup_bot::backup::spawn_worker(
                             Arc::clone(&backup_queue),
                             backup_worker_config,
-                            onedrive_client,
+                            destinations.all.clone(),
+                            Alerter::new(Arc::clone(&http), config_store.clone()),
+                            Arc::clone(&media_index),
                         );
                     }
 
                     // Spawn the cleanup scheduler
-                    spawn_worker(
-                        Arc::clone(&http),
-                        config_store.clone(),
-                        backup_queue,
-                        Arc::clone(&cancellation),
-                    );
+                    spawn_worker(CleanupContext {
+                        alerter: Alerter::new(Arc::clone(&http), config_store.clone()),
+                        storage_full: Arc::new(AtomicBool::new(false)),
+                        http: Arc::clone(&http),
+                        config: config_store.clone(),
+                        backup_queue: Arc::clone(&backup_queue),
+                        cancellation: Arc::clone(&cancellation),
+                        run_log: Arc::clone(&run_log),
+                        encryption: backup_encryption,
+                        media_index,
+                        transcribed,
+                        boundaries: BoundaryCache::default(),
+                    });
 
                     Ok(CommandData {
                         config: config_store,
                         cancellation,
+                        run_log,
+                        backup_queue,
+                        destinations,
+                        encryption: encryption_key,
                     })
                 })
             }
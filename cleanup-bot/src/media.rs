@@ -1,5 +1,10 @@
 pub mod attachment;
 pub mod downloader;
+pub mod index;
+pub mod quota;
+pub mod sidecar;
 
 pub use attachment::*;
 pub use downloader::MediaDownloader;
+pub use index::{IndexedMedia, MediaIndex};
+pub use sidecar::{ChannelContext, MessageAuthor, MessageMetadata};
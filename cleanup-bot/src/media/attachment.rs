@@ -1,38 +1,133 @@
-use serenity::all::Attachment;
+use serenity::all::{Attachment, AttachmentId};
+use tracing::debug;
 
-/// Information about a media attachment that needs to be backed up.
+use crate::config::AttachmentRules;
+
+/// Information about an attachment that needs to be backed up.
 #[derive(Debug, Clone)]
 pub struct MediaAttachment {
+    /// Used to look up a fresh URL when the signed one expires
+    pub id: AttachmentId,
     pub url: String,
     pub filename: String,
+    /// MIME type reported by Discord, or guessed from the file extension
+    pub content_type: String,
+    /// Size in bytes as reported by Discord
+    pub size: u64,
 }
 
 pub trait AttachmentsExt {
-    fn extract_media(&self) -> Vec<MediaAttachment>;
+    fn extract_media(&self, rules: &AttachmentRules) -> Vec<MediaAttachment>;
+}
+
+/// Resolve an attachment's MIME type, without parameters such as `charset`.
+/// Falls back to guessing from the file extension when Discord doesn't report one.
+pub fn content_type(attachment: &Attachment) -> String {
+    match &attachment.content_type {
+        Some(content_type) => content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+        None => guess_content_type(&attachment.filename).to_string(),
+    }
+}
+
+/// Guess a MIME type from common file extensions.
+fn guess_content_type(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "heic" => "image/heic",
+        "mp4" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" | "opus" => "audio/ogg",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "7z" => "application/x-7z-compressed",
+        "gz" => "application/gzip",
+        "json" => "application/json",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        _ => "application/octet-stream",
+    }
 }
 
-/// Check if an attachment is a media file (image or video).
-pub fn is_media(attachment: &Attachment) -> bool {
-    attachment
-        .content_type
-        .as_ref()
-        .map(|content_type| content_type.starts_with("image") || content_type.starts_with("video"))
-        .unwrap_or(false)
+/// Check a MIME type against a pattern: `*`, `type/*` or an exact type.
+fn matches_type(pattern: &str, content_type: &str) -> bool {
+    let pattern = pattern.trim().to_ascii_lowercase();
+
+    if pattern == "*" || pattern == "*/*" {
+        return true;
+    }
+
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => content_type
+            .split_once('/')
+            .is_some_and(|(kind, _)| kind == prefix),
+        None => pattern == content_type,
+    }
+}
+
+/// Check if an attachment should be backed up under the given rules.
+pub fn should_back_up(attachment: &Attachment, rules: &AttachmentRules) -> bool {
+    let content_type = content_type(attachment);
+
+    if rules.deny.iter().any(|p| matches_type(p, &content_type))
+        || !rules.allow.iter().any(|p| matches_type(p, &content_type))
+    {
+        return false;
+    }
+
+    if let Some(max_size) = rules.max_size_bytes
+        && attachment.size as u64 > max_size
+    {
+        debug!(
+            "Not backing up {} ({} bytes, limit {max_size})",
+            attachment.filename, attachment.size
+        );
+        return false;
+    }
+
+    true
 }
 
 impl AttachmentsExt for Vec<Attachment> {
-    /// Extract media attachments from a list of attachments.
-    fn extract_media(&self) -> Vec<MediaAttachment> {
+    /// Extract the attachments to back up from a list of attachments.
+    fn extract_media(&self, rules: &AttachmentRules) -> Vec<MediaAttachment> {
         self.iter()
-            .filter_map(|a| {
-                if is_media(a) {
-                    Some(MediaAttachment {
-                        url: a.url.clone(),
-                        filename: a.filename.clone(),
-                    })
-                } else {
-                    None
-                }
+            .filter(|a| should_back_up(a, rules))
+            .map(|a| MediaAttachment {
+                id: a.id,
+                url: a.url.clone(),
+                filename: a.filename.clone(),
+                content_type: content_type(a),
+                size: a.size as u64,
             })
             .collect()
     }
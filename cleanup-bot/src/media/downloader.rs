@@ -1,62 +1,180 @@
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use chrono::{DateTime, Utc};
 use futures::StreamExt;
-use reqwest::Client;
-use serenity::all::MessageId;
+use reqwest::{Client, StatusCode, Url};
+use serenity::all::Http;
+use sha2::{Digest, Sha256};
+use tokio::time::sleep;
 use tokio::{fs, io::AsyncWriteExt};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
+use crate::crypto::{ENCRYPTED_EXTENSION, EncryptionKey};
+use crate::destination::remote_path;
 use crate::media::MediaAttachment;
+use crate::media::index::{IndexedMedia, MediaIndex};
+use crate::media::sidecar::{MessageMetadata, MessageSidecar, SidecarAttachment};
+
+const DOWNLOAD_ATTEMPTS: u32 = 4;
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(2);
+// Refresh URLs about to expire rather than racing the download against the expiry
+const URL_EXPIRY_MARGIN_SECS: i64 = 60;
 
 /// Downloads media attachments to the local filesystem.
 pub struct MediaDownloader {
     client: Client,
+    /// Used to fetch fresh attachment URLs
+    http: Arc<Http>,
     base_dir: PathBuf,
+    /// Files are encrypted as they are written when set
+    encryption: Option<EncryptionKey>,
+    /// Content already backed up, duplicates aren't kept
+    index: Arc<Mutex<MediaIndex>>,
 }
 
-/// Result of a successful download.
+/// A file to back up.
 #[derive(Debug, Clone)]
 pub struct DownloadResult {
     pub local_path: PathBuf,
     pub filename: String,
+    /// Content to add to the media index once the file is uploaded, unset for sidecars
+    pub content: Option<IndexedMedia>,
+}
+
+/// What became of a downloaded attachment.
+enum StoredAttachment {
+    File(DownloadResult),
+    /// The content was backed up before, so the download was removed again
+    Duplicate {
+        sha256: String,
+        of: String,
+    },
 }
 
 impl MediaDownloader {
-    pub fn new(base_dir: PathBuf) -> Self {
+    pub fn new(
+        http: Arc<Http>,
+        base_dir: PathBuf,
+        encryption: Option<EncryptionKey>,
+        index: Arc<Mutex<MediaIndex>>,
+    ) -> Self {
         Self {
             client: Client::new(),
+            http,
             base_dir,
+            encryption,
+            index,
+        }
+    }
+
+    /// Name a file is stored under, marked if it will be encrypted.
+    fn stored_name(&self, filename: String) -> String {
+        match self.encryption {
+            Some(_) => format!("{filename}.{ENCRYPTED_EXTENSION}"),
+            None => filename,
         }
     }
 
-    /// Download all media attachments for a message.
-    /// Returns the local paths where files were saved.
+    /// Download all media attachments for a message and write its metadata sidecar.
+    /// Returns the files to back up, sidecar last. Attachments already backed up for an earlier
+    /// message are only referenced in the sidecar.
     pub async fn download_attachments(
         &self,
-        message_id: MessageId,
-        timestamp: DateTime<Utc>,
+        message: &MessageMetadata,
         attachments: &[MediaAttachment],
     ) -> Result<Vec<DownloadResult>> {
-        let dir = self.get_download_dir(timestamp);
+        let dir = self.get_download_dir(message.timestamp);
         fs::create_dir_all(&dir)
             .await
             .context("Failed to create download directory")?;
 
-        let mut results = Vec::with_capacity(attachments.len());
+        let mut stored = Vec::with_capacity(attachments.len());
 
         for attachment in attachments {
             let result = self
-                .download_attachment(&dir, message_id, attachment)
+                .download_attachment(&dir, message, attachment)
                 .await
                 .with_context(|| format!("Failed to download {}", attachment.filename))?;
-            results.push(result);
+            stored.push(result);
         }
 
+        let sidecar = self
+            .write_sidecar(&dir, message, attachments, &stored)
+            .await
+            .context("Failed to write metadata sidecar")?;
+
+        let mut results: Vec<_> = stored
+            .into_iter()
+            .filter_map(|s| match s {
+                StoredAttachment::File(result) => Some(result),
+                StoredAttachment::Duplicate { .. } => None,
+            })
+            .collect();
+        results.push(sidecar);
+
         Ok(results)
     }
 
+    /// Write `{message_id}.json` describing the message next to its attachments.
+    async fn write_sidecar(
+        &self,
+        dir: &Path,
+        message: &MessageMetadata,
+        attachments: &[MediaAttachment],
+        stored: &[StoredAttachment],
+    ) -> Result<DownloadResult> {
+        let sidecar = MessageSidecar {
+            message: Cow::Borrowed(message),
+            attachments: attachments
+                .iter()
+                .zip(stored)
+                .map(|(attachment, stored)| {
+                    let (stored_as, duplicate_of, sha256) = match stored {
+                        StoredAttachment::File(download) => (
+                            Some(download.filename.clone()),
+                            None,
+                            download.content.as_ref().map(|c| c.sha256.clone()),
+                        ),
+                        StoredAttachment::Duplicate { sha256, of } => {
+                            (None, Some(of.clone()), Some(sha256.clone()))
+                        }
+                    };
+                    SidecarAttachment {
+                        filename: attachment.filename.clone(),
+                        stored_as,
+                        duplicate_of,
+                        sha256,
+                        content_type: attachment.content_type.clone(),
+                        size: attachment.size,
+                        url: attachment.url.clone(),
+                    }
+                })
+                .collect(),
+        };
+
+        let filename = self.stored_name(format!("{}.json", message.message_id));
+        let path = dir.join(&filename);
+        let mut content = serde_json::to_vec_pretty(&sidecar)?;
+        if let Some(key) = &self.encryption {
+            content = key.encrypt(&content)?;
+        }
+        fs::write(&path, content)
+            .await
+            .context("Failed to write file")?;
+
+        debug!("Wrote metadata sidecar {path:?}");
+
+        Ok(DownloadResult {
+            local_path: path,
+            filename,
+            content: None,
+        })
+    }
+
     /// Get the download directory path for a date.
     /// Format: base_dir/YYYY-MM-DD/
     fn get_download_dir(&self, timestamp: DateTime<Utc>) -> PathBuf {
@@ -64,53 +182,229 @@ impl MediaDownloader {
         self.base_dir.join(date_str)
     }
 
-    /// Download an attachment.
+    /// Download an attachment, hashing it on the way to detect duplicates.
     async fn download_attachment(
         &self,
         dir: &Path,
-        message_id: MessageId,
+        message: &MessageMetadata,
         attachment: &MediaAttachment,
-    ) -> Result<DownloadResult> {
+    ) -> Result<StoredAttachment> {
         // Prefix filename with message ID to avoid collisions
-        let filename = format!("{}_{}", message_id, attachment.filename);
+        let filename = self.stored_name(format!("{}_{}", message.message_id, attachment.filename));
         let path = dir.join(&filename);
 
-        debug!("Downloading {} to {path:?}", attachment.url);
+        let mut url = attachment.url.clone();
+        let mut refreshed = url_expired(&url);
+        if refreshed {
+            debug!("URL of {} has expired, refreshing it", attachment.filename);
+            url = self.refresh_url(message, attachment).await?;
+        }
+
+        let mut attempt = 1;
+        let result = loop {
+            debug!("Downloading {url} to {path:?} (attempt {attempt})");
+
+            match self.fetch(&url, &path, attachment.size).await {
+                Ok(result) => break Ok(result),
+                // The URL may have expired since the message was fetched, that doesn't count as
+                // an attempt. A second rejection means the attachment is really gone.
+                Err(FetchError::Expired(status)) if !refreshed => {
+                    debug!(
+                        "{} was rejected with {status}, refreshing its URL",
+                        attachment.filename
+                    );
+                    refreshed = true;
+                    match self.refresh_url(message, attachment).await {
+                        Ok(fresh) => url = fresh,
+                        Err(e) => break Err(e),
+                    }
+                }
+                Err(FetchError::Transient(e)) if attempt < DOWNLOAD_ATTEMPTS => {
+                    let delay = DOWNLOAD_RETRY_DELAY * 2u32.pow(attempt - 1);
+                    warn!(
+                        "Download of {} failed, retrying in {delay:?}: {e:#}",
+                        attachment.filename
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(FetchError::Expired(status)) => {
+                    break Err(anyhow!(
+                        "Download rejected with {status} after refreshing the URL"
+                    ));
+                }
+                Err(FetchError::Transient(e) | FetchError::Fatal(e)) => break Err(e),
+            }
+        };
+
+        let (bytes_written, sha256) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                let _ = fs::remove_file(&path).await;
+                return Err(e);
+            }
+        };
 
+        let earlier = self
+            .index
+            .lock()
+            .unwrap()
+            .get(&sha256, bytes_written)
+            .map(|e| e.path.clone());
+        if let Some(of) = earlier {
+            fs::remove_file(&path)
+                .await
+                .context("Failed to remove duplicate download")?;
+            info!(
+                "{} is identical to the backup {of}, storing a reference",
+                attachment.filename
+            );
+            return Ok(StoredAttachment::Duplicate { sha256, of });
+        }
+
+        info!(
+            "Downloaded {} ({bytes_written} bytes) to {path:?}",
+            attachment.filename,
+        );
+
+        Ok(StoredAttachment::File(DownloadResult {
+            content: Some(IndexedMedia {
+                sha256,
+                size: bytes_written,
+                path: remote_path(&path),
+            }),
+            local_path: path,
+            filename,
+        }))
+    }
+
+    /// Download `url` into `path` once, encrypting it if enabled, and check the byte count
+    /// against the size Discord reported. Returns the size and the hex SHA-256 of the content.
+    async fn fetch(
+        &self,
+        url: &str,
+        path: &Path,
+        expected_size: u64,
+    ) -> Result<(u64, String), FetchError> {
         let response = self
             .client
-            .get(&attachment.url)
+            .get(url)
             .send()
             .await
-            .context("HTTP request failed")?
-            .error_for_status()
-            .context("HTTP error response")?;
+            .map_err(|e| FetchError::Transient(anyhow!(e).context("HTTP request failed")))?;
+
+        let status = response.status();
+        if status == StatusCode::FORBIDDEN || status == StatusCode::NOT_FOUND {
+            return Err(FetchError::Expired(status));
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status.is_server_error()
+        {
+            return Err(FetchError::Transient(anyhow!(
+                "HTTP error response: {status}"
+            )));
+        }
+        if !status.is_success() {
+            return Err(FetchError::Fatal(anyhow!("HTTP error response: {status}")));
+        }
 
-        let mut file = fs::File::create(&path)
+        let mut file = fs::File::create(path)
             .await
-            .context("Failed to create file")?;
+            .context("Failed to create file")
+            .map_err(FetchError::Fatal)?;
+
+        let mut encryptor = self.encryption.as_ref().map(EncryptionKey::encryptor);
+        if let Some(encryptor) = &encryptor {
+            file.write_all(encryptor.header())
+                .await
+                .context("Failed to write to file")
+                .map_err(FetchError::Fatal)?;
+        }
 
         let mut stream = response.bytes_stream();
         let mut bytes_written: u64 = 0;
+        let mut hasher = Sha256::new();
 
         while let Some(chunk) = stream.next().await {
-            let chunk = chunk.context("Failed to read response chunk")?;
+            let chunk = chunk.map_err(|e| {
+                FetchError::Transient(anyhow!(e).context("Failed to read response chunk"))
+            })?;
+            bytes_written += chunk.len() as u64;
+            hasher.update(&chunk);
+
+            let chunk = match &mut encryptor {
+                Some(encryptor) => encryptor.update(&chunk).map_err(FetchError::Fatal)?.into(),
+                None => chunk,
+            };
             file.write_all(&chunk)
                 .await
-                .context("Failed to write to file")?;
-            bytes_written += chunk.len() as u64;
+                .context("Failed to write to file")
+                .map_err(FetchError::Fatal)?;
         }
 
-        file.flush().await.context("Failed to flush file")?;
+        // A connection dropped mid-body can look like a complete response
+        if bytes_written != expected_size {
+            return Err(FetchError::Transient(anyhow!(
+                "Downloaded {bytes_written} bytes, Discord reported {expected_size}"
+            )));
+        }
 
-        info!(
-            "Downloaded {} ({bytes_written} bytes) to {path:?}",
-            attachment.filename,
-        );
+        if let Some(encryptor) = encryptor {
+            let last = encryptor.finish().map_err(FetchError::Fatal)?;
+            file.write_all(&last)
+                .await
+                .context("Failed to write to file")
+                .map_err(FetchError::Fatal)?;
+        }
 
-        Ok(DownloadResult {
-            local_path: path,
-            filename,
-        })
+        file.flush()
+            .await
+            .context("Failed to flush file")
+            .map_err(FetchError::Fatal)?;
+
+        Ok((bytes_written, format!("{:x}", hasher.finalize())))
     }
+
+    /// Fetch the message again for a freshly signed URL of the attachment.
+    async fn refresh_url(
+        &self,
+        message: &MessageMetadata,
+        attachment: &MediaAttachment,
+    ) -> Result<String> {
+        let fresh = message
+            .channel_id
+            .message(&self.http, message.message_id)
+            .await
+            .context("Failed to fetch the message for a fresh attachment URL")?;
+
+        fresh
+            .attachments
+            .into_iter()
+            .find(|a| a.id == attachment.id)
+            .map(|a| a.url)
+            .with_context(|| format!("{} was removed from the message", attachment.filename))
+    }
+}
+
+/// Why a download attempt failed.
+enum FetchError {
+    /// Rejected by the CDN, usually because the signed URL expired
+    Expired(StatusCode),
+    /// Worth retrying after a pause
+    Transient(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+/// Returns true if a signed Discord CDN URL is past (or about to pass) its `ex` expiry.
+/// URLs without one, e.g. from older messages, are assumed to still work.
+fn url_expired(url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+
+    url.query_pairs()
+        .find(|(key, _)| key == "ex")
+        .and_then(|(_, value)| i64::from_str_radix(&value, 16).ok())
+        .is_some_and(|expires_at| expires_at <= Utc::now().timestamp() + URL_EXPIRY_MARGIN_SECS)
 }
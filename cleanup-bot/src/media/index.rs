@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const MEDIA_INDEX_PATH: &str = "./media_index.jsonl";
+
+/// Backed-up media content, by hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedMedia {
+    /// Hex SHA-256 of the content, before encryption
+    pub sha256: String,
+    pub size: u64,
+    /// Path of the backed-up file relative to the backup root, e.g. `2024/05/01/123_meme.jpg`
+    pub path: String,
+}
+
+/// Index of media content that has been uploaded to every backup destination, so reposts of the
+/// same file are stored as references instead of being uploaded again. Only confirmed uploads are
+/// indexed, so a reference never points at a backup that may still be discarded. Persisted as
+/// JSON lines, synced like the backup journal.
+pub struct MediaIndex {
+    entries: HashMap<String, IndexedMedia>,
+}
+
+impl MediaIndex {
+    /// Load the index from disk, or start an empty one.
+    pub fn load() -> Result<Self> {
+        let Ok(content) = fs::read_to_string(MEDIA_INDEX_PATH) else {
+            return Ok(Self {
+                entries: HashMap::new(),
+            });
+        };
+
+        let mut entries = HashMap::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            match serde_json::from_str::<IndexedMedia>(line) {
+                Ok(entry) => {
+                    entries.insert(entry.sha256.clone(), entry);
+                }
+                // A crash mid-write can leave a truncated last line
+                Err(e) => warn!("Skipping unreadable media index entry: {e}"),
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Find an earlier backup with the same content.
+    pub fn get(&self, sha256: &str, size: u64) -> Option<&IndexedMedia> {
+        self.entries.get(sha256).filter(|e| e.size == size)
+    }
+
+    /// Record backed-up content and persist it. The first backup of some content is kept.
+    pub fn insert(&mut self, entry: IndexedMedia) -> Result<()> {
+        if self.entries.contains_key(&entry.sha256) {
+            return Ok(());
+        }
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(MEDIA_INDEX_PATH)
+            .context(format!("Failed to open {MEDIA_INDEX_PATH}"))?;
+        file.write_all(line.as_bytes())
+            .context("Failed to write media index entry")?;
+        file.sync_data().context("Failed to sync media index")?;
+
+        self.entries.insert(entry.sha256.clone(), entry);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tokio::fs;
+
+use crate::config::MediaBackupConfig;
+
+/// Bytes that may still be downloaded before `download_dir` reaches its quota or the disk drops
+/// below the free space threshold. `None` if neither limit is configured.
+pub async fn download_budget(config: &MediaBackupConfig) -> Result<Option<u64>> {
+    if config.max_download_dir_bytes.is_none() && config.min_free_bytes.is_none() {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(&config.download_dir)
+        .await
+        .context("Failed to create download directory")?;
+
+    let mut budget = u64::MAX;
+
+    if let Some(quota) = config.max_download_dir_bytes {
+        let used = dir_size(&config.download_dir)
+            .await
+            .context("Failed to measure download directory")?;
+        budget = budget.min(quota.saturating_sub(used));
+    }
+
+    if let Some(min_free) = config.min_free_bytes {
+        let free = free_space(&config.download_dir).context("Failed to check free disk space")?;
+        budget = budget.min(free.saturating_sub(min_free));
+    }
+
+    Ok(Some(budget))
+}
+
+/// Total size of the files under `dir`.
+async fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+#[cfg(unix)]
+fn free_space(path: &Path) -> Result<u64> {
+    let stat = nix::sys::statvfs::statvfs(path)?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Free space isn't checked on other platforms, only the quota applies.
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Result<u64> {
+    Ok(u64::MAX)
+}
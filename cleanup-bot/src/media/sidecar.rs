@@ -0,0 +1,76 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, MessageId, User, UserId};
+
+/// Who posted a backed-up message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageAuthor {
+    pub id: UserId,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+impl From<&User> for MessageAuthor {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id,
+            name: user.name.clone(),
+            display_name: user.global_name.clone(),
+        }
+    }
+}
+
+/// Where a backed-up message was posted. Names are looked up once per cleanup run and are
+/// missing if the lookup failed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChannelContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<GuildId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guild_name: Option<String>,
+}
+
+/// Context of a message whose attachments are backed up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    pub message_id: MessageId,
+    pub channel_id: ChannelId,
+    #[serde(flatten)]
+    pub channel: ChannelContext,
+    pub author: MessageAuthor,
+    pub content: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A backed-up attachment, as listed in the sidecar.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SidecarAttachment {
+    pub filename: String,
+    /// Name of the backed-up file next to the sidecar, unset for duplicates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stored_as: Option<String>,
+    /// Earlier backup of the same content, relative to the backup root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+    /// Hex SHA-256 of the content, before encryption
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub content_type: String,
+    pub size: u64,
+    pub url: String,
+}
+
+/// JSON file stored next to a message's backed-up attachments, so the backup is
+/// self-describing after the Discord message is gone. Borrows the message when writing,
+/// owns it when read back for a restore.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageSidecar<'a> {
+    #[serde(flatten)]
+    pub message: Cow<'a, MessageMetadata>,
+    pub attachments: Vec<SidecarAttachment>,
+}
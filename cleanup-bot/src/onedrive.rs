@@ -1,8 +1,10 @@
 mod auth;
 mod client;
+mod hash;
 
-pub use auth::TokenStore;
+pub use auth::{StoredTokens, TokenStore};
 pub use client::OneDriveClient;
+pub use hash::QuickXorHash;
 
 use thiserror::Error;
 
@@ -14,12 +16,21 @@ pub enum OneDriveError {
     #[error("Authentication failed: {0}")]
     Auth(String),
 
+    #[error("Re-authentication required: {0}")]
+    ReauthRequired(String),
+
     #[error("Token storage error: {0}")]
     TokenStorage(String),
 
     #[error("Upload failed: {0}")]
     Upload(String),
 
+    #[error("Download failed: {0}")]
+    Download(String),
+
+    #[error("Verification failed: {0}")]
+    Verification(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -8,9 +8,10 @@ use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
 use super::OneDriveError;
+use crate::crypto::{EncryptionKey, write_private_file};
 
 const TOKENS_PATH: &str = "./onedrive_tokens.toml";
-const AUTH_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0";
+const ENCRYPTED_TOKENS_PATH: &str = "./onedrive_tokens.toml.enc";
 const SCOPES: &str = "Files.ReadWrite offline_access";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -44,32 +45,76 @@ struct ErrorResponse {
 
 pub struct TokenStore {
     client_id: String,
+    auth_url: String,
     http: Client,
     tokens: Option<StoredTokens>,
+    /// Set when there are no tokens or the refresh token was rejected
+    needs_reauth: bool,
+    /// Tokens are stored encrypted when a key is configured
+    key: Option<EncryptionKey>,
 }
 
 impl TokenStore {
-    pub fn new(client_id: String) -> Self {
-        Self {
+    pub fn new(client_id: String, auth_url: String, key: Option<EncryptionKey>) -> Self {
+        let (tokens, plaintext) = Self::load_tokens(key.as_ref());
+        let store = Self {
             client_id,
+            auth_url: auth_url.trim_end_matches('/').to_string(),
             http: Client::new(),
-            tokens: Self::load_tokens(),
+            needs_reauth: tokens.is_none(),
+            tokens,
+            key,
+        };
+
+        // Encrypt tokens saved before a key was configured
+        if plaintext && store.key.is_some() {
+            match store.save_tokens() {
+                Ok(()) => info!("Encrypted stored OneDrive tokens"),
+                Err(e) => warn!("Failed to encrypt stored OneDrive tokens: {e}"),
+            }
         }
-    }
 
-    fn load_tokens() -> Option<StoredTokens> {
-        let path = Path::new(TOKENS_PATH);
+        store
+    }
 
-        match fs::read_to_string(path) {
-            Ok(content) => match toml::from_str(&content) {
-                Ok(tokens) => Some(tokens),
+    /// Load stored tokens, preferring the encrypted file.
+    /// Also returns whether they were read from the plaintext file.
+    fn load_tokens(key: Option<&EncryptionKey>) -> (Option<StoredTokens>, bool) {
+        let encrypted_path = Path::new(ENCRYPTED_TOKENS_PATH);
+
+        if encrypted_path.exists() {
+            let Some(key) = key else {
+                warn!("OneDrive tokens are encrypted but no encryption key is configured");
+                return (None, false);
+            };
+
+            let content = fs::read(encrypted_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| key.decrypt(&content))
+                .and_then(|content| Ok(String::from_utf8(content)?));
+            return match content {
+                Ok(content) => (Self::parse_tokens(&content), false),
                 Err(e) => {
-                    warn!("Failed to parse tokens file: {e}");
-                    None
+                    warn!("Failed to decrypt tokens file: {e:#}");
+                    (None, false)
                 }
-            },
+            };
+        }
+
+        match fs::read_to_string(TOKENS_PATH) {
+            Ok(content) => (Self::parse_tokens(&content), true),
             Err(e) => {
                 warn!("Failed to read tokens file: {e}");
+                (None, false)
+            }
+        }
+    }
+
+    fn parse_tokens(content: &str) -> Option<StoredTokens> {
+        match toml::from_str(content) {
+            Ok(tokens) => Some(tokens),
+            Err(e) => {
+                warn!("Failed to parse tokens file: {e}");
                 None
             }
         }
@@ -83,18 +128,42 @@ impl TokenStore {
         let content = toml::to_string_pretty(tokens)
             .map_err(|e| OneDriveError::TokenStorage(e.to_string()))?;
 
-        fs::write(TOKENS_PATH, content)?;
+        let Some(key) = &self.key else {
+            return write_private_file(Path::new(TOKENS_PATH), content.as_bytes())
+                .map_err(|e| OneDriveError::TokenStorage(format!("{e:#}")));
+        };
+
+        key.encrypt(content.as_bytes())
+            .and_then(|content| write_private_file(Path::new(ENCRYPTED_TOKENS_PATH), &content))
+            .map_err(|e| OneDriveError::TokenStorage(format!("{e:#}")))?;
+
+        // Don't leave a plaintext copy behind
+        match fs::remove_file(TOKENS_PATH) {
+            Ok(()) => info!("Removed plaintext tokens file {TOKENS_PATH}"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove plaintext tokens file {TOKENS_PATH}: {e}"),
+        }
+
         Ok(())
     }
 
-    pub fn has_tokens(&self) -> bool {
-        self.tokens.is_some()
+    /// Returns true if an admin has to authenticate again before uploads can continue.
+    pub fn needs_reauth(&self) -> bool {
+        self.needs_reauth
     }
 
     /// Get a valid access token, refreshing if necessary.
     pub async fn get_valid_token(&mut self) -> Result<String, OneDriveError> {
+        if self.needs_reauth {
+            return Err(OneDriveError::ReauthRequired(
+                "Run /onedrive auth to sign in again".to_string(),
+            ));
+        }
+
         let Some(tokens) = &self.tokens else {
-            return Err(OneDriveError::Auth("No tokens available".to_string()));
+            return Err(OneDriveError::ReauthRequired(
+                "No tokens available".to_string(),
+            ));
         };
 
         // Check if token is expired (with 5 minute buffer)
@@ -109,12 +178,95 @@ impl TokenStore {
         Ok(self.tokens.as_ref().unwrap().access_token.clone())
     }
 
-    /// Perform device code flow for initial authentication.
-    pub async fn device_code_flow(&mut self) -> Result<(), OneDriveError> {
-        // Request device code
+    /// Start authenticating a new account, e.g. after the refresh token was revoked.
+    /// The flow doesn't hold the token store, so uploads aren't blocked while waiting for the user.
+    pub fn device_code_flow(&self) -> DeviceCodeFlow {
+        DeviceCodeFlow {
+            client_id: self.client_id.clone(),
+            auth_url: self.auth_url.clone(),
+            http: self.http.clone(),
+        }
+    }
+
+    /// Store tokens obtained through the device code flow.
+    pub fn set_tokens(&mut self, tokens: StoredTokens) -> Result<(), OneDriveError> {
+        self.tokens = Some(tokens);
+        self.needs_reauth = false;
+        self.save_tokens()
+    }
+
+    /// Refresh the access token using the refresh token.
+    async fn refresh_token(&mut self) -> Result<(), OneDriveError> {
+        let refresh_token = self
+            .tokens
+            .as_ref()
+            .map(|t| t.refresh_token.clone())
+            .ok_or_else(|| OneDriveError::Auth("No refresh token available".to_string()))?;
+
         let resp = self
             .http
-            .post(format!("{AUTH_URL}/devicecode"))
+            .post(format!("{}/token", self.auth_url))
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &refresh_token),
+            ])
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let error: ErrorResponse = resp.json().await?;
+            let description = error.error_description.unwrap_or(error.error.clone());
+
+            // The refresh token expired or was revoked, retrying won't help
+            if matches!(
+                error.error.as_str(),
+                "invalid_grant" | "interaction_required" | "invalid_client"
+            ) {
+                warn!("OneDrive refresh token rejected: {description}");
+                self.needs_reauth = true;
+                return Err(OneDriveError::ReauthRequired(description));
+            }
+
+            return Err(OneDriveError::Auth(description));
+        }
+
+        let token_resp: TokenResponse = resp.json().await?;
+        self.tokens = Some(StoredTokens {
+            access_token: token_resp.access_token,
+            refresh_token: token_resp.refresh_token,
+            expires_at: Utc::now() + chrono::Duration::seconds(token_resp.expires_in),
+        });
+        self.save_tokens()?;
+        debug!("Access token refreshed successfully");
+
+        Ok(())
+    }
+}
+
+/// A device code to show to the user.
+pub struct DeviceCode {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub expires_in: Duration,
+    device_code: String,
+    interval: Duration,
+}
+
+/// The OAuth device code flow, see
+/// <https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-device-code>.
+pub struct DeviceCodeFlow {
+    client_id: String,
+    auth_url: String,
+    http: Client,
+}
+
+impl DeviceCodeFlow {
+    /// Request a device code for the user to enter.
+    pub async fn start(&self) -> Result<DeviceCode, OneDriveError> {
+        let resp = self
+            .http
+            .post(format!("{}/devicecode", self.auth_url))
             .form(&[
                 ("client_id", &self.client_id),
                 ("scope", &SCOPES.to_string()),
@@ -136,38 +288,45 @@ impl TokenStore {
             device_code.verification_uri, device_code.user_code
         );
 
-        // Poll for token
-        let poll_interval = Duration::from_secs(device_code.interval);
-        let deadline = std::time::Instant::now() + Duration::from_secs(device_code.expires_in);
+        Ok(DeviceCode {
+            user_code: device_code.user_code,
+            verification_uri: device_code.verification_uri,
+            expires_in: Duration::from_secs(device_code.expires_in),
+            device_code: device_code.device_code,
+            interval: Duration::from_secs(device_code.interval),
+        })
+    }
+
+    /// Poll until the user has entered the code, or the code expires.
+    pub async fn wait_for_tokens(&self, code: &DeviceCode) -> Result<StoredTokens, OneDriveError> {
+        let deadline = std::time::Instant::now() + code.expires_in;
 
         loop {
             if std::time::Instant::now() > deadline {
                 return Err(OneDriveError::Auth("Device code expired".to_string()));
             }
 
-            tokio::time::sleep(poll_interval).await;
+            tokio::time::sleep(code.interval).await;
 
             let resp = self
                 .http
-                .post(format!("{AUTH_URL}/token"))
+                .post(format!("{}/token", self.auth_url))
                 .form(&[
                     ("client_id", self.client_id.as_str()),
                     ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
-                    ("device_code", &device_code.device_code),
+                    ("device_code", &code.device_code),
                 ])
                 .send()
                 .await?;
 
             if resp.status().is_success() {
                 let token_resp: TokenResponse = resp.json().await?;
-                self.tokens = Some(StoredTokens {
+                info!("OneDrive authentication successful");
+                return Ok(StoredTokens {
                     access_token: token_resp.access_token,
                     refresh_token: token_resp.refresh_token,
                     expires_at: Utc::now() + chrono::Duration::seconds(token_resp.expires_in),
                 });
-                self.save_tokens()?;
-                info!("OneDrive authentication successful");
-                return Ok(());
             }
 
             let error: ErrorResponse = resp.json().await?;
@@ -188,42 +347,4 @@ impl TokenStore {
             }
         }
     }
-
-    /// Refresh the access token using the refresh token.
-    async fn refresh_token(&mut self) -> Result<(), OneDriveError> {
-        let refresh_token = self
-            .tokens
-            .as_ref()
-            .map(|t| t.refresh_token.clone())
-            .ok_or_else(|| OneDriveError::Auth("No refresh token available".to_string()))?;
-
-        let resp = self
-            .http
-            .post(format!("{AUTH_URL}/token"))
-            .form(&[
-                ("client_id", self.client_id.as_str()),
-                ("grant_type", "refresh_token"),
-                ("refresh_token", &refresh_token),
-            ])
-            .send()
-            .await?;
-
-        if !resp.status().is_success() {
-            let error: ErrorResponse = resp.json().await?;
-            return Err(OneDriveError::Auth(
-                error.error_description.unwrap_or(error.error),
-            ));
-        }
-
-        let token_resp: TokenResponse = resp.json().await?;
-        self.tokens = Some(StoredTokens {
-            access_token: token_resp.access_token,
-            refresh_token: token_resp.refresh_token,
-            expires_at: Utc::now() + chrono::Duration::seconds(token_resp.expires_in),
-        });
-        self.save_tokens()?;
-        debug!("Access token refreshed successfully");
-
-        Ok(())
-    }
 }
@@ -1,42 +1,111 @@
+use std::io::SeekFrom;
 use std::path::Path;
 use std::sync::Arc;
 
-use chrono::{Datelike, NaiveDate, Utc};
-use reqwest::Client;
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Client, StatusCode};
 use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use super::OneDriveError;
 use super::auth::TokenStore;
+use super::hash::quick_xor_hash_file;
+use crate::backup::UploadSession;
+use crate::destination::{UploadProgress, remote_path, uri_encode};
 
-const GRAPH_API: &str = "https://graph.microsoft.com/v1.0";
 const SIMPLE_UPLOAD_LIMIT: u64 = 4 * 1024 * 1024; // 4MB
-const CHUNK_SIZE: usize = 10 * 1024 * 1024; // 10MB chunks for resumable upload
+// Chunks must be a multiple of 320 KiB
+const CHUNK_SIZE: u64 = 10 * 1024 * 1024; // 10MB chunks for resumable upload
+const DEFAULT_SESSION_LIFETIME: TimeDelta = TimeDelta::days(1);
+// Don't resume sessions about to expire mid-chunk
+const SESSION_EXPIRY_MARGIN: TimeDelta = TimeDelta::minutes(5);
 
 #[derive(Deserialize)]
-struct UploadSession {
-    #[serde(rename = "uploadUrl")]
-    upload_url: String,
+#[serde(rename_all = "camelCase")]
+struct UploadSessionResponse {
+    upload_url: Option<String>,
+    expiration_date_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    next_expected_ranges: Vec<String>,
+}
+
+impl UploadSessionResponse {
+    /// Start of the first missing byte range, e.g. `"26-"` or `"26-1023"`.
+    fn next_offset(&self) -> Option<u64> {
+        self.next_expected_ranges
+            .first()
+            .and_then(|r| r.split('-').next())
+            .and_then(|start| start.parse().ok())
+    }
+}
+
+#[derive(Deserialize)]
+struct DriveItem {
+    size: u64,
+    file: Option<FileFacet>,
+}
+
+/// A page of a folder listing.
+#[derive(Deserialize)]
+struct Children {
+    value: Vec<ChildItem>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChildItem {
+    name: String,
+    file: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct FileFacet {
+    #[serde(default)]
+    hashes: Hashes,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Hashes {
+    quick_xor_hash: Option<String>,
 }
 
 pub struct OneDriveClient {
     http: Client,
     token_store: Arc<Mutex<TokenStore>>,
     upload_folder: String,
+    graph_api: String,
 }
 
 impl OneDriveClient {
-    pub fn new(token_store: Arc<Mutex<TokenStore>>, upload_folder: String) -> Self {
+    pub fn new(
+        token_store: Arc<Mutex<TokenStore>>,
+        upload_folder: String,
+        graph_api: String,
+    ) -> Self {
         Self {
             http: Client::new(),
             token_store,
             upload_folder,
+            graph_api: graph_api.trim_end_matches('/').to_string(),
         }
     }
 
+    /// Returns true if uploads fail until an admin signs in again.
+    pub async fn needs_reauth(&self) -> bool {
+        self.token_store.lock().await.needs_reauth()
+    }
+
     /// Upload a file to OneDrive. Automatically uses simple or resumable upload based on file size.
-    pub async fn upload_file(&self, local_path: &Path) -> Result<(), OneDriveError> {
+    pub async fn upload_file(
+        &self,
+        local_path: &Path,
+        progress: &UploadProgress<'_>,
+    ) -> Result<(), OneDriveError> {
         let remote_path = self.build_remote_path(local_path);
         let metadata = tokio::fs::metadata(local_path).await?;
         let file_size = metadata.len();
@@ -47,36 +116,123 @@ impl OneDriveClient {
         );
 
         if file_size < SIMPLE_UPLOAD_LIMIT {
-            self.simple_upload(local_path, &remote_path).await
+            self.simple_upload(local_path, &remote_path, progress)
+                .await?;
         } else {
-            self.resumable_upload(local_path, &remote_path, file_size)
-                .await
+            self.resumable_upload(local_path, &remote_path, file_size, progress)
+                .await?;
         }
+
+        self.verify_upload(local_path, &remote_path, file_size)
+            .await
     }
 
-    /// Build the remote path with date-based organization.
-    /// Extracts the date from the parent directory name (format: YYYY-MM-DD).
-    fn build_remote_path(&self, local_path: &Path) -> String {
-        let file_name = local_path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown");
-
-        // Extract date from parent directory name (format: YYYY-MM-DD)
-        let (year, month, day) = local_path
-            .parent()
-            .and_then(|p| p.file_name())
-            .and_then(|n| n.to_str())
-            .and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
-            .map(|d| (d.year(), d.month(), d.day()))
-            .unwrap_or_else(|| {
-                let now = Utc::now();
-                (now.year(), now.month(), now.day())
-            });
+    /// Read back the uploaded item and compare its size and QuickXorHash with the local file,
+    /// so a truncated or corrupted upload is never mistaken for a complete one.
+    async fn verify_upload(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        file_size: u64,
+    ) -> Result<(), OneDriveError> {
+        let token = self.token_store.lock().await.get_valid_token().await?;
+        let url = self.item_url(remote_path, "?$select=size,file");
+
+        let resp = self.http.get(&url).bearer_auth(&token).send().await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(OneDriveError::Verification(format!(
+                "Failed to read back {remote_path}: {status}: {body}"
+            )));
+        }
+
+        let item: DriveItem = resp.json().await?;
+
+        if item.size != file_size {
+            return Err(OneDriveError::Verification(format!(
+                "{remote_path} is {} bytes, expected {file_size}",
+                item.size
+            )));
+        }
+
+        let Some(remote_hash) = item.file.and_then(|f| f.hashes.quick_xor_hash) else {
+            warn!("OneDrive reported no hash for {remote_path}, verified size only");
+            return Ok(());
+        };
+
+        let local_hash = quick_xor_hash_file(local_path).await?;
+        if remote_hash != local_hash {
+            return Err(OneDriveError::Verification(format!(
+                "{remote_path} has hash {remote_hash}, expected {local_hash}"
+            )));
+        }
+
+        debug!("Verified {remote_path} ({file_size} bytes, hash {local_hash})");
+        Ok(())
+    }
+
+    /// Names of the files in a folder given relative to the upload folder.
+    pub async fn list_folder(&self, dir: &str) -> Result<Vec<String>, OneDriveError> {
+        let token = self.token_store.lock().await.get_valid_token().await?;
+        let folder = format!("{}/{dir}", self.upload_folder.trim_end_matches('/'));
+        let mut url = Some(self.item_url(&folder, ":/children?$select=name,file"));
+        let mut names = Vec::new();
+
+        while let Some(page_url) = url {
+            let resp = self.http.get(&page_url).bearer_auth(&token).send().await?;
+
+            if resp.status() == StatusCode::NOT_FOUND {
+                return Ok(Vec::new());
+            }
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                return Err(OneDriveError::Download(format!(
+                    "Failed to list {dir}: {status}: {body}"
+                )));
+            }
 
+            let page: Children = resp.json().await?;
+            names.extend(
+                page.value
+                    .into_iter()
+                    .filter(|item| item.file.is_some())
+                    .map(|item| item.name),
+            );
+            url = page.next_link;
+        }
+
+        Ok(names)
+    }
+
+    /// Download a file given relative to the upload folder. Graph redirects to a
+    /// pre-authenticated URL, which the client follows.
+    pub async fn download_file(&self, path: &str) -> Result<reqwest::Response, OneDriveError> {
+        let token = self.token_store.lock().await.get_valid_token().await?;
+        let path = format!("{}/{path}", self.upload_folder.trim_end_matches('/'));
+        let url = self.item_url(&path, ":/content");
+
+        Ok(self.http.get(&url).bearer_auth(&token).send().await?)
+    }
+
+    /// Graph URL of the item at `path`, followed by `suffix`. Attachment names may contain
+    /// characters like `#` or `?`, so the path is percent-encoded.
+    fn item_url(&self, path: &str, suffix: &str) -> String {
         format!(
-            "{}/{year:04}/{month:02}/{day:02}/{file_name}",
+            "{}/me/drive/root:{}{suffix}",
+            self.graph_api,
+            uri_encode(path, true)
+        )
+    }
+
+    /// Build the remote path with date-based organization inside the upload folder.
+    fn build_remote_path(&self, local_path: &Path) -> String {
+        format!(
+            "{}/{}",
             self.upload_folder.trim_end_matches('/'),
+            remote_path(local_path)
         )
     }
 
@@ -85,18 +241,20 @@ impl OneDriveClient {
         &self,
         local_path: &Path,
         remote_path: &str,
+        progress: &UploadProgress<'_>,
     ) -> Result<(), OneDriveError> {
         let token = self.token_store.lock().await.get_valid_token().await?;
         let content = tokio::fs::read(local_path).await?;
 
-        let url = format!("{GRAPH_API}/me/drive/root:{remote_path}:/content");
+        let url = self.item_url(remote_path, ":/content");
 
         let resp = self
             .http
             .put(&url)
             .bearer_auth(&token)
             .header("Content-Type", "application/octet-stream")
-            .body(content)
+            .header(CONTENT_LENGTH, content.len())
+            .body(progress.body(content))
             .send()
             .await?;
 
@@ -112,17 +270,97 @@ impl OneDriveClient {
         Ok(())
     }
 
-    /// Resumable upload for files >= 4MB.
+    /// Resumable upload for files >= 4MB. Chunks are streamed from disk, and the session is
+    /// saved after every chunk so an interrupted upload resumes at the next expected byte.
     async fn resumable_upload(
         &self,
         local_path: &Path,
         remote_path: &str,
-        _file_size: u64,
+        file_size: u64,
+        progress: &UploadProgress<'_>,
     ) -> Result<(), OneDriveError> {
+        let mut session = match self.resume_session(progress).await? {
+            Some(session) => {
+                info!(
+                    "Resuming upload of {remote_path} at byte {}",
+                    session.next_offset
+                );
+                session
+            }
+            None => self.create_upload_session(remote_path, progress).await?,
+        };
+        progress.save_session(Some(session.clone()));
+
+        let mut file = tokio::fs::File::open(local_path).await?;
+
+        loop {
+            let start = session.next_offset;
+            if start >= file_size {
+                progress.save_session(None);
+                return Err(OneDriveError::Upload(format!(
+                    "Upload session expects byte {start} of a {file_size} byte file"
+                )));
+            }
+
+            let len = (file_size - start).min(CHUNK_SIZE);
+            let end = start + len - 1;
+            let content_range = format!("bytes {start}-{end}/{file_size}");
+
+            file.seek(SeekFrom::Start(start)).await?;
+            let mut chunk = vec![0; len as usize];
+            file.read_exact(&mut chunk).await?;
+
+            debug!("Uploading chunk {content_range}");
+
+            let resp = self
+                .http
+                .put(&session.url)
+                .header("Content-Range", &content_range)
+                .header(CONTENT_LENGTH, len)
+                .body(progress.body(chunk))
+                .send()
+                .await?;
+
+            match resp.status() {
+                StatusCode::OK | StatusCode::CREATED => {
+                    progress.save_session(None);
+                    debug!("Resumable upload completed for {remote_path}");
+                    return Ok(());
+                }
+                StatusCode::ACCEPTED => {
+                    let status: UploadSessionResponse = resp.json().await?;
+                    session.next_offset = status.next_offset().unwrap_or(end + 1);
+                    if let Some(expires_at) = status.expiration_date_time {
+                        session.expires_at = expires_at;
+                    }
+                    progress.save_session(Some(session.clone()));
+                }
+                StatusCode::NOT_FOUND => {
+                    // The session expired or was cancelled, the next attempt starts a new one
+                    progress.save_session(None);
+                    return Err(OneDriveError::Upload(
+                        "Upload session no longer exists".to_string(),
+                    ));
+                }
+                status => {
+                    let body = resp.text().await.unwrap_or_default();
+                    return Err(OneDriveError::Upload(format!(
+                        "Chunk upload failed: {status}: {body}"
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Create an upload session for a file.
+    async fn create_upload_session(
+        &self,
+        remote_path: &str,
+        progress: &UploadProgress<'_>,
+    ) -> Result<UploadSession, OneDriveError> {
         let token = self.token_store.lock().await.get_valid_token().await?;
 
-        // Create upload session
-        let url = format!("{GRAPH_API}/me/drive/root:{remote_path}:/createUploadSession");
+        let url = self.item_url(remote_path, ":/createUploadSession");
         let body = serde_json::json!({
             "item": {
                 "@microsoft.graph.conflictBehavior": "replace"
@@ -145,38 +383,65 @@ impl OneDriveClient {
             )));
         }
 
-        let session: UploadSession = resp.json().await?;
+        let session: UploadSessionResponse = resp.json().await?;
+        let url = session.upload_url.ok_or_else(|| {
+            OneDriveError::Upload("Upload session response has no upload URL".to_string())
+        })?;
         debug!("Created upload session for {remote_path}");
 
-        // Read file and upload in chunks
-        let content = tokio::fs::read(local_path).await?;
-        let total_size = content.len();
+        Ok(UploadSession {
+            destination: progress.destination().to_string(),
+            url,
+            next_offset: 0,
+            expires_at: session
+                .expiration_date_time
+                .unwrap_or_else(|| Utc::now() + DEFAULT_SESSION_LIFETIME),
+        })
+    }
 
-        for (chunk_num, chunk) in content.chunks(CHUNK_SIZE).enumerate() {
-            let start = chunk_num * CHUNK_SIZE;
-            let end = start + chunk.len() - 1;
-            let content_range = format!("bytes {start}-{end}/{total_size}");
+    /// Ask the server where a saved session left off. Returns `None` if there is no usable
+    /// session and a new one has to be created.
+    async fn resume_session(
+        &self,
+        progress: &UploadProgress<'_>,
+    ) -> Result<Option<UploadSession>, OneDriveError> {
+        let Some(session) = progress.session() else {
+            return Ok(None);
+        };
 
-            debug!("Uploading chunk {}: {content_range}", chunk_num + 1);
+        if session.expires_at <= Utc::now() + SESSION_EXPIRY_MARGIN {
+            debug!("Saved upload session expired at {}", session.expires_at);
+            progress.save_session(None);
+            return Ok(None);
+        }
 
-            let resp = self
-                .http
-                .put(&session.upload_url)
-                .header("Content-Range", &content_range)
-                .body(chunk.to_vec())
-                .send()
-                .await?;
+        let resp = self.http.get(&session.url).send().await?;
 
-            if !resp.status().is_success() && resp.status().as_u16() != 202 {
-                let status = resp.status();
-                let body = resp.text().await.unwrap_or_default();
-                return Err(OneDriveError::Upload(format!(
-                    "Chunk upload failed: {status}: {body}"
-                )));
-            }
+        if resp.status() == StatusCode::NOT_FOUND {
+            debug!("Saved upload session no longer exists");
+            progress.save_session(None);
+            return Ok(None);
         }
 
-        debug!("Resumable upload completed for {remote_path}");
-        Ok(())
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(OneDriveError::Upload(format!(
+                "Failed to query upload session: {status}: {body}"
+            )));
+        }
+
+        // The server's view is authoritative: a chunk may have landed after the last save
+        let status: UploadSessionResponse = resp.json().await?;
+        let Some(next_offset) = status.next_offset() else {
+            progress.save_session(None);
+            return Ok(None);
+        };
+
+        Ok(Some(UploadSession {
+            next_offset,
+            expires_at: status.expiration_date_time.unwrap_or(session.expires_at),
+            ..session
+        }))
     }
 }
@@ -0,0 +1,154 @@
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use tokio::io::AsyncReadExt;
+
+const WIDTH_IN_BITS: usize = 160;
+const SHIFT: usize = 11;
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// OneDrive's QuickXorHash, see
+/// <https://learn.microsoft.com/en-us/onedrive/developer/code-snippets/quickxorhash>.
+#[derive(Default)]
+pub struct QuickXorHash {
+    data: [u64; 3],
+    shift_so_far: usize,
+    length_so_far: u64,
+}
+
+impl QuickXorHash {
+    pub fn new() -> Self {
+        Self {
+            data: [0; 3],
+            shift_so_far: 0,
+            length_so_far: 0,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut vector_index = self.shift_so_far / 64;
+        let mut vector_offset = self.shift_so_far % 64;
+        let iterations = bytes.len().min(WIDTH_IN_BITS);
+
+        for i in 0..iterations {
+            let is_last_cell = vector_index == self.data.len() - 1;
+            let bits_in_cell = if is_last_cell { 32 } else { 64 };
+
+            let xored = bytes
+                .iter()
+                .skip(i)
+                .step_by(WIDTH_IN_BITS)
+                .fold(0u8, |acc, b| acc ^ b) as u64;
+
+            if vector_offset <= bits_in_cell - 8 {
+                self.data[vector_index] ^= xored << vector_offset;
+            } else {
+                let next_index = if is_last_cell { 0 } else { vector_index + 1 };
+                let low = bits_in_cell - vector_offset;
+                self.data[vector_index] ^= xored << vector_offset;
+                self.data[next_index] ^= xored >> low;
+            }
+
+            vector_offset += SHIFT;
+            while vector_offset >= bits_in_cell {
+                vector_index = if is_last_cell { 0 } else { vector_index + 1 };
+                vector_offset -= bits_in_cell;
+            }
+        }
+
+        self.shift_so_far =
+            (self.shift_so_far + SHIFT * (bytes.len() % WIDTH_IN_BITS)) % WIDTH_IN_BITS;
+        self.length_so_far += bytes.len() as u64;
+    }
+
+    /// The base64-encoded hash, as reported by the Graph API.
+    pub fn finish(&self) -> String {
+        let mut hash = [0u8; WIDTH_IN_BITS / 8];
+        hash[..8].copy_from_slice(&self.data[0].to_le_bytes());
+        hash[8..16].copy_from_slice(&self.data[1].to_le_bytes());
+        hash[16..].copy_from_slice(&self.data[2].to_le_bytes()[..4]);
+
+        for (i, b) in self.length_so_far.to_le_bytes().iter().enumerate() {
+            hash[WIDTH_IN_BITS / 8 - 8 + i] ^= b;
+        }
+
+        STANDARD.encode(hash)
+    }
+}
+
+/// Compute the QuickXorHash of a file, reading it from disk in chunks.
+pub async fn quick_xor_hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut hash = QuickXorHash::new();
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buffer[..read]);
+    }
+
+    Ok(hash.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected hashes come from a port of Microsoft's C# reference implementation
+
+    fn hash(parts: &[&[u8]]) -> String {
+        let mut hash = QuickXorHash::new();
+        for part in parts {
+            hash.update(part);
+        }
+        hash.finish()
+    }
+
+    /// Bytes that don't repeat within a 160-byte block.
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(hash(&[]), "AAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+    }
+
+    #[test]
+    fn shorter_than_a_block() {
+        assert_eq!(hash(&[b"Hello, World!"]), "SCgDG9jwBhaA4ApvnQMbyBACAAA=");
+    }
+
+    #[test]
+    fn several_blocks() {
+        let data = pattern(1000);
+        assert_eq!(hash(&[&data]), "egLp3hU8LkfrSX0d1jXwNKIbDmw=");
+    }
+
+    #[test]
+    fn several_blocks_split_across_updates() {
+        let data = pattern(1000);
+        assert_eq!(
+            hash(&[&data[..7], &data[7..400], &data[400..]]),
+            "egLp3hU8LkfrSX0d1jXwNKIbDmw="
+        );
+    }
+
+    #[tokio::test]
+    async fn file_larger_than_read_buffer() {
+        let data: Vec<u8> = (0..3 * READ_BUFFER_SIZE + 17)
+            .map(|i| ((i * 7 + 3) % 256) as u8)
+            .collect();
+        let path = std::env::temp_dir().join(format!("quickxorhash-{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let result = quick_xor_hash_file(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap(), "svBq9WiUc25+kb6Gb/tm5bxk+OA=");
+    }
+}
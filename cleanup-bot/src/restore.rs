@@ -0,0 +1,418 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use serenity::all::{
+    ChannelId, CreateAllowedMentions, CreateAttachment, CreateMessage, Http, Mentionable,
+    MessageId, UserId,
+};
+use tokio::fs;
+use tracing::debug;
+
+use crate::cleanup::archive::ArchivedMessage;
+use crate::command::truncate_message;
+use crate::crypto::{ENCRYPTED_EXTENSION, EncryptionKey, KEY_ENV, is_encrypted};
+use crate::destination::{BackupDestination, remote_dir};
+use crate::media::sidecar::MessageSidecar;
+
+/// Longest date range searched by one restore, every day is a separate listing.
+pub const MAX_RESTORE_DAYS: i64 = 31;
+
+/// Which backed-up messages to restore.
+#[derive(Debug, Clone)]
+pub struct RestoreQuery {
+    /// First day to search, by the UTC date messages were posted
+    pub from: NaiveDate,
+    /// Last day to search, inclusive
+    pub until: NaiveDate,
+    pub channel_id: Option<ChannelId>,
+    pub message_id: Option<MessageId>,
+}
+
+impl RestoreQuery {
+    /// Build a query from user input: a message ID, or a first day with an optional last day,
+    /// both `YYYY-MM-DD`.
+    pub fn parse(
+        message_id: Option<&str>,
+        from: Option<&str>,
+        until: Option<&str>,
+        channel_id: Option<ChannelId>,
+    ) -> Result<Self> {
+        let parse_date = |date: &str| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .with_context(|| format!("`{date}` isn't a date like 2024-05-31"))
+        };
+
+        match (message_id, from) {
+            (Some(message_id), _) => message_id
+                .parse::<u64>()
+                .ok()
+                .filter(|&id| id != 0)
+                .map(|id| Self::message(MessageId::new(id)))
+                .with_context(|| format!("`{message_id}` isn't a message ID")),
+            (None, Some(from)) => {
+                let from = parse_date(from)?;
+                let until = until.map(parse_date).transpose()?.unwrap_or(from);
+                Self::dates(from, until, channel_id)
+            }
+            (None, None) => bail!("Give a message ID or a first day to restore"),
+        }
+    }
+
+    /// Messages posted between two days, optionally only from one channel.
+    pub fn dates(from: NaiveDate, until: NaiveDate, channel_id: Option<ChannelId>) -> Result<Self> {
+        if until < from {
+            bail!("The end date is before the start date");
+        }
+        if (until - from).num_days() >= MAX_RESTORE_DAYS {
+            bail!("Restore at most {MAX_RESTORE_DAYS} days at a time");
+        }
+
+        Ok(Self {
+            from,
+            until,
+            channel_id,
+            message_id: None,
+        })
+    }
+
+    /// A single message, searched for on the day its ID was created.
+    pub fn message(message_id: MessageId) -> Self {
+        let date = message_id.created_at().date_naive();
+        Self {
+            from: date,
+            until: date,
+            channel_id: None,
+            message_id: Some(message_id),
+        }
+    }
+
+    fn matches(&self, id: MessageId, channel_id: ChannelId, timestamp: DateTime<Utc>) -> bool {
+        let date = timestamp.date_naive();
+        self.message_id.is_none_or(|m| m == id)
+            && self.channel_id.is_none_or(|c| c == channel_id)
+            && (self.from..=self.until).contains(&date)
+    }
+}
+
+/// A message found in the backups.
+#[derive(Debug, Serialize)]
+pub struct RestoredMessage {
+    pub id: MessageId,
+    pub channel_id: ChannelId,
+    pub author_id: UserId,
+    pub author: String,
+    pub content: String,
+    pub timestamp: DateTime<Utc>,
+    pub files: Vec<RestoredFile>,
+    /// Attachments that weren't backed up, only their names are known
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_files: Vec<String>,
+}
+
+/// A backed-up attachment of a restored message.
+#[derive(Debug, Serialize)]
+pub struct RestoredFile {
+    pub filename: String,
+    /// Path relative to the destination's root
+    #[serde(skip)]
+    pub remote_path: String,
+}
+
+/// Finds backed-up messages in a destination and fetches their files.
+pub struct Restorer<'a> {
+    destination: &'a dyn BackupDestination,
+    encryption: Option<&'a EncryptionKey>,
+    /// Downloads are staged here until they are decrypted and used
+    work_dir: PathBuf,
+}
+
+impl<'a> Restorer<'a> {
+    pub fn new(
+        destination: &'a dyn BackupDestination,
+        encryption: Option<&'a EncryptionKey>,
+    ) -> Self {
+        let work_dir = std::env::temp_dir().join(format!(
+            "cleanup-bot-restore-{:016x}",
+            rand::random::<u64>()
+        ));
+
+        Self {
+            destination,
+            encryption,
+            work_dir,
+        }
+    }
+
+    /// Find matching messages, oldest first. Media messages are read from their sidecars and
+    /// text messages from JSON lines transcripts, HTML transcripts can't be read back.
+    pub async fn find(&self, query: &RestoreQuery) -> Result<Vec<RestoredMessage>> {
+        fs::create_dir_all(&self.work_dir)
+            .await
+            .context("Failed to create restore directory")?;
+
+        let mut messages = Vec::new();
+        for date in query.from.iter_days().take_while(|d| *d <= query.until) {
+            self.find_on(date, query, &mut messages)
+                .await
+                .with_context(|| format!("Failed to search backups from {date}"))?;
+        }
+
+        messages.sort_by_key(|m| m.id);
+        Ok(messages)
+    }
+
+    async fn find_on(
+        &self,
+        date: NaiveDate,
+        query: &RestoreQuery,
+        messages: &mut Vec<RestoredMessage>,
+    ) -> Result<()> {
+        let dir = remote_dir(date);
+        let names = self.destination.list(&dir).await?;
+        let mut found = HashSet::new();
+
+        for name in &names {
+            let Some(id) = sidecar_message_id(name) else {
+                continue;
+            };
+            if query.message_id.is_some_and(|m| m != id) {
+                continue;
+            }
+
+            let content = self.fetch_bytes(&format!("{dir}/{name}")).await?;
+            let sidecar: MessageSidecar = serde_json::from_slice(&content)
+                .with_context(|| format!("Invalid sidecar {name}"))?;
+            let message = sidecar.message.into_owned();
+            if !query.matches(message.message_id, message.channel_id, message.timestamp) {
+                continue;
+            }
+
+            // Duplicates refer to an earlier message's backup of the same content
+            let mut files = Vec::new();
+            let mut missing_files = Vec::new();
+            for attachment in sidecar.attachments {
+                match (attachment.duplicate_of, attachment.stored_as) {
+                    (Some(path), _) => files.push(RestoredFile {
+                        filename: attachment.filename,
+                        remote_path: path,
+                    }),
+                    (None, Some(stored_as)) => files.push(RestoredFile {
+                        filename: attachment.filename,
+                        remote_path: format!("{dir}/{stored_as}"),
+                    }),
+                    (None, None) => missing_files.push(attachment.filename),
+                }
+            }
+
+            found.insert(message.message_id);
+            messages.push(RestoredMessage {
+                id: message.message_id,
+                channel_id: message.channel_id,
+                author_id: message.author.id,
+                author: message.author.display_name.unwrap_or(message.author.name),
+                content: message.content,
+                timestamp: message.timestamp,
+                files,
+                missing_files,
+            });
+        }
+
+        for name in &names {
+            let Some((channel_id, first, last)) = transcript_range(name) else {
+                continue;
+            };
+            if query.channel_id.is_some_and(|c| c != channel_id)
+                || query.message_id.is_some_and(|m| m < first || m > last)
+            {
+                continue;
+            }
+
+            let content = self.fetch_bytes(&format!("{dir}/{name}")).await?;
+            let content = std::str::from_utf8(&content)
+                .with_context(|| format!("Transcript {name} is not valid UTF-8"))?;
+
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                let archived: ArchivedMessage = serde_json::from_str(line)
+                    .with_context(|| format!("Invalid message in transcript {name}"))?;
+
+                // Media messages are in transcripts too, their sidecars list the files
+                if found.contains(&archived.id)
+                    || !query.matches(archived.id, archived.channel_id, archived.timestamp)
+                {
+                    continue;
+                }
+
+                found.insert(archived.id);
+                messages.push(RestoredMessage {
+                    id: archived.id,
+                    channel_id: archived.channel_id,
+                    author_id: archived.author.id,
+                    author: archived.author.display_name.unwrap_or(archived.author.name),
+                    content: archived.content,
+                    timestamp: archived.timestamp,
+                    files: Vec::new(),
+                    missing_files: archived
+                        .attachments
+                        .into_iter()
+                        .map(|a| a.filename)
+                        .collect(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Download a small file, such as a sidecar, and decrypt it if needed.
+    async fn fetch_bytes(&self, remote_path: &str) -> Result<Vec<u8>> {
+        let staged = self
+            .work_dir
+            .join(format!("{:016x}", rand::random::<u64>()));
+        let result = self.destination.download(remote_path, &staged).await;
+        let content = match result {
+            Ok(()) => fs::read(&staged).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        let _ = fs::remove_file(&staged).await;
+        let content = content.with_context(|| format!("Failed to download {remote_path}"))?;
+
+        if !is_encrypted(Path::new(remote_path)) {
+            return Ok(content);
+        }
+        self.key(remote_path)?.decrypt(&content)
+    }
+
+    /// Download a backed-up file to `local_path`, decrypting it if needed.
+    async fn fetch(&self, file: &RestoredFile, local_path: &Path) -> Result<()> {
+        debug!("Restoring {} to {}", file.remote_path, local_path.display());
+
+        if !is_encrypted(Path::new(&file.remote_path)) {
+            return self
+                .destination
+                .download(&file.remote_path, local_path)
+                .await
+                .with_context(|| format!("Failed to download {}", file.remote_path));
+        }
+
+        let key = self.key(&file.remote_path)?;
+        let staged = self.work_dir.join(format!(
+            "{:016x}.{ENCRYPTED_EXTENSION}",
+            rand::random::<u64>()
+        ));
+        let result = match self.destination.download(&file.remote_path, &staged).await {
+            Ok(()) => key.decrypt_file(&staged, local_path).await,
+            Err(e) => Err(e),
+        };
+        let _ = fs::remove_file(&staged).await;
+        result.with_context(|| format!("Failed to restore {}", file.remote_path))
+    }
+
+    fn key(&self, remote_path: &str) -> Result<&EncryptionKey> {
+        self.encryption
+            .with_context(|| format!("{remote_path} is encrypted, but {KEY_ENV} is not set"))
+    }
+
+    /// Post a message with its backed-up files to `target`, attributed to its original author.
+    pub async fn repost(
+        &self,
+        http: &Http,
+        target: ChannelId,
+        message: &RestoredMessage,
+    ) -> Result<()> {
+        let mut attachments = Vec::with_capacity(message.files.len());
+        for (i, file) in message.files.iter().enumerate() {
+            let path = self.work_dir.join(format!("{}_{i}", message.id));
+            let data = match self.fetch(file, &path).await {
+                Ok(()) => fs::read(&path).await.map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            let _ = fs::remove_file(&path).await;
+            attachments.push(CreateAttachment::bytes(data?, file.filename.clone()));
+        }
+
+        let mut content = format!(
+            "**{}** ({}) · <t:{}:f>\n{}",
+            message.author,
+            message.author_id.mention(),
+            message.timestamp.timestamp(),
+            message.content
+        );
+        if !message.missing_files.is_empty() {
+            content.push_str(&format!(
+                "\n_Not backed up: {}_",
+                message.missing_files.join(", ")
+            ));
+        }
+
+        // Restored messages mention their authors for attribution, without pinging them
+        let post = CreateMessage::new()
+            .content(truncate_message(content))
+            .add_files(attachments)
+            .allowed_mentions(CreateAllowedMentions::new());
+        target
+            .send_message(http, post)
+            .await
+            .with_context(|| format!("Failed to repost message {}", message.id))?;
+
+        Ok(())
+    }
+
+    /// Save a message's backed-up files and a JSON description of it under
+    /// `output_dir/YYYY-MM-DD/`.
+    pub async fn save(&self, message: &RestoredMessage, output_dir: &Path) -> Result<()> {
+        let dir = output_dir.join(message.timestamp.format("%Y-%m-%d").to_string());
+        fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        for file in &message.files {
+            let filename = file.filename.replace(['/', '\\'], "_");
+            self.fetch(file, &dir.join(format!("{}_{filename}", message.id)))
+                .await?;
+        }
+
+        fs::write(
+            dir.join(format!("{}.json", message.id)),
+            serde_json::to_vec_pretty(message)?,
+        )
+        .await
+        .context("Failed to write message description")?;
+
+        Ok(())
+    }
+
+    /// Remove anything left in the staging directory.
+    pub async fn finish(self) {
+        let _ = fs::remove_dir_all(&self.work_dir).await;
+    }
+}
+
+/// Message ID of a sidecar named `{message_id}.json`, optionally encrypted.
+fn sidecar_message_id(name: &str) -> Option<MessageId> {
+    let name = strip_encrypted(name);
+    let id: u64 = name.strip_suffix(".json")?.parse().ok()?;
+    (id != 0).then(|| MessageId::new(id))
+}
+
+/// Channel and first and last message IDs of a transcript named `{channel}_{first}-{last}.jsonl`,
+/// optionally encrypted.
+fn transcript_range(name: &str) -> Option<(ChannelId, MessageId, MessageId)> {
+    let name = strip_encrypted(name).strip_suffix(".jsonl")?;
+    let (channel_id, range) = name.split_once('_')?;
+    let (first, last) = range.split_once('-')?;
+
+    let id = |s: &str| s.parse::<u64>().ok().filter(|&id| id != 0);
+    Some((
+        ChannelId::new(id(channel_id)?),
+        MessageId::new(id(first)?),
+        MessageId::new(id(last)?),
+    ))
+}
+
+fn strip_encrypted(name: &str) -> &str {
+    name.strip_suffix(&format!(".{ENCRYPTED_EXTENSION}"))
+        .unwrap_or(name)
+}
@@ -0,0 +1,210 @@
+//! Local stand-ins for the services backups are uploaded to, for integration tests and for
+//! trying the bot without live accounts. They keep everything in memory and speak just enough
+//! HTTP/1.1 for the bot's client: one request per connection, no chunked bodies.
+
+pub mod onedrive;
+pub mod s3;
+pub mod webdav;
+
+pub use onedrive::OneDriveStandIn;
+pub use s3::S3StandIn;
+pub use webdav::WebDavStandIn;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+struct Request {
+    method: String,
+    /// Path and query, e.g. `/v1.0/me/drive/root:/a.jpg?$select=size`
+    path: String,
+    /// Headers by lowercase name
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, value: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn bytes(status: u16, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type: "application/octet-stream",
+            body,
+        }
+    }
+
+    fn xml(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "application/xml; charset=utf-8",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn empty(status: u16) -> Self {
+        Self::bytes(status, Vec::new())
+    }
+}
+
+/// Bind `address` and answer every request with `handle`. Returns the base URL, e.g.
+/// `http://127.0.0.1:8787`. Binding port 0 picks a free port.
+async fn serve<H>(address: &str, name: &'static str, handle: H) -> Result<String>
+where
+    H: Fn(&Request, &str) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to bind {address}"))?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    let handle = Arc::new(handle);
+
+    tokio::spawn({
+        let base_url = base_url.clone();
+        async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("{name} stand-in failed to accept a connection: {e}");
+                        continue;
+                    }
+                };
+                let handle = Arc::clone(&handle);
+                let base_url = base_url.clone();
+
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, name, &base_url, &*handle).await {
+                        warn!("{name} stand-in connection error: {e:#}");
+                    }
+                });
+            }
+        }
+    });
+
+    Ok(base_url)
+}
+
+async fn handle_connection<H>(
+    stream: TcpStream,
+    name: &str,
+    base_url: &str,
+    handle: &H,
+) -> Result<()>
+where
+    H: Fn(&Request, &str) -> Response,
+{
+    let mut stream = BufReader::new(stream);
+    let request = read_request(&mut stream).await?;
+    let response = handle(&request, base_url);
+
+    info!(
+        "{name} stand-in: {} {} -> {} ({} bytes in)",
+        request.method,
+        request.path,
+        response.status,
+        request.body.len()
+    );
+
+    let head = format!(
+        "HTTP/1.1 {} Stand-in\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    let stream = stream.get_mut();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Request> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let byte = stream.read_u8().await.context("Connection closed")?;
+        head.push(byte);
+    }
+
+    let head = String::from_utf8(head)?;
+    let mut lines = head.lines();
+    let mut request_line = lines.next().context("Empty request")?.split(' ');
+    let method = request_line.next().context("No method")?.to_string();
+    let path = request_line.next().context("No path")?.to_string();
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+
+    if headers
+        .get("transfer-encoding")
+        .is_some_and(|v| v.contains("chunked"))
+    {
+        bail!("Chunked request bodies aren't supported");
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .map(|v| v.parse())
+        .transpose()?
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// Undo percent-encoding. Invalid escapes are kept as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
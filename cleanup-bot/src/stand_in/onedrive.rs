@@ -0,0 +1,378 @@
+//! Stand-in for the Microsoft identity platform and the OneDrive parts of Microsoft Graph that
+//! the bot uses: the device code flow, token refresh, simple and resumable uploads, listing,
+//! downloads and item metadata with a QuickXorHash.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use chrono::{TimeDelta, Utc};
+use serde_json::{Value, json};
+
+use super::{Request, Response, percent_decode, serve};
+use crate::onedrive::QuickXorHash;
+
+const SESSION_LIFETIME: TimeDelta = TimeDelta::hours(1);
+
+/// Errors to inject into the stand-in's answers.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Answer every n-th request with `503 Service Unavailable`
+    pub fail_every: Option<u64>,
+    /// Only accept the first half of each upload session chunk
+    pub partial_chunks: bool,
+    /// Reject refresh tokens with `invalid_grant`
+    pub reject_refresh: bool,
+    /// Report a wrong QuickXorHash for uploaded files
+    pub corrupt_hash: bool,
+    /// Lifetime of issued access tokens in seconds
+    pub token_lifetime: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            fail_every: None,
+            partial_chunks: false,
+            reject_refresh: false,
+            corrupt_hash: false,
+            token_lifetime: 3600,
+        }
+    }
+}
+
+impl Options {
+    /// Read options from `STAND_IN_FAIL_EVERY`, `STAND_IN_PARTIAL_CHUNKS`,
+    /// `STAND_IN_REJECT_REFRESH`, `STAND_IN_CORRUPT_HASH` and `STAND_IN_TOKEN_LIFETIME`.
+    pub fn from_env() -> Self {
+        let flag = |name| env::var(name).is_ok_and(|v| v == "1");
+        Self {
+            fail_every: env::var("STAND_IN_FAIL_EVERY")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            partial_chunks: flag("STAND_IN_PARTIAL_CHUNKS"),
+            reject_refresh: flag("STAND_IN_REJECT_REFRESH"),
+            corrupt_hash: flag("STAND_IN_CORRUPT_HASH"),
+            token_lifetime: env::var("STAND_IN_TOKEN_LIFETIME")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3600),
+        }
+    }
+}
+
+struct UploadSession {
+    path: String,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    files: HashMap<String, Vec<u8>>,
+    sessions: HashMap<u64, UploadSession>,
+    next_id: u64,
+    requests: u64,
+    device_code_polls: u64,
+    refreshes: u64,
+    /// Requests to let through before failing `fail_count` of them
+    fail_after: u64,
+    fail_count: u64,
+}
+
+/// A running OneDrive stand-in. It stops with the Tokio runtime it was started on.
+pub struct OneDriveStandIn {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl OneDriveStandIn {
+    /// Start serving on `address`, e.g. `127.0.0.1:0` for any free port.
+    pub async fn start(address: &str, options: Options) -> Result<Self> {
+        let state = Arc::new(Mutex::new(State::default()));
+        let url = serve(address, "OneDrive", {
+            let state = Arc::clone(&state);
+            move |request, base_url| handle(request, base_url, &options, &state)
+        })
+        .await?;
+
+        Ok(Self { url, state })
+    }
+
+    /// Base URL, e.g. `http://127.0.0.1:8787`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Value for the `graph_api` config option.
+    pub fn graph_api(&self) -> String {
+        format!("{}/v1.0", self.url)
+    }
+
+    /// Value for the `auth_url` config option.
+    pub fn auth_url(&self) -> String {
+        format!("{}/oauth2", self.url)
+    }
+
+    /// Content of an uploaded file by its path in the drive, e.g. `/discord-backups/a.jpg`.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().files.get(path).cloned()
+    }
+
+    /// Number of access tokens issued by refreshing.
+    pub fn refreshes(&self) -> u64 {
+        self.state.lock().unwrap().refreshes
+    }
+
+    /// Answer `count` requests with `503 Service Unavailable`, after letting the next `after`
+    /// through.
+    pub fn fail_requests(&self, after: u64, count: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.fail_after = after;
+        state.fail_count = count;
+    }
+}
+
+fn handle(request: &Request, base_url: &str, options: &Options, state: &Mutex<State>) -> Response {
+    let mut state = state.lock().unwrap();
+    state.requests += 1;
+
+    let injected_failure = if state.fail_after > 0 {
+        state.fail_after -= 1;
+        false
+    } else if state.fail_count > 0 {
+        state.fail_count -= 1;
+        true
+    } else {
+        options
+            .fail_every
+            .is_some_and(|n| n > 0 && state.requests.is_multiple_of(n))
+    };
+    if injected_failure {
+        return Response::json(503, json!({ "error": { "code": "serviceNotAvailable" } }));
+    }
+
+    route(request, base_url, options, &mut state)
+}
+
+fn route(request: &Request, base_url: &str, options: &Options, state: &mut State) -> Response {
+    let path = request.path.as_str();
+
+    if let Some(endpoint) = path.strip_prefix("/oauth2/") {
+        return match (request.method.as_str(), endpoint) {
+            ("POST", "devicecode") => Response::json(
+                200,
+                json!({
+                    "device_code": "stand-in-device-code",
+                    "user_code": "STANDIN",
+                    "verification_uri": format!("{base_url}/verify"),
+                    "expires_in": 900,
+                    "interval": 1,
+                }),
+            ),
+            ("POST", "token") => token(request, options, state),
+            _ => Response::empty(404),
+        };
+    }
+
+    if let Some(id) = path.strip_prefix("/upload/") {
+        let Ok(id) = id.parse() else {
+            return Response::empty(404);
+        };
+        return match request.method.as_str() {
+            "PUT" => upload_chunk(request, id, options, state),
+            "GET" => match state.sessions.get(&id) {
+                Some(session) => Response::json(200, session_status(session)),
+                None => Response::empty(404),
+            },
+            "DELETE" => {
+                state.sessions.remove(&id);
+                Response::empty(204)
+            }
+            _ => Response::empty(405),
+        };
+    }
+
+    let Some(item) = path.strip_prefix("/v1.0/me/drive/root:") else {
+        return Response::empty(404);
+    };
+    // Like Graph, match on the decoded path and ignore the query (`$select`)
+    let item = percent_decode(item.split('?').next().unwrap_or_default());
+    let item = item.as_str();
+
+    let authorized = request
+        .header("authorization")
+        .is_some_and(|v| v.starts_with("Bearer access-"));
+    if !authorized {
+        return Response::json(
+            401,
+            json!({ "error": { "code": "InvalidAuthenticationToken" } }),
+        );
+    }
+
+    if let Some(remote_path) = item.strip_suffix(":/content") {
+        return match request.method.as_str() {
+            "PUT" => {
+                let size = request.body.len();
+                state
+                    .files
+                    .insert(remote_path.to_string(), request.body.clone());
+                Response::json(201, json!({ "name": remote_path, "size": size }))
+            }
+            "GET" => match state.files.get(remote_path) {
+                Some(content) => Response::bytes(200, content.clone()),
+                None => Response::json(404, json!({ "error": { "code": "itemNotFound" } })),
+            },
+            _ => Response::empty(405),
+        };
+    }
+
+    // Folder listing, e.g. `/folder:/children`
+    if let Some(folder) = item.strip_suffix(":/children") {
+        let prefix = format!("{folder}/");
+        let children: Vec<Value> = state
+            .files
+            .keys()
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(|name| json!({ "name": name, "file": {} }))
+            .collect();
+        if children.is_empty() {
+            return Response::json(404, json!({ "error": { "code": "itemNotFound" } }));
+        }
+        return Response::json(200, json!({ "value": children }));
+    }
+
+    if let Some(remote_path) = item.strip_suffix(":/createUploadSession") {
+        if request.method != "POST" {
+            return Response::empty(405);
+        }
+        state.next_id += 1;
+        let id = state.next_id;
+        state.sessions.insert(
+            id,
+            UploadSession {
+                path: remote_path.to_string(),
+                data: Vec::new(),
+            },
+        );
+        let mut status = session_status(&state.sessions[&id]);
+        status["uploadUrl"] = json!(format!("{base_url}/upload/{id}"));
+        return Response::json(200, status);
+    }
+
+    // Item metadata, e.g. `/path/file.jpg`
+    match state.files.get(item) {
+        Some(content) if request.method == "GET" => {
+            let mut hash = QuickXorHash::new();
+            hash.update(content);
+            if options.corrupt_hash {
+                hash.update(b"corrupt");
+            }
+            Response::json(
+                200,
+                json!({
+                    "size": content.len(),
+                    "file": { "hashes": { "quickXorHash": hash.finish() } },
+                }),
+            )
+        }
+        Some(_) => Response::empty(405),
+        None => Response::json(404, json!({ "error": { "code": "itemNotFound" } })),
+    }
+}
+
+fn token(request: &Request, options: &Options, state: &mut State) -> Response {
+    let form: HashMap<String, String> = String::from_utf8_lossy(&request.body)
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    match form.get("grant_type").map(String::as_str) {
+        Some("refresh_token") if options.reject_refresh => Response::json(
+            400,
+            json!({
+                "error": "invalid_grant",
+                "error_description": "The refresh token has expired (stand-in)",
+            }),
+        ),
+        Some("refresh_token") => {
+            state.refreshes += 1;
+            issue_tokens(options, state)
+        }
+        // The first poll is pending, like a user who hasn't entered the code yet
+        Some(grant) if grant.ends_with("device_code") => {
+            state.device_code_polls += 1;
+            if state.device_code_polls % 2 == 1 {
+                Response::json(400, json!({ "error": "authorization_pending" }))
+            } else {
+                issue_tokens(options, state)
+            }
+        }
+        _ => Response::json(400, json!({ "error": "unsupported_grant_type" })),
+    }
+}
+
+fn issue_tokens(options: &Options, state: &mut State) -> Response {
+    state.next_id += 1;
+    Response::json(
+        200,
+        json!({
+            "access_token": format!("access-{}", state.next_id),
+            "refresh_token": format!("refresh-{}", state.next_id),
+            "expires_in": options.token_lifetime,
+        }),
+    )
+}
+
+fn upload_chunk(request: &Request, id: u64, options: &Options, state: &mut State) -> Response {
+    let Some(session) = state.sessions.get_mut(&id) else {
+        return Response::json(404, json!({ "error": { "code": "itemNotFound" } }));
+    };
+
+    // Content-Range: bytes start-end/total
+    let range = request
+        .header("content-range")
+        .and_then(|r| r.strip_prefix("bytes "))
+        .and_then(|r| r.split_once('/'))
+        .and_then(|(range, total)| {
+            let (start, end) = range.split_once('-')?;
+            Some((
+                start.parse::<usize>().ok()?,
+                end.parse::<usize>().ok()?,
+                total.parse::<usize>().ok()?,
+            ))
+        });
+
+    let Some((start, end, total)) = range else {
+        return Response::json(400, json!({ "error": { "code": "invalidRange" } }));
+    };
+
+    if start != session.data.len() || end + 1 != start + request.body.len() {
+        return Response::json(416, json!({ "error": { "code": "invalidRange" } }));
+    }
+
+    let accepted = if options.partial_chunks && request.body.len() > 1 {
+        &request.body[..request.body.len() / 2]
+    } else {
+        &request.body[..]
+    };
+    session.data.extend_from_slice(accepted);
+
+    if session.data.len() < total {
+        return Response::json(202, session_status(session));
+    }
+
+    let session = state.sessions.remove(&id).expect("session exists");
+    let size = session.data.len();
+    state.files.insert(session.path.clone(), session.data);
+    Response::json(201, json!({ "name": session.path, "size": size }))
+}
+
+fn session_status(session: &UploadSession) -> Value {
+    json!({
+        "expirationDateTime": (Utc::now() + SESSION_LIFETIME).to_rfc3339(),
+        "nextExpectedRanges": [format!("{}-", session.data.len())],
+    })
+}
@@ -0,0 +1,268 @@
+//! Stand-in for an S3-compatible object store with path-style addressing: PUT and GET of
+//! objects and ListObjectsV2. Every request has to carry a valid AWS Signature Version 4,
+//! which is checked against the request as it arrived.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::{Request, Response, percent_decode, serve};
+
+pub const BUCKET: &str = "backups";
+pub const ACCESS_KEY_ID: &str = "stand-in";
+pub const SECRET_ACCESS_KEY: &str = "stand-in-secret";
+pub const REGION: &str = "us-east-1";
+
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+// Real S3 returns at most 1000 keys per listing
+const DEFAULT_MAX_KEYS: usize = 1000;
+
+struct State {
+    /// Objects by key, sorted like S3 lists them
+    objects: BTreeMap<String, Vec<u8>>,
+    max_keys: usize,
+    /// Requests to let through before failing `fail_count` of them
+    fail_after: u64,
+    fail_count: u64,
+}
+
+/// A running S3 stand-in. It stops with the Tokio runtime it was started on.
+pub struct S3StandIn {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl S3StandIn {
+    /// Start serving on `address`, e.g. `127.0.0.1:0` for any free port.
+    pub async fn start(address: &str) -> Result<Self> {
+        let state = Arc::new(Mutex::new(State {
+            objects: BTreeMap::new(),
+            max_keys: DEFAULT_MAX_KEYS,
+            fail_after: 0,
+            fail_count: 0,
+        }));
+        let url = serve(address, "S3", {
+            let state = Arc::clone(&state);
+            move |request, _| handle(request, &state)
+        })
+        .await?;
+
+        Ok(Self { url, state })
+    }
+
+    /// Value for the `endpoint` config option, used with `path_style`.
+    pub fn endpoint(&self) -> &str {
+        &self.url
+    }
+
+    /// Content of an object by its key, e.g. `discord-backups/a.jpg`.
+    pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().objects.get(key).cloned()
+    }
+
+    /// Return at most `max_keys` keys per listing, so listings are split into pages.
+    pub fn set_max_keys(&self, max_keys: usize) {
+        self.state.lock().unwrap().max_keys = max_keys;
+    }
+
+    /// Answer `count` requests with `503 Service Unavailable`, after letting the next `after`
+    /// through.
+    pub fn fail_requests(&self, after: u64, count: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.fail_after = after;
+        state.fail_count = count;
+    }
+}
+
+fn handle(request: &Request, state: &Mutex<State>) -> Response {
+    let mut state = state.lock().unwrap();
+
+    if state.fail_after > 0 {
+        state.fail_after -= 1;
+    } else if state.fail_count > 0 {
+        state.fail_count -= 1;
+        return error(503, "SlowDown");
+    }
+
+    if let Err(code) = verify_signature(request) {
+        return error(403, code);
+    }
+
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    let Some(key) = path
+        .strip_prefix('/')
+        .and_then(|p| p.strip_prefix(BUCKET))
+        .map(|k| percent_decode(k.trim_start_matches('/')))
+    else {
+        return error(404, "NoSuchBucket");
+    };
+
+    match request.method.as_str() {
+        "GET" if key.is_empty() => list_objects(query, &state),
+        "GET" => match state.objects.get(&key) {
+            Some(content) => Response::bytes(200, content.clone()),
+            None => error(404, "NoSuchKey"),
+        },
+        "PUT" if !key.is_empty() => {
+            state.objects.insert(key, request.body.clone());
+            Response::empty(200)
+        }
+        _ => error(405, "MethodNotAllowed"),
+    }
+}
+
+/// ListObjectsV2. Keys below a further `/` after the prefix are left out, like with
+/// `delimiter=/`.
+fn list_objects(query: &str, state: &State) -> Response {
+    let params: BTreeMap<String, String> = query
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.to_string(), percent_decode(v)))
+        .collect();
+    if params.get("list-type").map(String::as_str) != Some("2") {
+        return error(400, "InvalidArgument");
+    }
+
+    let prefix = params.get("prefix").cloned().unwrap_or_default();
+    let start_after = params
+        .get("continuation-token")
+        .cloned()
+        .unwrap_or_default();
+
+    let mut keys = state
+        .objects
+        .keys()
+        .filter(|k| k.as_str() > start_after.as_str())
+        .filter(|k| k.strip_prefix(&prefix).is_some_and(|n| !n.contains('/')));
+    let page: Vec<&String> = keys.by_ref().take(state.max_keys).collect();
+    let truncated = keys.next().is_some();
+
+    let mut body = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+    );
+    let _ = write!(
+        body,
+        "<Name>{BUCKET}</Name><Prefix>{}</Prefix>",
+        escape(&prefix)
+    );
+    for key in &page {
+        let _ = write!(body, "<Contents><Key>{}</Key></Contents>", escape(key));
+    }
+    let _ = write!(body, "<IsTruncated>{truncated}</IsTruncated>");
+    if let Some(last) = page.last().filter(|_| truncated) {
+        let _ = write!(
+            body,
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            escape(last)
+        );
+    }
+    body.push_str("</ListBucketResult>");
+
+    Response::xml(200, body)
+}
+
+/// Check the request's `Authorization` header. Returns the S3 error code if it doesn't match.
+fn verify_signature(request: &Request) -> Result<(), &'static str> {
+    let authorization = request
+        .header("authorization")
+        .and_then(|a| a.strip_prefix("AWS4-HMAC-SHA256 "))
+        .ok_or("AccessDenied")?;
+    let fields: BTreeMap<&str, &str> = authorization
+        .split(", ")
+        .filter_map(|f| f.split_once('='))
+        .collect();
+    let (credential, signed_headers, signature) = match (
+        fields.get("Credential"),
+        fields.get("SignedHeaders"),
+        fields.get("Signature"),
+    ) {
+        (Some(c), Some(h), Some(s)) => (*c, *h, *s),
+        _ => return Err("AuthorizationHeaderMalformed"),
+    };
+
+    let (access_key_id, scope) = credential
+        .split_once('/')
+        .ok_or("AuthorizationHeaderMalformed")?;
+    if access_key_id != ACCESS_KEY_ID {
+        return Err("InvalidAccessKeyId");
+    }
+    let date = scope.split('/').next().unwrap_or_default();
+    if scope != format!("{date}/{REGION}/s3/aws4_request") {
+        return Err("AuthorizationHeaderMalformed");
+    }
+
+    let amz_date = request.header("x-amz-date").ok_or("AccessDenied")?;
+    let payload_hash = request
+        .header("x-amz-content-sha256")
+        .ok_or("InvalidRequest")?;
+    if payload_hash != UNSIGNED_PAYLOAD && payload_hash != hex(&Sha256::digest(&request.body)) {
+        return Err("XAmzContentSHA256Mismatch");
+    }
+
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    let mut query: Vec<&str> = query.split('&').filter(|p| !p.is_empty()).collect();
+    query.sort_unstable();
+
+    let mut canonical_headers = String::new();
+    for name in signed_headers.split(';') {
+        let value = request.header(name).ok_or("AccessDenied")?;
+        let _ = writeln!(canonical_headers, "{name}:{}", value.trim());
+    }
+
+    let canonical_request = format!(
+        "{}\n{path}\n{}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+        request.method,
+        query.join("&")
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = hmac(
+        format!("AWS4{SECRET_ACCESS_KEY}").as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac(&key, REGION.as_bytes());
+    let key = hmac(&key, b"s3");
+    let key = hmac(&key, b"aws4_request");
+
+    if hex(&hmac(&key, string_to_sign.as_bytes())) == signature {
+        Ok(())
+    } else {
+        Err("SignatureDoesNotMatch")
+    }
+}
+
+fn error(status: u16, code: &str) -> Response {
+    Response::xml(
+        status,
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>{code}</Code><Message>{code} (stand-in)</Message></Error>"#
+        ),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
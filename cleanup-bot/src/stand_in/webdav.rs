@@ -0,0 +1,254 @@
+//! Stand-in for a Nextcloud WebDAV server: collections, plain uploads, listing, downloads and
+//! chunked uploads (upload collections assembled with a MOVE). Any basic auth credentials are
+//! accepted, and paths are kept percent-encoded as they were sent.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use super::{Request, Response, serve};
+
+const FILES_ROOT: &str = "/remote.php/dav/files/stand-in";
+const UPLOADS_ROOT: &str = "/remote.php/dav/uploads/stand-in";
+
+/// Chunks of a chunked upload by number.
+#[derive(Default)]
+struct Upload {
+    chunks: BTreeMap<u64, Vec<u8>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Collections below the user's root, e.g. `discord-backups/2024`
+    collections: HashSet<String>,
+    files: HashMap<String, Vec<u8>>,
+    uploads: HashMap<String, Upload>,
+    /// Requests to let through before failing `fail_count` of them
+    fail_after: u64,
+    fail_count: u64,
+}
+
+/// A running WebDAV stand-in. It stops with the Tokio runtime it was started on.
+pub struct WebDavStandIn {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl WebDavStandIn {
+    /// Start serving on `address`, e.g. `127.0.0.1:0` for any free port.
+    pub async fn start(address: &str) -> Result<Self> {
+        let state = Arc::new(Mutex::new(State::default()));
+        let url = serve(address, "WebDAV", {
+            let state = Arc::clone(&state);
+            move |request, base_url| handle(request, base_url, &state)
+        })
+        .await?;
+
+        Ok(Self { url, state })
+    }
+
+    /// Value for the `url` config option. The chunked upload endpoint is derived from it.
+    pub fn files_url(&self) -> String {
+        format!("{}{FILES_ROOT}", self.url)
+    }
+
+    /// Content of an uploaded file by its path below the user's root, e.g.
+    /// `discord-backups/a.jpg`.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().files.get(path).cloned()
+    }
+
+    /// Number of unfinished chunked uploads.
+    pub fn uploads(&self) -> usize {
+        self.state.lock().unwrap().uploads.len()
+    }
+
+    /// Remove unfinished chunked uploads, like Nextcloud's cleanup job.
+    pub fn remove_uploads(&self) {
+        self.state.lock().unwrap().uploads.clear();
+    }
+
+    /// Answer `count` requests with `503 Service Unavailable`, after letting the next `after`
+    /// through.
+    pub fn fail_requests(&self, after: u64, count: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.fail_after = after;
+        state.fail_count = count;
+    }
+}
+
+fn handle(request: &Request, base_url: &str, state: &Mutex<State>) -> Response {
+    let mut state = state.lock().unwrap();
+
+    if state.fail_after > 0 {
+        state.fail_after -= 1;
+    } else if state.fail_count > 0 {
+        state.fail_count -= 1;
+        return Response::empty(503);
+    }
+
+    if !request
+        .header("authorization")
+        .is_some_and(|v| v.starts_with("Basic "))
+    {
+        return Response::empty(401);
+    }
+
+    let path = request.path.trim_end_matches('/');
+    if let Some(path) = path.strip_prefix(UPLOADS_ROOT) {
+        return upload_request(request, path.trim_start_matches('/'), base_url, &mut state);
+    }
+    match path.strip_prefix(FILES_ROOT) {
+        Some(path) => file_request(request, path.trim_start_matches('/'), &mut state),
+        None => Response::empty(404),
+    }
+}
+
+fn file_request(request: &Request, path: &str, state: &mut State) -> Response {
+    let parent_exists = |state: &State| {
+        let parent = parent(path);
+        parent.is_empty() || state.collections.contains(parent)
+    };
+
+    match request.method.as_str() {
+        "MKCOL" if state.collections.contains(path) || state.files.contains_key(path) => {
+            Response::empty(405)
+        }
+        "MKCOL" if !parent_exists(state) => Response::empty(409),
+        "MKCOL" => {
+            state.collections.insert(path.to_string());
+            Response::empty(201)
+        }
+        "PUT" if !parent_exists(state) => Response::empty(409),
+        "PUT" => {
+            let created = state
+                .files
+                .insert(path.to_string(), request.body.clone())
+                .is_none();
+            Response::empty(if created { 201 } else { 204 })
+        }
+        "GET" => match state.files.get(path) {
+            Some(content) => Response::bytes(200, content.clone()),
+            None => Response::empty(404),
+        },
+        "PROPFIND" if path.is_empty() || state.collections.contains(path) => {
+            let mut entries = vec![(path.to_string(), true)];
+            if request.header("depth") != Some("0") {
+                let is_child = |p: &&String| parent(p) == path;
+                entries.extend(
+                    state
+                        .collections
+                        .iter()
+                        .filter(is_child)
+                        .map(|p| (p.clone(), true)),
+                );
+                entries.extend(
+                    state
+                        .files
+                        .keys()
+                        .filter(is_child)
+                        .map(|p| (p.clone(), false)),
+                );
+            }
+            multistatus(FILES_ROOT, &entries)
+        }
+        "PROPFIND" if state.files.contains_key(path) => {
+            multistatus(FILES_ROOT, &[(path.to_string(), false)])
+        }
+        "PROPFIND" => Response::empty(404),
+        _ => Response::empty(405),
+    }
+}
+
+/// Chunked uploads: `MKCOL {id}`, `PUT {id}/{chunk}`, `PROPFIND {id}` and `MOVE {id}/.file`.
+fn upload_request(request: &Request, path: &str, base_url: &str, state: &mut State) -> Response {
+    let (id, item) = match path.split_once('/') {
+        Some((id, item)) => (id, Some(item)),
+        None => (path, None),
+    };
+
+    match (request.method.as_str(), item) {
+        ("MKCOL", None) if state.uploads.contains_key(id) => Response::empty(405),
+        ("MKCOL", None) => {
+            state.uploads.insert(id.to_string(), Upload::default());
+            Response::empty(201)
+        }
+        ("PROPFIND", None) if state.uploads.contains_key(id) => {
+            multistatus(UPLOADS_ROOT, &[(id.to_string(), true)])
+        }
+        ("PUT", Some(number)) => {
+            let Ok(number) = number.parse() else {
+                return Response::empty(400);
+            };
+            match state.uploads.get_mut(id) {
+                Some(upload) => {
+                    upload.chunks.insert(number, request.body.clone());
+                    Response::empty(201)
+                }
+                None => Response::empty(404),
+            }
+        }
+        ("MOVE", Some(".file")) => {
+            let Some(destination) = request
+                .header("destination")
+                .and_then(|d| d.strip_prefix(base_url))
+                .and_then(|d| d.strip_prefix(FILES_ROOT))
+                .map(|d| d.trim_matches('/').to_string())
+            else {
+                return Response::empty(400);
+            };
+            let Some(upload) = state.uploads.get(id) else {
+                return Response::empty(404);
+            };
+
+            let content: Vec<u8> = upload.chunks.values().flatten().copied().collect();
+            let total_length = request
+                .header("oc-total-length")
+                .and_then(|l| l.parse::<usize>().ok());
+            if total_length.is_some_and(|l| l != content.len()) {
+                return Response::empty(400);
+            }
+            let parent = parent(&destination);
+            if !parent.is_empty() && !state.collections.contains(parent) {
+                return Response::empty(409);
+            }
+
+            state.uploads.remove(id);
+            state.files.insert(destination, content);
+            Response::empty(201)
+        }
+        _ => Response::empty(404),
+    }
+}
+
+/// The collection containing `path`, empty for the user's root.
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// A PROPFIND answer listing `entries` (path below `root`, is a collection).
+fn multistatus(root: &str, entries: &[(String, bool)]) -> Response {
+    let responses: String = entries
+        .iter()
+        .map(|(path, collection)| {
+            let (href, resource_type) = if *collection {
+                (format!("{root}/{path}/"), "<d:collection/>")
+            } else {
+                (format!("{root}/{path}"), "")
+            };
+            format!(
+                "<d:response><d:href>{href}</d:href><d:propstat><d:prop>\
+                 <d:resourcetype>{resource_type}</d:resourcetype></d:prop>\
+                 <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+            )
+        })
+        .collect();
+
+    Response::xml(
+        207,
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><d:multistatus xmlns:d="DAV:">{responses}</d:multistatus>"#
+        ),
+    )
+}
@@ -0,0 +1,272 @@
+//! Uploads to the OneDrive stand-in: simple and resumable uploads, resuming an interrupted
+//! upload, names that need encoding, token refresh, retrying after a server error and upload
+//! verification.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Once};
+
+use chrono::{TimeDelta, Utc};
+use cleanup_bot::backup::{BackupQueue, BackupStatus, BandwidthLimiter, PendingBackup};
+use cleanup_bot::config::BackupWorkerConfig;
+use cleanup_bot::destination::UploadProgress;
+use cleanup_bot::onedrive::{OneDriveClient, OneDriveError, StoredTokens, TokenStore};
+use cleanup_bot::stand_in::OneDriveStandIn;
+use cleanup_bot::stand_in::onedrive::Options;
+use tokio::sync::Mutex as TokioMutex;
+
+const UPLOAD_FOLDER: &str = "/discord-backups";
+const MIB: usize = 1024 * 1024;
+
+/// Token files and the backup queue live in the working directory, so every test runs in a
+/// scratch directory.
+fn work_dir() -> PathBuf {
+    static INIT: Once = Once::new();
+    let dir = std::env::temp_dir().join(format!("cleanup-bot-onedrive-{}", std::process::id()));
+    INIT.call_once(|| {
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+    });
+    dir
+}
+
+struct Harness {
+    stand_in: OneDriveStandIn,
+    client: OneDriveClient,
+    tokens: Arc<TokioMutex<TokenStore>>,
+    queue: Mutex<BackupQueue>,
+    limiter: Arc<BandwidthLimiter>,
+}
+
+impl Harness {
+    async fn start(options: Options) -> Self {
+        let dir = work_dir();
+        let stand_in = OneDriveStandIn::start("127.0.0.1:0", options)
+            .await
+            .unwrap();
+
+        let mut tokens = TokenStore::new("stand-in".to_string(), stand_in.auth_url(), None);
+        tokens
+            .set_tokens(StoredTokens {
+                access_token: "access-0".to_string(),
+                refresh_token: "refresh-0".to_string(),
+                expires_at: Utc::now() + TimeDelta::hours(1),
+            })
+            .unwrap();
+        let tokens = Arc::new(TokioMutex::new(tokens));
+
+        let client = OneDriveClient::new(
+            Arc::clone(&tokens),
+            UPLOAD_FOLDER.to_string(),
+            stand_in.graph_api(),
+        );
+
+        assert!(dir.exists());
+        Self {
+            stand_in,
+            client,
+            tokens,
+            queue: Mutex::new(BackupQueue::load().unwrap()),
+            limiter: Arc::new(BandwidthLimiter::new(BackupWorkerConfig::default())),
+        }
+    }
+
+    /// Write a file to back up and queue it. Names must be unique across tests.
+    fn queue_file(&self, name: &str, size: usize) -> (PathBuf, Vec<u8>) {
+        let content: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        let dir = work_dir().join(name).join("2024-05-01");
+        std::fs::create_dir_all(&dir).unwrap();
+        let local_path = dir.join(format!("100_{name}.bin"));
+        std::fs::write(&local_path, &content).unwrap();
+
+        self.queue
+            .lock()
+            .unwrap()
+            .add(PendingBackup {
+                message_id: 100,
+                channel_id: 200,
+                local_path: local_path.clone(),
+                original_filename: format!("{name}.bin"),
+                timestamp: Utc::now(),
+                retry_count: 0,
+                status: BackupStatus::Pending,
+                next_attempt_at: None,
+                uploaded_to: Vec::new(),
+                upload_session: None,
+                content: None,
+            })
+            .unwrap();
+
+        (local_path, content)
+    }
+
+    async fn upload(&self, local_path: &Path) -> Result<(), OneDriveError> {
+        let progress = UploadProgress::new(&self.queue, local_path, "onedrive", &self.limiter);
+        self.client.upload_file(local_path, &progress).await
+    }
+
+    fn uploaded(&self, name: &str) -> Option<Vec<u8>> {
+        self.stand_in
+            .file(&format!("{UPLOAD_FOLDER}/2024/05/01/100_{name}.bin"))
+    }
+
+    /// Byte the saved upload session of a file continues at.
+    fn session_offset(&self, local_path: &Path) -> Option<u64> {
+        self.queue
+            .lock()
+            .unwrap()
+            .get(local_path)
+            .and_then(|b| b.upload_session.as_ref())
+            .map(|s| s.next_offset)
+    }
+}
+
+#[tokio::test]
+async fn simple_upload() {
+    let harness = Harness::start(Options::default()).await;
+    let (local_path, content) = harness.queue_file("simple", 1024);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("simple"), Some(content));
+}
+
+#[tokio::test]
+async fn resumable_upload_follows_next_expected_ranges() {
+    // The server only takes half of every chunk, so the client has to send the rest again
+    let options = Options {
+        partial_chunks: true,
+        ..Options::default()
+    };
+    let harness = Harness::start(options).await;
+    let (local_path, content) = harness.queue_file("resumable", 12 * MIB);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("resumable"), Some(content));
+    assert_eq!(harness.session_offset(&local_path), None);
+}
+
+#[tokio::test]
+async fn interrupted_upload_resumes_at_saved_offset() {
+    let harness = Harness::start(Options::default()).await;
+    let (local_path, content) = harness.queue_file("interrupted", 12 * MIB);
+
+    // Create the session and send the first chunk, then fail the second
+    harness.stand_in.fail_requests(2, 1);
+    assert!(harness.upload(&local_path).await.is_err());
+    assert_eq!(harness.session_offset(&local_path), Some(10 * MIB as u64));
+    assert_eq!(harness.uploaded("interrupted"), None);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("interrupted"), Some(content));
+    assert_eq!(harness.session_offset(&local_path), None);
+}
+
+#[tokio::test]
+async fn name_with_reserved_characters() {
+    let harness = Harness::start(Options::default()).await;
+    let name = "reserved #1?%20 (ü)";
+    let (local_path, content) = harness.queue_file(name, 1024);
+
+    harness.upload(&local_path).await.unwrap();
+    assert_eq!(harness.uploaded(name), Some(content.clone()));
+
+    let file_name = format!("100_{name}.bin");
+    let names = harness.client.list_folder("2024/05/01").await.unwrap();
+    assert!(names.contains(&file_name), "{names:?}");
+
+    let downloaded = harness
+        .client
+        .download_file(&format!("2024/05/01/{file_name}"))
+        .await
+        .unwrap();
+    assert_eq!(downloaded.bytes().await.unwrap(), content);
+}
+
+#[tokio::test]
+async fn expired_access_token_is_refreshed() {
+    let harness = Harness::start(Options::default()).await;
+    harness
+        .tokens
+        .lock()
+        .await
+        .set_tokens(StoredTokens {
+            access_token: "access-0".to_string(),
+            refresh_token: "refresh-0".to_string(),
+            expires_at: Utc::now() - TimeDelta::minutes(1),
+        })
+        .unwrap();
+    let (local_path, content) = harness.queue_file("refresh", 1024);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.stand_in.refreshes(), 1);
+    assert_eq!(harness.uploaded("refresh"), Some(content));
+    let token = harness.tokens.lock().await.get_valid_token().await.unwrap();
+    assert_ne!(token, "access-0");
+}
+
+#[tokio::test]
+async fn rejected_refresh_token_requires_reauth() {
+    let options = Options {
+        reject_refresh: true,
+        ..Options::default()
+    };
+    let harness = Harness::start(options).await;
+    harness
+        .tokens
+        .lock()
+        .await
+        .set_tokens(StoredTokens {
+            access_token: "access-0".to_string(),
+            refresh_token: "refresh-0".to_string(),
+            expires_at: Utc::now() - TimeDelta::minutes(1),
+        })
+        .unwrap();
+    let (local_path, _) = harness.queue_file("rejected", 1024);
+
+    let result = harness.upload(&local_path).await;
+
+    assert!(
+        matches!(result, Err(OneDriveError::ReauthRequired(_))),
+        "{result:?}"
+    );
+    assert!(harness.client.needs_reauth().await);
+    assert_eq!(harness.uploaded("rejected"), None);
+}
+
+#[tokio::test]
+async fn server_error_then_successful_retry() {
+    let harness = Harness::start(Options::default()).await;
+    let (local_path, content) = harness.queue_file("retry", 1024);
+
+    harness.stand_in.fail_requests(0, 1);
+    let result = harness.upload(&local_path).await;
+    assert!(
+        matches!(result, Err(OneDriveError::Upload(_))),
+        "{result:?}"
+    );
+    assert_eq!(harness.uploaded("retry"), None);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("retry"), Some(content));
+}
+
+#[tokio::test]
+async fn verification_catches_hash_mismatch() {
+    let options = Options {
+        corrupt_hash: true,
+        ..Options::default()
+    };
+    let harness = Harness::start(options).await;
+    let (local_path, _) = harness.queue_file("corrupt", 1024);
+
+    let result = harness.upload(&local_path).await;
+
+    assert!(
+        matches!(result, Err(OneDriveError::Verification(_))),
+        "{result:?}"
+    );
+}
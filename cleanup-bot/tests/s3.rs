@@ -0,0 +1,180 @@
+//! Uploads to the S3 stand-in, which checks every request's signature: streamed uploads, keys
+//! that need encoding, paged listings, downloads and rejected credentials.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Once};
+
+use anyhow::Result;
+use chrono::Utc;
+use cleanup_bot::backup::{BackupQueue, BackupStatus, BandwidthLimiter, PendingBackup};
+use cleanup_bot::config::{BackupWorkerConfig, S3Config};
+use cleanup_bot::destination::{BackupDestination, S3Destination, UploadProgress};
+use cleanup_bot::stand_in::S3StandIn;
+use cleanup_bot::stand_in::s3::{ACCESS_KEY_ID, BUCKET, REGION, SECRET_ACCESS_KEY};
+
+const PREFIX: &str = "discord-backups";
+const MIB: usize = 1024 * 1024;
+
+/// The backup queue lives in the working directory, so every test runs in a scratch directory.
+fn work_dir() -> PathBuf {
+    static INIT: Once = Once::new();
+    let dir = std::env::temp_dir().join(format!("cleanup-bot-s3-{}", std::process::id()));
+    INIT.call_once(|| {
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+    });
+    dir
+}
+
+struct Harness {
+    stand_in: S3StandIn,
+    destination: S3Destination,
+    queue: Mutex<BackupQueue>,
+    limiter: Arc<BandwidthLimiter>,
+}
+
+impl Harness {
+    async fn start(secret_access_key: &str) -> Self {
+        work_dir();
+        let stand_in = S3StandIn::start("127.0.0.1:0").await.unwrap();
+        let destination = S3Destination::new(S3Config {
+            endpoint: stand_in.endpoint().to_string(),
+            bucket: BUCKET.to_string(),
+            region: REGION.to_string(),
+            access_key_id: ACCESS_KEY_ID.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            prefix: PREFIX.to_string(),
+            path_style: true,
+        });
+
+        Self {
+            stand_in,
+            destination,
+            queue: Mutex::new(BackupQueue::load().unwrap()),
+            limiter: Arc::new(BandwidthLimiter::new(BackupWorkerConfig::default())),
+        }
+    }
+
+    /// Write a file to back up and queue it. Directories must be unique across tests.
+    fn queue_file(&self, dir: &str, name: &str, size: usize) -> (PathBuf, Vec<u8>) {
+        let content: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        let dir = work_dir().join(dir).join("2024-05-01");
+        std::fs::create_dir_all(&dir).unwrap();
+        let local_path = dir.join(name);
+        std::fs::write(&local_path, &content).unwrap();
+
+        self.queue
+            .lock()
+            .unwrap()
+            .add(PendingBackup {
+                message_id: 100,
+                channel_id: 200,
+                local_path: local_path.clone(),
+                original_filename: name.to_string(),
+                timestamp: Utc::now(),
+                retry_count: 0,
+                status: BackupStatus::Pending,
+                next_attempt_at: None,
+                uploaded_to: Vec::new(),
+                upload_session: None,
+                content: None,
+            })
+            .unwrap();
+
+        (local_path, content)
+    }
+
+    async fn upload(&self, local_path: &Path) -> Result<()> {
+        let progress = UploadProgress::new(
+            &self.queue,
+            local_path,
+            self.destination.name(),
+            &self.limiter,
+        );
+        self.destination.upload(local_path, &progress).await
+    }
+
+    fn uploaded(&self, name: &str) -> Option<Vec<u8>> {
+        self.stand_in.object(&format!("{PREFIX}/2024/05/01/{name}"))
+    }
+}
+
+#[tokio::test]
+async fn upload() {
+    let harness = Harness::start(SECRET_ACCESS_KEY).await;
+    let (local_path, content) = harness.queue_file("upload", "100_small.bin", 1024);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("100_small.bin"), Some(content));
+}
+
+#[tokio::test]
+async fn large_upload_is_streamed() {
+    let harness = Harness::start(SECRET_ACCESS_KEY).await;
+    let (local_path, content) = harness.queue_file("streamed", "100_large.bin", 12 * MIB);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("100_large.bin"), Some(content));
+}
+
+#[tokio::test]
+async fn key_with_reserved_characters() {
+    let harness = Harness::start(SECRET_ACCESS_KEY).await;
+    let name = "100_holiday photo (1)+ü.jpg";
+    let (local_path, content) = harness.queue_file("reserved", name, 1024);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded(name), Some(content));
+}
+
+#[tokio::test]
+async fn paged_listing_and_download() {
+    let harness = Harness::start(SECRET_ACCESS_KEY).await;
+    harness.stand_in.set_max_keys(2);
+    let mut expected = Vec::new();
+    for i in 0..5 {
+        let name = format!("10{i}_listed file.bin");
+        let (local_path, _) = harness.queue_file("listed", &name, 64);
+        harness.upload(&local_path).await.unwrap();
+        expected.push(name);
+    }
+
+    let names = harness.destination.list("2024/05/01").await.unwrap();
+    assert_eq!(names, expected);
+    assert!(
+        harness
+            .destination
+            .list("2024/05/02")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let downloaded = work_dir().join("listed").join("downloaded.bin");
+    harness
+        .destination
+        .download("2024/05/01/102_listed file.bin", &downloaded)
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(&downloaded).unwrap(),
+        harness.uploaded("102_listed file.bin").unwrap()
+    );
+}
+
+#[tokio::test]
+async fn wrong_secret_is_rejected() {
+    let harness = Harness::start("not-the-secret").await;
+    let (local_path, _) = harness.queue_file("rejected", "100_rejected.bin", 1024);
+
+    let error = harness.upload(&local_path).await.unwrap_err();
+
+    assert!(
+        error.to_string().contains("SignatureDoesNotMatch"),
+        "{error:#}"
+    );
+    assert_eq!(harness.uploaded("100_rejected.bin"), None);
+}
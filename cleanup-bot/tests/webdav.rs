@@ -0,0 +1,190 @@
+//! Uploads to the WebDAV stand-in: plain and chunked uploads, resuming an interrupted chunked
+//! upload, starting over when the upload collection was removed, listing and downloading.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Once};
+
+use anyhow::Result;
+use chrono::Utc;
+use cleanup_bot::backup::{BackupQueue, BackupStatus, BandwidthLimiter, PendingBackup};
+use cleanup_bot::config::{BackupWorkerConfig, WebDavConfig};
+use cleanup_bot::destination::{BackupDestination, UploadProgress, WebDavDestination};
+use cleanup_bot::stand_in::WebDavStandIn;
+
+const UPLOAD_FOLDER: &str = "discord-backups";
+const MIB: usize = 1024 * 1024;
+
+/// The backup queue lives in the working directory, so every test runs in a scratch directory.
+fn work_dir() -> PathBuf {
+    static INIT: Once = Once::new();
+    let dir = std::env::temp_dir().join(format!("cleanup-bot-webdav-{}", std::process::id()));
+    INIT.call_once(|| {
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+    });
+    dir
+}
+
+struct Harness {
+    stand_in: WebDavStandIn,
+    destination: WebDavDestination,
+    queue: Mutex<BackupQueue>,
+    limiter: Arc<BandwidthLimiter>,
+}
+
+impl Harness {
+    async fn start() -> Self {
+        work_dir();
+        let stand_in = WebDavStandIn::start("127.0.0.1:0").await.unwrap();
+        let destination = WebDavDestination::new(WebDavConfig {
+            url: stand_in.files_url(),
+            username: "stand-in".to_string(),
+            password: "stand-in".to_string(),
+            upload_folder: UPLOAD_FOLDER.to_string(),
+            uploads_url: None,
+        });
+
+        Self {
+            stand_in,
+            destination,
+            queue: Mutex::new(BackupQueue::load().unwrap()),
+            limiter: Arc::new(BandwidthLimiter::new(BackupWorkerConfig::default())),
+        }
+    }
+
+    /// Write a file to back up and queue it. Names must be unique across tests.
+    fn queue_file(&self, name: &str, size: usize) -> (PathBuf, Vec<u8>) {
+        let content: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        let dir = work_dir().join(name).join("2024-05-01");
+        std::fs::create_dir_all(&dir).unwrap();
+        let local_path = dir.join(format!("100_{name}.bin"));
+        std::fs::write(&local_path, &content).unwrap();
+
+        self.queue
+            .lock()
+            .unwrap()
+            .add(PendingBackup {
+                message_id: 100,
+                channel_id: 200,
+                local_path: local_path.clone(),
+                original_filename: format!("{name}.bin"),
+                timestamp: Utc::now(),
+                retry_count: 0,
+                status: BackupStatus::Pending,
+                next_attempt_at: None,
+                uploaded_to: Vec::new(),
+                upload_session: None,
+                content: None,
+            })
+            .unwrap();
+
+        (local_path, content)
+    }
+
+    async fn upload(&self, local_path: &Path) -> Result<()> {
+        let progress = UploadProgress::new(
+            &self.queue,
+            local_path,
+            self.destination.name(),
+            &self.limiter,
+        );
+        self.destination.upload(local_path, &progress).await
+    }
+
+    fn uploaded(&self, name: &str) -> Option<Vec<u8>> {
+        self.stand_in
+            .file(&format!("{UPLOAD_FOLDER}/2024/05/01/100_{name}.bin"))
+    }
+
+    /// Byte the saved upload session of a file continues at.
+    fn session_offset(&self, local_path: &Path) -> Option<u64> {
+        self.queue
+            .lock()
+            .unwrap()
+            .get(local_path)
+            .and_then(|b| b.upload_session.as_ref())
+            .map(|s| s.next_offset)
+    }
+}
+
+#[tokio::test]
+async fn simple_upload() {
+    let harness = Harness::start().await;
+    let (local_path, content) = harness.queue_file("simple", 1024);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("simple"), Some(content));
+    assert_eq!(harness.stand_in.uploads(), 0);
+}
+
+#[tokio::test]
+async fn chunked_upload() {
+    let harness = Harness::start().await;
+    let (local_path, content) = harness.queue_file("chunked", 25 * MIB);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("chunked"), Some(content));
+    assert_eq!(harness.stand_in.uploads(), 0);
+    assert_eq!(harness.session_offset(&local_path), None);
+}
+
+#[tokio::test]
+async fn interrupted_chunked_upload_resumes_with_next_chunk() {
+    let harness = Harness::start().await;
+    let (local_path, content) = harness.queue_file("interrupted", 12 * MIB);
+
+    // Four collections, the upload collection and the first chunk, then fail the second chunk
+    harness.stand_in.fail_requests(6, 1);
+    assert!(harness.upload(&local_path).await.is_err());
+    assert_eq!(harness.session_offset(&local_path), Some(10 * MIB as u64));
+    assert_eq!(harness.uploaded("interrupted"), None);
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("interrupted"), Some(content));
+    assert_eq!(harness.stand_in.uploads(), 0);
+    assert_eq!(harness.session_offset(&local_path), None);
+}
+
+#[tokio::test]
+async fn removed_upload_collection_starts_over() {
+    let harness = Harness::start().await;
+    let (local_path, content) = harness.queue_file("removed", 12 * MIB);
+
+    harness.stand_in.fail_requests(6, 1);
+    assert!(harness.upload(&local_path).await.is_err());
+    harness.stand_in.remove_uploads();
+
+    harness.upload(&local_path).await.unwrap();
+
+    assert_eq!(harness.uploaded("removed"), Some(content));
+    assert_eq!(harness.session_offset(&local_path), None);
+}
+
+#[tokio::test]
+async fn list_and_download() {
+    let harness = Harness::start().await;
+    let (local_path, content) = harness.queue_file("listed", 1024);
+    harness.upload(&local_path).await.unwrap();
+
+    let names = harness.destination.list("2024/05/01").await.unwrap();
+    assert_eq!(names, ["100_listed.bin"]);
+    assert!(
+        harness
+            .destination
+            .list("2024/05/02")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let downloaded = work_dir().join("listed").join("downloaded.bin");
+    harness
+        .destination
+        .download("2024/05/01/100_listed.bin", &downloaded)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&downloaded).unwrap(), content);
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    fs,
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serenity::all::ChannelId;
+
+const CHANNELS_PATH: &str = "./channels.toml";
+const CHANNELS_TEMP_PATH: &str = "./channels.toml.tmp";
+
+/// Per-channel summarizer settings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelConfig {
+    pub name: String,
+    /// Messages at least this long get a thread with the summary as its first message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_min_length: Option<usize>,
+    /// Post a follow-up summary each time a summary thread grows by this many messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_follow_up_messages: Option<NonZeroU32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Channels {
+    #[serde(default)]
+    channels: HashMap<ChannelId, ChannelConfig>,
+}
+
+impl Channels {
+    fn load() -> Result<Self> {
+        match fs::read_to_string(CHANNELS_PATH) {
+            Ok(content) => {
+                toml::from_str(&content).context(format!("Failed to parse {CHANNELS_PATH}"))
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let content = toml::to_string_pretty(&self)?;
+        fs::write(CHANNELS_TEMP_PATH, &content).context("saving temp channels file")?;
+        fs::rename(CHANNELS_TEMP_PATH, CHANNELS_PATH).context("updating channels file")?;
+        Ok(())
+    }
+}
+
+/// Thread-safe store for per-channel settings, persisted to `channels.toml`.
+#[derive(Clone, Debug)]
+pub struct ChannelConfigStore {
+    inner: Arc<Mutex<Channels>>,
+}
+
+impl ChannelConfigStore {
+    pub fn load() -> Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Channels::load()?)),
+        })
+    }
+
+    /// Returns the settings for a channel, if any.
+    pub fn get(&self, channel_id: ChannelId) -> Option<ChannelConfig> {
+        self.inner
+            .lock()
+            .unwrap()
+            .channels
+            .get(&channel_id)
+            .cloned()
+    }
+
+    /// Adds or updates a channel's settings.
+    pub fn set(&self, channel_id: ChannelId, config: ChannelConfig) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.channels.insert(channel_id, config);
+        inner.save()
+    }
+
+    /// Removes a channel's settings.
+    pub fn remove(&self, channel_id: ChannelId) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.channels.remove(&channel_id);
+        inner.save()
+    }
+}
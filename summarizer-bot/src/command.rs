@@ -0,0 +1,148 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use anyhow::{Error, Result};
+use indoc::formatdoc;
+use serenity::all::Mentionable;
+
+use crate::channels::{ChannelConfig, ChannelConfigStore};
+use crate::llm::SummaryGenerator;
+
+pub struct CommandData {
+    pub summary_generator: Arc<SummaryGenerator>,
+    pub channels: ChannelConfigStore,
+    /// Longer messages are not summarized at all
+    pub message_length_max: usize,
+}
+
+type Context<'a> = poise::Context<'a, CommandData, Error>;
+
+#[poise::command(slash_command, subcommands("status", "threads"))]
+pub async fn summarizer(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[poise::command(slash_command)]
+pub async fn status(ctx: Context<'_>) -> Result<()> {
+    // Checking the Ollama host can take longer than the interaction deadline
+    ctx.defer().await?;
+
+    let generator = &ctx.data().summary_generator;
+    let status = generator.refresh_status().await;
+
+    let availability = if status.available {
+        match status.size {
+            Some(size) => format!("available ({:.1} GB)", size as f64 / 1e9),
+            None => "available".to_string(),
+        }
+    } else {
+        "**missing**".to_string()
+    };
+
+    let residency = if status.is_resident() {
+        "loaded"
+    } else {
+        "not loaded"
+    };
+
+    let active_hours = generator
+        .active_hours()
+        .map(|h| format!("{}:00-{}:00", h.start, h.end))
+        .unwrap_or_else(|| "not configured".to_string());
+
+    let last_used = status
+        .last_used
+        .map(|t| format!("<t:{}:R>", t.timestamp()))
+        .unwrap_or_else(|| "never".to_string());
+
+    let last_load = status
+        .last_load_duration
+        .map(|d| format!("{:.1}s", d.as_secs_f64()))
+        .unwrap_or_else(|| "n/a".to_string());
+
+    let mut message = formatdoc! {"
+        Model: `{model}`
+        Availability: {availability}
+        Memory: {residency}
+        Active hours: {active_hours}
+        Last used: {last_used}
+        Last load time: {last_load}
+        ",
+        model = generator.model(),
+    };
+
+    if let Some(error) = status.last_error {
+        message.push_str(&format!("Last error: `{error}`\n"));
+    }
+
+    ctx.say(message).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    subcommands("threads_enable", "threads_disable")
+)]
+pub async fn threads(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Post summaries of long messages in a thread off the source message.
+#[poise::command(slash_command, guild_only, rename = "enable")]
+pub async fn threads_enable(
+    ctx: Context<'_>,
+    #[description = "Messages at least this long get a summary thread"]
+    #[min = 1]
+    min_length: usize,
+    #[description = "Post a follow-up summary every N messages in the thread"]
+    #[min = 1]
+    follow_up_every: Option<NonZeroU32>,
+) -> Result<()> {
+    // Only messages that get summarized can get a summary thread
+    let message_length_max = ctx.data().message_length_max;
+    if min_length > message_length_max {
+        ctx.say(format!(
+            "Minimum length must be at most **{message_length_max}**, longer messages are not summarized"
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let channel_config = ChannelConfig {
+        name: ctx.channel_id().name(&ctx.http()).await?,
+        thread_min_length: Some(min_length),
+        thread_follow_up_messages: follow_up_every,
+    };
+
+    ctx.data().channels.set(ctx.channel_id(), channel_config)?;
+
+    let follow_ups = match follow_up_every {
+        Some(n) => format!("every **{n}** messages"),
+        None => "off".to_string(),
+    };
+
+    ctx.say(formatdoc! {"
+        Enabled summary threads for {channel}
+        Minimum message length: **{min_length}**
+        Follow-up summaries: {follow_ups}
+        ",
+        channel = ctx.channel_id().mention(),
+    })
+    .await?;
+    Ok(())
+}
+
+/// Post summaries inline again.
+#[poise::command(slash_command, guild_only, rename = "disable")]
+pub async fn threads_disable(ctx: Context<'_>) -> Result<()> {
+    ctx.data().channels.remove(ctx.channel_id())?;
+
+    ctx.say(format!(
+        "Disabled summary threads for {channel}",
+        channel = ctx.channel_id().mention()
+    ))
+    .await?;
+    Ok(())
+}
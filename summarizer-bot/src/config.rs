@@ -1,13 +1,53 @@
-use std::env;
+use std::{env, str::FromStr};
 
 use anyhow::{Context, Result, anyhow};
 use shared::config::BotConfig;
 
+/// Hours of the day (local time) during which the model is kept loaded.
+/// `start` is inclusive and `end` is exclusive; ranges may wrap past midnight (e.g. `22-6`).
+#[derive(Debug, Clone, Copy)]
+pub struct ActiveHours {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl ActiveHours {
+    pub fn contains(&self, hour: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&hour)
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+impl FromStr for ActiveHours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("expected a range like 8-23"))?;
+        let start: u32 = start.trim().parse().context("invalid start hour")?;
+        let end: u32 = end.trim().parse().context("invalid end hour")?;
+
+        if start > 23 || end > 24 || start == end {
+            return Err(anyhow!("hours must be 0-23 (end may be 24) and not equal"));
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
 pub struct Config {
     pub bot: BotConfig,
     pub llm_model: String,
     pub llm_host: String,
     pub llm_port: u16,
+    /// Pull the model at startup if the Ollama host doesn't have it
+    pub llm_pull_missing: bool,
+    /// Keep the model loaded during these hours
+    pub llm_active_hours: Option<ActiveHours>,
     pub message_length_min: usize,
     pub message_length_max: usize,
 }
@@ -22,6 +62,17 @@ impl Config {
                 .context("Expected LLM_PORT in environment")?
                 .parse()
                 .context("LLM_PORT must be a valid port number")?,
+            llm_pull_missing: env::var("LLM_PULL_MISSING")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("LLM_PULL_MISSING must be true or false")?
+                .unwrap_or(false),
+            llm_active_hours: env::var("LLM_ACTIVE_HOURS")
+                .ok()
+                .map(|v| v.parse())
+                .transpose()
+                .context("LLM_ACTIVE_HOURS must be a range of hours like 8-23")?,
             message_length_min: env::var("MESSAGE_LENGTH_MIN")
                 .context("Expected MESSAGE_LENGTH_MIN in environment")?
                 .parse()
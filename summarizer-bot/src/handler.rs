@@ -1,14 +1,43 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
 use serenity::{
-    all::{EditMessage, EventHandler, Mentionable, Message, Ready},
+    all::{
+        AutoArchiveDuration, ChannelId, CreateThread, EditMessage, EventHandler, GetMessages,
+        Mentionable, Message, Ready,
+    },
     async_trait,
 };
-use tracing::{error, info};
+use tracing::{debug, error, info};
+
+use crate::{channels::ChannelConfigStore, config::Config, llm::SummaryGenerator};
 
-use crate::{config::Config, llm::SummaryGenerator};
+// Discord limits thread names to 100 characters
+const THREAD_NAME_MAX: usize = 100;
+const MAX_MESSAGES_PER_FETCH: u32 = 100;
+// Summary threads auto-archive after a day without messages, after which they're forgotten
+const THREAD_ARCHIVE_AFTER: AutoArchiveDuration = AutoArchiveDuration::OneDay;
+const THREAD_IDLE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A summary thread we created that gets follow-up summaries as it grows.
+#[derive(Debug)]
+struct TrackedThread {
+    follow_up_every: NonZeroU32,
+    messages_since_summary: u32,
+    last_message: Instant,
+}
 
 #[derive(Debug)]
 pub struct Handler {
-    summary_generator: SummaryGenerator,
+    summary_generator: Arc<SummaryGenerator>,
+    channels: ChannelConfigStore,
+    // Summary threads awaiting follow-ups. Kept in memory only, so threads stop getting
+    // follow-ups after a restart. Idle threads are dropped once they would have archived.
+    threads: Mutex<HashMap<ChannelId, TrackedThread>>,
     // Messages at least this long are summarized
     message_length_min: usize,
     // Messages longer than this are not summarized
@@ -23,6 +52,8 @@ impl EventHandler for Handler {
             return;
         }
 
+        self.track_thread_message(&ctx, &msg).await;
+
         let is_dm = msg.guild_id.is_none();
 
         if (msg.content.len() >= self.message_length_min
@@ -45,8 +76,13 @@ impl EventHandler for Handler {
                 )
             }
 
-            let mut response = match msg
-                .channel_id
+            let target_channel = if is_dm {
+                msg.channel_id
+            } else {
+                self.summary_channel(&ctx, &msg).await
+            };
+
+            let mut response = match target_channel
                 .say(
                     &ctx.http,
                     format!(
@@ -95,11 +131,138 @@ impl EventHandler for Handler {
 }
 
 impl Handler {
-    pub fn new(summary_generator: SummaryGenerator, config: &Config) -> Self {
+    pub fn new(
+        summary_generator: Arc<SummaryGenerator>,
+        channels: ChannelConfigStore,
+        config: &Config,
+    ) -> Self {
         Handler {
             summary_generator,
+            channels,
+            threads: Mutex::new(HashMap::new()),
             message_length_min: config.message_length_min,
             message_length_max: config.message_length_max,
         }
     }
+
+    /// Pick the channel a summary should be posted to. Long enough messages in channels with
+    /// thread mode get a new thread off the source message; everything else is answered inline.
+    async fn summary_channel(&self, ctx: &serenity::client::Context, msg: &Message) -> ChannelId {
+        let Some(channel_config) = self.channels.get(msg.channel_id) else {
+            return msg.channel_id;
+        };
+
+        let Some(thread_min_length) = channel_config.thread_min_length else {
+            return msg.channel_id;
+        };
+
+        if msg.content.len() < thread_min_length {
+            return msg.channel_id;
+        }
+
+        let thread_name: String = format!("Summary: {}'s message", msg.author.display_name())
+            .chars()
+            .take(THREAD_NAME_MAX)
+            .collect();
+
+        match msg
+            .channel_id
+            .create_thread_from_message(
+                &ctx.http,
+                msg.id,
+                CreateThread::new(thread_name).auto_archive_duration(THREAD_ARCHIVE_AFTER),
+            )
+            .await
+        {
+            Ok(thread) => {
+                debug!("Created summary thread {}", thread.id);
+
+                if let Some(follow_up_every) = channel_config.thread_follow_up_messages {
+                    let mut threads = self.threads.lock().unwrap();
+                    threads.retain(|_, t| t.last_message.elapsed() < THREAD_IDLE_TIMEOUT);
+                    threads.insert(
+                        thread.id,
+                        TrackedThread {
+                            follow_up_every,
+                            messages_since_summary: 0,
+                            last_message: Instant::now(),
+                        },
+                    );
+                }
+
+                thread.id
+            }
+            Err(why) => {
+                error!("Error creating summary thread, replying inline: {why:?}");
+                msg.channel_id
+            }
+        }
+    }
+
+    /// Count a message posted in a summary thread and post a follow-up summary once the thread
+    /// has grown by the configured number of messages.
+    async fn track_thread_message(&self, ctx: &serenity::client::Context, msg: &Message) {
+        let follow_up_every = {
+            let mut threads = self.threads.lock().unwrap();
+            let Some(thread) = threads.get_mut(&msg.channel_id) else {
+                return;
+            };
+
+            // The thread has archived since the last message
+            if thread.last_message.elapsed() >= THREAD_IDLE_TIMEOUT {
+                threads.remove(&msg.channel_id);
+                return;
+            }
+
+            thread.last_message = Instant::now();
+            thread.messages_since_summary += 1;
+            if thread.messages_since_summary < thread.follow_up_every.get() {
+                return;
+            }
+
+            thread.messages_since_summary = 0;
+            thread.follow_up_every.get()
+        };
+
+        info!("Posting follow-up summary in thread {}", msg.channel_id);
+
+        let messages = match msg
+            .channel_id
+            .messages(
+                &ctx.http,
+                GetMessages::new().limit(follow_up_every.min(MAX_MESSAGES_PER_FETCH) as u8),
+            )
+            .await
+        {
+            Ok(messages) => messages,
+            Err(why) => {
+                error!("Error fetching thread messages: {why:?}");
+                return;
+            }
+        };
+
+        // Messages come back newest first
+        let conversation: Vec<_> = messages
+            .iter()
+            .rev()
+            .filter(|m| !m.author.bot)
+            .map(|m| (m.author.display_name().to_string(), m.content.clone()))
+            .collect();
+
+        let summary = match self
+            .summary_generator
+            .generate_thread_summary(&conversation)
+            .await
+        {
+            Ok(summary) => summary,
+            Err(why) => {
+                error!("Error summarizing thread: {why:?}");
+                return;
+            }
+        };
+
+        if let Err(why) = msg.channel_id.say(&ctx.http, summary).await {
+            error!("Error sending follow-up summary: {why:?}");
+        }
+    }
 }
@@ -1,18 +1,58 @@
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::{Context, Result};
-use ollama_rs::{Ollama, generation::completion::request::GenerationRequest};
-use tokio::time::timeout;
-use tracing::instrument;
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, Timelike, Utc};
+use ollama_rs::{
+    Ollama,
+    generation::{
+        completion::{GenerationResponse, request::GenerationRequest},
+        parameters::{KeepAlive, TimeUnit},
+    },
+    models::LocalModel,
+};
+use tokio::{task::JoinHandle, time::timeout};
+use tracing::{debug, error, info, instrument, warn};
 
-use crate::config::Config;
+use crate::config::{ActiveHours, Config};
 
 const LLM_TIMEOUT: Duration = Duration::from_mins(10);
+// How often the model is re-touched during active hours. Must be shorter than ACTIVE_KEEP_ALIVE_MINUTES.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_mins(4);
+const ACTIVE_KEEP_ALIVE_MINUTES: u64 = 10;
+// Ollama unloads idle models after 5 minutes unless told otherwise
+const DEFAULT_KEEP_ALIVE_MINUTES: u64 = 5;
+
+/// Snapshot of what we know about the model on the Ollama host.
+#[derive(Debug, Clone, Default)]
+pub struct ModelStatus {
+    /// Whether the model was present on the Ollama host at the last check
+    pub available: bool,
+    /// Size of the model on disk in bytes
+    pub size: Option<u64>,
+    /// Last time a request touched the model
+    pub last_used: Option<DateTime<Utc>>,
+    /// Time the model is expected to stay loaded until, based on the keep_alive we sent
+    pub resident_until: Option<DateTime<Utc>>,
+    /// Time the last request spent loading the model (non-zero means it wasn't resident)
+    pub last_load_duration: Option<Duration>,
+    /// Error from the most recent request to Ollama
+    pub last_error: Option<String>,
+}
+
+impl ModelStatus {
+    pub fn is_resident(&self) -> bool {
+        self.resident_until.is_some_and(|until| until > Utc::now())
+    }
+}
 
 #[derive(Debug)]
 pub struct SummaryGenerator {
     ollama_client: Ollama,
     llm_model: String,
+    pull_missing: bool,
+    active_hours: Option<ActiveHours>,
+    status: Mutex<ModelStatus>,
 }
 
 impl SummaryGenerator {
@@ -20,25 +60,206 @@ impl SummaryGenerator {
         Self {
             llm_model: config.llm_model.clone(),
             ollama_client: Ollama::new(&config.llm_host, config.llm_port),
+            pull_missing: config.llm_pull_missing,
+            active_hours: config.llm_active_hours,
+            status: Mutex::new(ModelStatus::default()),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.llm_model
+    }
+
+    pub fn active_hours(&self) -> Option<ActiveHours> {
+        self.active_hours
+    }
+
+    /// Verify the model exists on the Ollama host, pulling it first if configured to.
+    /// A failure is recorded in the model status.
+    pub async fn ensure_model_available(&self) -> Result<()> {
+        let result = self.find_or_pull_model().await;
+        if let Err(e) = &result {
+            self.status.lock().unwrap().last_error = Some(format!("{e:#}"));
         }
+        result
+    }
+
+    async fn find_or_pull_model(&self) -> Result<()> {
+        if self.find_local_model().await?.is_some() {
+            info!("Model {} is available", self.llm_model);
+            return Ok(());
+        }
+
+        if !self.pull_missing {
+            return Err(anyhow!(
+                "Model {} not found on Ollama host (set LLM_PULL_MISSING=true to pull it)",
+                self.llm_model
+            ));
+        }
+
+        info!("Model {} not found, pulling...", self.llm_model);
+        let pull_status = self
+            .ollama_client
+            .pull_model(self.llm_model.clone(), false)
+            .await
+            .context("Failed to pull model")?;
+        info!("Pull finished: {}", pull_status.message);
+
+        self.find_local_model()
+            .await?
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("Model {} still missing after pull", self.llm_model))
+    }
+
+    /// Load the model into memory so the first summary doesn't pay the load time.
+    pub async fn warm_up(&self) -> Result<()> {
+        // An empty prompt makes Ollama load the model without generating anything
+        let response = self
+            .generate(GenerationRequest::new(self.llm_model.clone(), ""))
+            .await
+            .context("Model warm-up failed")?;
+
+        debug!(
+            "Warm-up took {:?} to load the model",
+            response.load_duration.map(Duration::from_nanos)
+        );
+
+        Ok(())
+    }
+
+    /// Re-check the model on the Ollama host and return the current status.
+    pub async fn refresh_status(&self) -> ModelStatus {
+        if let Err(e) = self.find_local_model().await {
+            warn!("Failed to refresh model status: {e:?}");
+            self.status.lock().unwrap().last_error = Some(format!("{e:#}"));
+        }
+
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Spawn a task that keeps the model loaded during active hours.
+    /// Returns `None` if no active hours are configured.
+    pub fn spawn_keep_alive(self: Arc<Self>) -> Option<JoinHandle<()>> {
+        let active_hours = self.active_hours?;
+
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+
+            info!(
+                "Keep-alive started (active hours: {}-{})",
+                active_hours.start, active_hours.end
+            );
+
+            loop {
+                interval.tick().await;
+
+                if !self.is_active_now() {
+                    continue;
+                }
+
+                if let Err(e) = self.warm_up().await {
+                    error!("Keep-alive request failed: {e:?}");
+                }
+            }
+        }))
     }
 
     #[instrument(level = "trace", skip_all)]
     pub async fn generate_summary(&self, author: &str, content: &str) -> Result<String> {
-        let result = timeout(
-            LLM_TIMEOUT,
-            self.ollama_client.generate(
+        let result = self
+            .generate(
                 GenerationRequest::new(
                     self.llm_model.clone(),
                     format!("Author: {author}\nMessage: {content}"),
                 )
                 .system(include_str!("../system_prompt.txt")),
-            ),
-        )
-        .await
-        .context("LLM request timed out")?
-        .context("LLM generation failed")?;
+            )
+            .await?;
+
+        Ok(result.response)
+    }
+
+    /// Summarize a conversation. `messages` are `(author, content)` pairs, oldest first.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn generate_thread_summary(&self, messages: &[(String, String)]) -> Result<String> {
+        let transcript = messages
+            .iter()
+            .map(|(author, content)| format!("Author: {author}\nMessage: {content}"))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let result = self
+            .generate(
+                GenerationRequest::new(self.llm_model.clone(), transcript)
+                    .system(include_str!("../thread_system_prompt.txt")),
+            )
+            .await?;
 
         Ok(result.response)
     }
+
+    /// Send a generation request, applying the keep_alive for the current time and recording
+    /// the outcome in the model status.
+    async fn generate(&self, request: GenerationRequest<'_>) -> Result<GenerationResponse> {
+        let (request, keep_alive_minutes) = if self.is_active_now() {
+            (
+                request.keep_alive(KeepAlive::Until {
+                    time: ACTIVE_KEEP_ALIVE_MINUTES,
+                    unit: TimeUnit::Minutes,
+                }),
+                ACTIVE_KEEP_ALIVE_MINUTES,
+            )
+        } else {
+            (request, DEFAULT_KEEP_ALIVE_MINUTES)
+        };
+
+        let result = timeout(LLM_TIMEOUT, self.ollama_client.generate(request))
+            .await
+            .context("LLM request timed out")
+            .and_then(|r| r.context("LLM generation failed"));
+
+        let mut status = self.status.lock().unwrap();
+        match &result {
+            Ok(response) => {
+                let now = Utc::now();
+                status.last_used = Some(now);
+                status.resident_until =
+                    Some(now + chrono::Duration::minutes(keep_alive_minutes as i64));
+                status.last_load_duration = response.load_duration.map(Duration::from_nanos);
+                status.last_error = None;
+            }
+            Err(e) => {
+                status.resident_until = None;
+                status.last_error = Some(format!("{e:#}"));
+            }
+        }
+
+        result
+    }
+
+    /// Look up the configured model in the Ollama host's local models.
+    async fn find_local_model(&self) -> Result<Option<LocalModel>> {
+        let models = self
+            .ollama_client
+            .list_local_models()
+            .await
+            .context("Failed to list models on Ollama host")?;
+
+        // Ollama reports untagged models with an explicit `:latest` tag
+        let model = models.into_iter().find(|m| {
+            m.name == self.llm_model
+                || (!self.llm_model.contains(':') && m.name == format!("{}:latest", self.llm_model))
+        });
+
+        let mut status = self.status.lock().unwrap();
+        status.available = model.is_some();
+        status.size = model.as_ref().map(|m| m.size);
+
+        Ok(model)
+    }
+
+    fn is_active_now(&self) -> bool {
+        self.active_hours
+            .is_some_and(|hours| hours.contains(Local::now().hour()))
+    }
 }
@@ -1,11 +1,18 @@
-use ::tracing::error;
+use std::sync::Arc;
+
+use ::tracing::{error, info, warn};
 use anyhow::{Context, Result};
+use poise::samples::register_in_guild;
 use serenity::prelude::*;
 
+use crate::channels::ChannelConfigStore;
+use crate::command::{CommandData, summarizer};
 use crate::config::Config;
 use crate::handler::Handler;
 use crate::llm::SummaryGenerator;
 
+mod channels;
+mod command;
 mod config;
 mod handler;
 mod llm;
@@ -19,10 +26,56 @@ async fn main() -> Result<()> {
         | GatewayIntents::MESSAGE_CONTENT
         | GatewayIntents::DIRECT_MESSAGES;
 
-    let summary_generator = SummaryGenerator::new(&config);
-    let handler = Handler::new(summary_generator, &config);
+    let summary_generator = Arc::new(SummaryGenerator::new(&config));
+    // Summaries fail until the model is there, but the bot still starts so `/summarizer status`
+    // can report the problem
+    match summary_generator.ensure_model_available().await {
+        Ok(()) => {
+            // Warm up in the background, loading a large model can take a while
+            tokio::spawn({
+                let summary_generator = Arc::clone(&summary_generator);
+                async move {
+                    info!("Warming up model {}...", summary_generator.model());
+                    if let Err(e) = summary_generator.warm_up().await {
+                        warn!("Model warm-up failed: {e:?}");
+                    }
+                }
+            });
+        }
+        Err(e) => warn!(
+            "Model {} is not available: {e:?}",
+            summary_generator.model()
+        ),
+    }
+
+    Arc::clone(&summary_generator).spawn_keep_alive();
+
+    let channels = ChannelConfigStore::load()?;
+    let handler = Handler::new(Arc::clone(&summary_generator), channels.clone(), &config);
+
+    let message_length_max = config.message_length_max;
+    let framework = poise::Framework::builder()
+        .options(poise::FrameworkOptions {
+            commands: vec![summarizer()],
+            ..Default::default()
+        })
+        .setup(move |ctx, ready, framework| {
+            Box::pin(async move {
+                for guild_id in &ready.guilds {
+                    register_in_guild(ctx, &framework.options().commands, guild_id.id).await?;
+                }
+
+                Ok(CommandData {
+                    summary_generator,
+                    channels,
+                    message_length_max,
+                })
+            })
+        })
+        .build();
 
     let mut client = Client::builder(&config.bot.discord_token, intents)
+        .framework(framework)
         .event_handler(handler)
         .await
         .context("Error creating client")?;